@@ -1,14 +1,13 @@
+use core::mem::size_of;
+
 use alloc::vec::Vec;
 use hba::{HBAMemory, HBAPort};
 use port::AHCIPort;
 
 use crate::log;
 use crate::{
-    arch::x86_64::acpi::{
-        acpi_mapping,
-        pci::{PciDevice, PciDeviceHeaderType0},
-    },
-    mem::PAGE_SIZE,
+    arch::x86_64::acpi::pci::{PciDevice, PciDeviceHeaderType0},
+    mem::{paging::entry::EntryFlags, GLOBAL_MEMORY_CONTROLLER},
 };
 
 mod fis;
@@ -98,9 +97,20 @@ pub fn init_ahci(controller: &PciDevice) -> Vec<AHCIPort> {
     let addr = controller.pci_base_addr;
     let header = unsafe { &*(addr as *const PciDeviceHeaderType0) };
 
-    // TODO: proper memory management
-    let hba_mem = unsafe { &*(header.bar5 as *const HBAMemory) };
-    acpi_mapping(header.bar5 as usize, PAGE_SIZE);
+    // the ABAR and the port registers inside it go into the direct map,
+    // with a huge page unless its 2 MiB are already partly mapped
+    let abar = {
+        let mut controller = GLOBAL_MEMORY_CONTROLLER.lock();
+        let controller = controller.as_mut().unwrap();
+
+        controller.map_physical(
+            header.bar5 as usize,
+            size_of::<HBAMemory>(),
+            EntryFlags::WRITABLE,
+        )
+    };
+
+    let hba_mem = unsafe { &*(abar as *const HBAMemory) };
 
     let ports = probe_ports(hba_mem);
     ports
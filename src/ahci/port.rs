@@ -3,9 +3,7 @@ use core::alloc::Layout;
 use alloc::alloc::{alloc, dealloc};
 
 use crate::log;
-use crate::mem::{
-    paging::entry::EntryFlags, PageFrame, PageFrameAllocator, GLOBAL_MEMORY_CONTROLLER, PAGE_SIZE,
-};
+use crate::mem::{phys_to_virt, GLOBAL_MEMORY_CONTROLLER, PAGE_SIZE};
 
 use super::{
     fis::{FisRegH2D, FisType},
@@ -54,21 +52,18 @@ struct PortStatus {
 }
 
 impl AHCIPort {
+    /// Creates a port, its registers are mapped along with the ABAR.
+    ///
+    /// ## Arguments
+    ///
+    /// - `port_address` the virtual address of the port registers
+    /// - `max_slots` the number of command slots of the controller
     pub fn new(port_address: usize, max_slots: u32) -> AHCIPort {
-        let port = AHCIPort {
+        AHCIPort {
             port_address: port_address,
             max_slots: max_slots,
             block_count: 0,
-        };
-
-        let frame = PageFrame::from_address(port_address);
-
-        let mut controller = GLOBAL_MEMORY_CONTROLLER.lock();
-        let controller = controller.as_mut().unwrap();
-
-        controller.identity_map(frame.clone(), frame, EntryFlags::WRITABLE);
-
-        port
+        }
     }
 
     pub fn init(&mut self) {
@@ -80,46 +75,27 @@ impl AHCIPort {
         let mut controller = GLOBAL_MEMORY_CONTROLLER.lock();
         let controller = controller.as_mut().unwrap();
 
-        let cl_base_frame = controller.frame_allocator.falloc().unwrap();
-        let cl_base_addr = cl_base_frame.start_address();
-
-        controller.identity_map(cl_base_frame.clone(), cl_base_frame, EntryFlags::WRITABLE);
-
-        unsafe {
-            core::ptr::write_bytes(cl_base_addr as *mut u8, 0, PAGE_SIZE);
-        }
+        // command list, received FIS and one command table per slot,
+        // all in one physically contiguous DMA block
+        let dma_frame = controller.alloc_dma(2 + 32).unwrap();
+        let cl_base_addr = dma_frame.start_address();
 
         port.clb = cl_base_addr as u32;
         port.clbu = (cl_base_addr >> 32) as u32; // this is weird
 
-        let fis_base_frame = controller.frame_allocator.falloc().unwrap();
-        let fis_base_addr = fis_base_frame.start_address();
-
-        controller.identity_map(fis_base_frame.clone(), fis_base_frame, EntryFlags::WRITABLE);
-
-        unsafe {
-            core::ptr::write_bytes(fis_base_addr as *mut u8, 0, PAGE_SIZE);
-        }
+        let fis_base_addr = cl_base_addr + PAGE_SIZE;
 
         port.fb = fis_base_addr as u32;
         port.fbu = (fis_base_addr >> 32) as u32;
 
         let cmd_header_addr = port.clb as usize + ((port.clbu as usize) << 32);
-        let cmd_header = cmd_header_addr as *mut HBACommandHeader;
+        let cmd_header = phys_to_virt(cmd_header_addr) as *mut HBACommandHeader;
 
         for i in 0..32 {
-            let cmd_table_base_frame = controller.frame_allocator.falloc().unwrap();
-            let cmd_table_base_addr = cmd_table_base_frame.start_address();
-
-            controller.identity_map(
-                cmd_table_base_frame.clone(),
-                cmd_table_base_frame,
-                EntryFlags::WRITABLE,
-            );
+            let cmd_table_base_addr = fis_base_addr + (i + 1) * PAGE_SIZE;
 
             unsafe {
                 let cmd = &mut *cmd_header.add(i);
-                core::ptr::write_bytes(cmd_table_base_addr as *mut u8, 0, PAGE_SIZE);
 
                 cmd.prdtl = HBA_PRDT_ENTRY_COUNT as u16;
 
@@ -270,15 +246,16 @@ impl AHCIPort {
         };
 
         let cmd_header_addr = port.clb as usize + ((port.clbu as usize) << 32);
-        let cmd_header =
-            unsafe { &mut *((cmd_header_addr as *mut HBACommandHeader).add(slot as usize)) };
+        let cmd_header = unsafe {
+            &mut *((phys_to_virt(cmd_header_addr) as *mut HBACommandHeader).add(slot as usize))
+        };
 
         let cfis_len = core::mem::size_of::<FisRegH2D>() / core::mem::size_of::<u32>();
         cmd_header.set_cfl(cfis_len as u8);
         cmd_header.set_write_bit(cmd.write);
 
         let cmd_table_addr = cmd_header.ctba as usize + ((cmd_header.ctbau as usize) << 32);
-        let cmd_table = unsafe { &mut *(cmd_table_addr as *mut HBACommandTable) };
+        let cmd_table = unsafe { &mut *(phys_to_virt(cmd_table_addr) as *mut HBACommandTable) };
         let cmd_table_size = core::mem::size_of::<HBACommandTable>();
 
         unsafe {
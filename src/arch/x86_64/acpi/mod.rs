@@ -4,7 +4,7 @@ use pci::{enumerate_pci, PciDevices};
 use rsdt::parse_rsdt;
 
 use crate::log;
-use crate::mem::{paging::entry::EntryFlags, PageFrame, GLOBAL_MEMORY_CONTROLLER, PAGE_SIZE};
+use crate::mem::{paging::entry::EntryFlags, VirtualAddress, GLOBAL_MEMORY_CONTROLLER, PAGE_SIZE};
 
 mod mcfg;
 pub mod pci;
@@ -34,9 +34,7 @@ pub fn init_acpi(boot_info: &BootInformation) -> PciDevices {
         loop {}
     }
 
-    let rsdt_address = rsdp.rsdt_address();
-    acpi_mapping(rsdt_address, PAGE_SIZE);
-
+    let rsdt_address = acpi_mapping(rsdp.rsdt_address(), PAGE_SIZE);
     let rsdt = parse_rsdt(rsdt_address);
 
     let mcfg = match rsdt.mcfg {
@@ -50,14 +48,20 @@ pub fn init_acpi(boot_info: &BootInformation) -> PciDevices {
     enumerate_pci(mcfg)
 }
 
-pub fn acpi_mapping(physical_address: usize, size: usize) {
-    let start_frame = PageFrame::from_address(physical_address);
-    let end_frame = PageFrame::from_address(physical_address + size);
-
+/// Maps physical memory holding ACPI tables or device registers.
+///
+/// ## Arguments
+///
+/// - `physical_address` the physical start address
+/// - `size` the size of the range in bytes
+///
+/// ## Returns
+/// The virtual address of `physical_address`.
+pub fn acpi_mapping(physical_address: usize, size: usize) -> VirtualAddress {
     let mut controller = GLOBAL_MEMORY_CONTROLLER.lock();
     let controller = controller.as_mut().unwrap();
 
-    controller.identity_map(start_frame, end_frame, EntryFlags::WRITABLE);
+    controller.map_physical(physical_address, size, EntryFlags::WRITABLE)
 }
 
 pub fn complies_table_checksum(slice: &[u8]) -> bool {
@@ -213,6 +213,7 @@ pub enum PciDeviceClass {
 }
 
 pub struct PciDevice {
+    /// Virtual address of the configuration space of the function.
     pub pci_base_addr: usize,
     pub vendor: u16,
     pub device_class: PciDeviceClass,
@@ -280,8 +281,7 @@ fn get_device_class(class: u8, subclass: u8) -> PciDeviceClass {
 
 fn enumerate_function(dev_addr: usize, function: usize, devices: &mut PciDevices) {
     let offset = (function as usize) << 12;
-    let func_addr = dev_addr + offset;
-    acpi_mapping(func_addr, PAGE_SIZE);
+    let func_addr = acpi_mapping(dev_addr + offset, PAGE_SIZE);
 
     let header = unsafe { &*(func_addr as *const PciDeviceHeader) };
     if header.device_id == 0xFFFF {
@@ -313,9 +313,9 @@ fn enumerate_function(dev_addr: usize, function: usize, devices: &mut PciDevices
 fn enumerate_device(bus_addr: usize, device: usize, devices: &mut PciDevices) {
     let offset = (device as usize) << 15;
     let dev_addr = bus_addr + offset;
-    acpi_mapping(dev_addr, PAGE_SIZE);
+    let header_addr = acpi_mapping(dev_addr, PAGE_SIZE);
 
-    let header = unsafe { &*(dev_addr as *const PciDeviceHeader) };
+    let header = unsafe { &*(header_addr as *const PciDeviceHeader) };
     if header.device_id == 0xFFFF {
         // device not present
         return;
@@ -329,9 +329,9 @@ fn enumerate_device(bus_addr: usize, device: usize, devices: &mut PciDevices) {
 fn enumerate_bus(base_addr: usize, bus: u8, devices: &mut PciDevices) {
     let offset = (bus as usize) << 20;
     let bus_addr = base_addr + offset;
-    acpi_mapping(bus_addr, PAGE_SIZE);
+    let header_addr = acpi_mapping(bus_addr, PAGE_SIZE);
 
-    let header = unsafe { &*(bus_addr as *const PciDeviceHeader) };
+    let header = unsafe { &*(header_addr as *const PciDeviceHeader) };
     if header.device_id == 0xFFFF {
         // bus not present
         return;
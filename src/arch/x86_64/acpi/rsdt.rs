@@ -1,6 +1,6 @@
 use crate::log;
 
-use super::{complies_table_checksum, AcpiSDTHeader};
+use super::{acpi_mapping, complies_table_checksum, AcpiSDTHeader};
 
 pub struct Rsdt {
     pub mcfg: Option<&'static AcpiSDTHeader>,
@@ -29,7 +29,9 @@ pub fn parse_rsdt(address: usize) -> Rsdt {
     for _ in 0..num_entries {
         // they're u32 pointers :D
         let ptr = unsafe { &*(curr_addr as *const u32) };
-        let header = unsafe { &*(*ptr as *const AcpiSDTHeader) };
+        let header_addr = acpi_mapping(*ptr as usize, size_of::<AcpiSDTHeader>());
+        let header = unsafe { &*(header_addr as *const AcpiSDTHeader) };
+        acpi_mapping(*ptr as usize, header.length as usize);
 
         let signature = core::str::from_utf8(&header.signature).unwrap();
         log!(
@@ -1,8 +1,14 @@
 ; multiboot kernel entry point
 global start
+global boot_stack_guard
+global stack_top
 extern long_mode_start
 
-section .text
+; the kernel is linked this far above its physical address, until paging
+; is on, everything outside of the boot code is reached physically
+KERNEL_OFFSET equ 0xFFFFFFFF80000000
+
+section .boot.text
 bits 32                             ; protected mode
 
 ; prints err and halts
@@ -79,23 +85,20 @@ no_long_mode:
     jmp error
 
 set_up_page_tables:
-    ; set up recursive page mapping
-    ; point the last entru in the p4
-    ; table to itself effectively
-    ; using the p4 as a p3
-    mov eax, p4_table
-    or eax, 0b11 ; present and writable
-    mov [p4_table + 511 * 8], eax
-
-    ; map first P4 entry to P3 table
-    mov eax, p3_table
-    or eax, 0b11 ; present +
-    mov [p4_table], eax
-
-    ; map first P3 entry to P2 table
-    mov eax, p2_table
+    ; the first GiB is mapped twice, identity mapped for the
+    ; boot code and at KERNEL_OFFSET for the kernel itself
+
+    ; map the first and the last P4 entry to the P3 table
+    mov eax, p3_table - KERNEL_OFFSET
+    or eax, 0b11 ; present + writable
+    mov [p4_table - KERNEL_OFFSET], eax
+    mov [p4_table - KERNEL_OFFSET + 511 * 8], eax
+
+    ; map the first and the second to last P3 entry to the P2 table
+    mov eax, p2_table - KERNEL_OFFSET
     or eax, 0b11 ; present + writable
-    mov [p3_table], eax
+    mov [p3_table - KERNEL_OFFSET], eax
+    mov [p3_table - KERNEL_OFFSET + 510 * 8], eax
 
     ; map each P2 entry to a huge 2MiB page
     mov ecx, 0 ; counter variable
@@ -106,7 +109,7 @@ map_p2_table:
     mov eax, 0x200000  ; 2MiB
     mul ecx            ; start address of ecx-th page
     or eax, 0b10000011 ; present + writable + huge
-    mov [p2_table + ecx * 8], eax ; map ecx-th entry
+    mov [p2_table - KERNEL_OFFSET + ecx * 8], eax ; map ecx-th entry
 
     inc ecx            ; increase counter
     cmp ecx, 512       ; if counter == 512, the whole P2 table is mapped
@@ -116,7 +119,7 @@ map_p2_table:
 
 enable_paging:
     ; load P4 to cr3 register (cpu uses this to access the P4 table)
-    mov eax, p4_table
+    mov eax, p4_table - KERNEL_OFFSET
     mov cr3, eax
 
     ; enable PAE-flag in cr4 (Physical Address Extension)
@@ -138,7 +141,7 @@ enable_paging:
     ret
 
 start:
-    mov esp, stack_top
+    mov esp, stack_top - KERNEL_OFFSET
     mov edi, ebx            ; move the multiboot header into ebx to be passed on as an arg
 
     call multiboot_check
@@ -149,7 +152,7 @@ start:
     call enable_paging
 
     ; load the 64-bit GDT
-    lgdt [gdt64.pointer]
+    lgdt [gdt64.pointer - KERNEL_OFFSET]
 
     jmp gdt64.code:long_mode_start
 
@@ -164,6 +167,11 @@ p3_table:
 p2_table:
     resb 4096
 
+; guard page below the stack, unmapped once
+; the kernel switches to its own page table
+boot_stack_guard:
+    resb 4096
+
 ; stack
 stack_bottom:
     resb 4096 * 4
@@ -178,4 +186,4 @@ gdt64:
     dq (1<<43) | (1<<44) | (1<<47) | (1<<53) ; code segment
 .pointer:
     dw $ - gdt64 - 1
-    dq gdt64
+    dq gdt64 - KERNEL_OFFSET
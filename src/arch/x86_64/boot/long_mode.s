@@ -2,7 +2,9 @@ global long_mode_start
 
 extern kernel_start
 
-section .text
+KERNEL_OFFSET equ 0xFFFFFFFF80000000
+
+section .boot.text
 bits 64
 
 long_mode_start:
@@ -14,6 +16,11 @@ long_mode_start:
     mov fs, ax
     mov gs, ax
 
+    ; move the stack to the kernel mapping, the
+    ; boot code is left behind with the jump
+    mov rax, KERNEL_OFFSET
+    add rsp, rax
+
     ; call the rust main
     ; extern rust_main
     ; call rust_main
@@ -22,4 +29,5 @@ long_mode_start:
     ; halt
     ; hlt
 
-    jmp kernel_start
\ No newline at end of file
+    mov rax, kernel_start
+    jmp rax
\ No newline at end of file
@@ -1,3 +1,4 @@
+use spin::Once;
 use x86_64::{
     instructions::tables::load_tss,
     registers::segmentation::{Segment, CS, DS},
@@ -9,7 +10,7 @@ use x86_64::{
 };
 
 use crate::log;
-use crate::mem::GLOBAL_MEMORY_CONTROLLER;
+use crate::mem::{register_kernel_stack, GLOBAL_MEMORY_CONTROLLER};
 
 pub struct Selectors {
     tss: SegmentSelector,
@@ -23,34 +24,45 @@ pub static PIT_STACK_INDEX: usize = 0;
 pub static SYSCALL_STACK_INDEX: usize = 1;
 pub static DOUBLE_FAULT_STACK_INDEX: usize = 2;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
+/// Number of pages of each stack the CPU switches to on an interrupt.
+pub const ENTRY_STACK_PAGES: usize = 16;
+
+// the CPU reads the GDT and TSS on every interrupt and interrupt return,
+// so they sit with the entry code that stays mapped in user page tables
+#[link_section = ".data.entry"]
+static TSS: Once<TaskStateSegment> = Once::new();
+
+#[link_section = ".data.entry"]
+static GDT: Once<(GlobalDescriptorTable, Selectors)> = Once::new();
+
+fn tss() -> &'static TaskStateSegment {
+    TSS.call_once(|| {
         let mut tss = TaskStateSegment::new();
 
-        let pit_stack = alloc_ist_stack();
+        let pit_stack = alloc_ist_stack("timer IST");
         tss.interrupt_stack_table[PIT_STACK_INDEX] = VirtAddr::new(pit_stack);
 
-        let syscall_stack = alloc_ist_stack();
+        let syscall_stack = alloc_ist_stack("syscall IST");
         tss.interrupt_stack_table[SYSCALL_STACK_INDEX] = VirtAddr::new(syscall_stack);
 
-        let double_fault_stack = alloc_ist_stack();
+        let double_fault_stack = alloc_ist_stack("double fault IST");
         tss.interrupt_stack_table[DOUBLE_FAULT_STACK_INDEX] = VirtAddr::new(double_fault_stack);
 
         // the stack the CPU switches to when an exception without a
         // dedicated IST stack arrives from ring 3; without it the CPU
         // would push the exception frame to address 0 and triple fault
-        let ring0_stack = alloc_ist_stack();
+        let ring0_stack = alloc_ist_stack("ring 0");
         tss.privilege_stack_table[0] = VirtAddr::new(ring0_stack);
 
         tss
-    };
+    })
 }
 
-lazy_static! {
-    pub static ref GDT: (GlobalDescriptorTable, Selectors) = {
+fn gdt() -> &'static (GlobalDescriptorTable, Selectors) {
+    GDT.call_once(|| {
         let mut gdt = GlobalDescriptorTable::new();
 
-        let tss = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let tss = gdt.add_entry(Descriptor::tss_segment(tss()));
         let code = gdt.add_entry(Descriptor::kernel_code_segment());
         let data = gdt.add_entry(Descriptor::kernel_data_segment());
 
@@ -66,15 +78,42 @@ lazy_static! {
         };
 
         (gdt, selectors)
-    };
+    })
 }
 
-fn alloc_ist_stack() -> u64 {
+/// The segment selectors of the GDT.
+pub fn selectors() -> &'static Selectors {
+    &gdt().1
+}
+
+/// The tops of the stacks the CPU switches to on an interrupt, each one
+/// `ENTRY_STACK_PAGES` pages large.
+pub fn entry_stack_tops() -> [u64; 4] {
+    let tss = tss();
+
+    [
+        tss.interrupt_stack_table[PIT_STACK_INDEX].as_u64(),
+        tss.interrupt_stack_table[SYSCALL_STACK_INDEX].as_u64(),
+        tss.interrupt_stack_table[DOUBLE_FAULT_STACK_INDEX].as_u64(),
+        tss.privilege_stack_table[0].as_u64(),
+    ]
+}
+
+/// Allocates a kernel stack for the TSS, right above an unmapped guard
+/// page, and registers it so overflows are reported by name.
+///
+/// ## Arguments
+///
+/// - `name` the name used when reporting an overflow
+fn alloc_ist_stack(name: &'static str) -> u64 {
     let mut mc = GLOBAL_MEMORY_CONTROLLER.lock();
     let mc = mc.as_mut().unwrap();
 
-    match mc.alloc_stack(16, false) {
-        Some(s) => s.top as u64,
+    match mc.alloc_stack(ENTRY_STACK_PAGES, false) {
+        Some(s) => {
+            register_kernel_stack(name, &s);
+            s.top as u64
+        }
         None => {
             log!(crate::io::LogType::ERR, "Couldn't allocate IST stack!");
             panic!();
@@ -83,11 +122,12 @@ fn alloc_ist_stack() -> u64 {
 }
 
 pub fn init_gdt() {
-    GDT.0.load();
+    let (gdt, selectors) = gdt();
+    gdt.load();
 
     unsafe {
-        CS::set_reg(GDT.1.code);
-        DS::set_reg(GDT.1.data);
-        load_tss(GDT.1.tss);
+        CS::set_reg(selectors.code);
+        DS::set_reg(selectors.data);
+        load_tss(selectors.tss);
     };
 }
@@ -11,16 +11,21 @@ use crate::{
     arch::x86_64::{
         gdt::{DOUBLE_FAULT_STACK_INDEX, PIT_STACK_INDEX, SYSCALL_STACK_INDEX},
         timer_isr::timer_trampoline,
+        user_access,
     },
     interrupt_trampoline,
     io::io,
-    print, syscall,
+    mem, print,
+    scheduling::{self, vma::FaultError},
+    syscall,
 };
 
 use super::registers::FullInterruptStackFrame;
 
 pub const IRQ0: usize = 0x20;
 
+// read by the CPU on every interrupt, so it sits with the entry code
+#[link_section = ".data.entry"]
 static mut IDT: InterruptDescriptorTable = InterruptDescriptorTable::new();
 
 extern "x86-interrupt" fn breakpoint_isr(_stack: InterruptStackFrame) {
@@ -32,18 +37,38 @@ extern "x86-interrupt" fn breakpoint_isr(_stack: InterruptStackFrame) {
     loop {}
 }
 
-extern "x86-interrupt" fn double_fault_isr(stack: InterruptStackFrame, err_code: u64) -> ! {
+#[naked]
+#[link_section = ".text.entry"]
+extern "x86-interrupt" fn double_fault_trampoline() {
+    interrupt_trampoline!(double_fault_isr, error_code);
+}
+
+extern "C" fn double_fault_isr(stack: *mut FullInterruptStackFrame, err_code: u64) -> ! {
+    let stack = unsafe { &*stack };
+
     log!(
         crate::io::LogType::EXCEPTION,
         "Double fault, err_code: 0x{:x}",
         err_code
     );
 
+    // overflowing a kernel stack faults on its guard page, and delivering
+    // that page fault on the same stack faults again
+    report_stack_overflow(Cr2::read().as_u64());
+
     log!(crate::io::LogType::ERR, "Dumping stack frame\n{:#?}", stack);
     loop {}
 }
 
-extern "x86-interrupt" fn gpf_isr(stack: InterruptStackFrame, err_code: u64) {
+#[naked]
+#[link_section = ".text.entry"]
+extern "x86-interrupt" fn gpf_trampoline() {
+    interrupt_trampoline!(gpf_isr, error_code);
+}
+
+extern "C" fn gpf_isr(stack: *mut FullInterruptStackFrame, err_code: u64) {
+    let stack = unsafe { &*stack };
+
     log!(
         crate::io::LogType::EXCEPTION,
         "General protection fault! With error code: 0x{:X}",
@@ -54,30 +79,104 @@ extern "x86-interrupt" fn gpf_isr(stack: InterruptStackFrame, err_code: u64) {
     loop {}
 }
 
-extern "x86-interrupt" fn page_fault_isr(stack: InterruptStackFrame, err_code: PageFaultErrorCode) {
+#[naked]
+#[link_section = ".text.entry"]
+extern "x86-interrupt" fn page_fault_trampoline() {
+    interrupt_trampoline!(page_fault_isr, error_code);
+}
+
+extern "C" fn page_fault_isr(stack: *mut FullInterruptStackFrame, err_code: u64) {
+    let stack = unsafe { &mut *stack };
+    let err_code = PageFaultErrorCode::from_bits_truncate(err_code);
     let cr2 = Cr2::read().as_u64();
+
+    if err_code.contains(PageFaultErrorCode::USER_MODE) {
+        // user pages that are reserved but not touched yet get mapped
+        // here, any other fault ends the process
+        let reason = if err_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            "protection violation"
+        } else {
+            let write = err_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE);
+            match scheduling::handle_current_page_fault(cr2 as usize, write) {
+                Ok(()) => return,
+                Err(FaultError::StackOverflow) => "stack overflow",
+                Err(FaultError::ReadOnly) => "write to read-only memory",
+                Err(FaultError::OutOfMemory) => "out of memory",
+                Err(FaultError::Unmapped) => "unmapped address",
+                Err(FaultError::ReadFailed) => "failed to read page from disk",
+            }
+        };
+
+        log!(
+            crate::io::LogType::EXCEPTION,
+            "Segmentation fault at 0x{:X}, rip: 0x{:X} ({}), killing process",
+            cr2,
+            stack.rip,
+            reason
+        );
+
+        scheduling::exit_current();
+        scheduling::schedule(None);
+        return;
+    }
+
+    if let Some(fixup) = user_access::find_fixup(stack.rip) {
+        // a user copy touched a page of the current process, which is
+        // mapped like for a fault from ring 3, or the copy fails
+        let write = err_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE);
+        let resolved = !err_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+            && scheduling::handle_current_page_fault(cr2 as usize, write).is_ok();
+
+        if !resolved {
+            stack.rip = fixup;
+        }
+
+        return;
+    }
+
     log!(
         crate::io::LogType::EXCEPTION,
         "Page fault! With error code: 0x{:X}, and cr2: 0x{:X}",
         err_code,
         cr2
     );
+    report_stack_overflow(cr2);
 
     log!(crate::io::LogType::ERR, "Dumping stack frame\n{:#?}", stack);
     loop {}
 }
 
+/// Logs which kernel stack overflowed when an address lies in the guard
+/// page of one.
+///
+/// ## Arguments
+///
+/// - `addr` the faulting address from CR2
+fn report_stack_overflow(addr: u64) {
+    if let Some((name, guard)) = mem::find_guard_page_hit(addr as usize) {
+        log!(
+            crate::io::LogType::ERR,
+            "Kernel stack overflow: hit the guard page of the {} stack at 0x{:X}",
+            name,
+            guard
+        );
+    }
+}
+
 extern "x86-interrupt" fn debug_isr(_stack: InterruptStackFrame) {
     log!(crate::io::LogType::OK, "Debug isr called!");
 }
 
 #[naked]
+#[link_section = ".text.entry"]
 extern "x86-interrupt" fn syscall_trampoline() {
     interrupt_trampoline!(syscall_isr);
 }
 
 #[no_mangle]
 extern "C" fn syscall_isr(stack: *mut FullInterruptStackFrame) {
+    user_access::close_user_access();
+
     let stack = unsafe { &mut *stack };
     let syscall_number = stack.rax;
 
@@ -99,6 +198,23 @@ extern "C" fn syscall_isr(stack: *mut FullInterruptStackFrame) {
         15 => syscall::rmdir(stack),
         16 => syscall::clock_gettime(stack),
         17 => syscall::nanosleep(stack),
+        18 => syscall::stat(stack),
+        19 => syscall::fstat(stack),
+        20 => syscall::rename(stack),
+        21 => syscall::opendir(stack),
+        22 => syscall::getdents(stack),
+        23 => syscall::getcwd(stack),
+        24 => syscall::set_env(stack),
+        25 => syscall::read_env(stack),
+        26 => syscall::brk(stack),
+        27 => syscall::mmap(stack),
+        28 => syscall::munmap(stack),
+        29 => syscall::msync(stack),
+        30 => syscall::meminfo(stack),
+        31 => syscall::shm_open(stack),
+        32 => syscall::shm_unlink(stack),
+        33 => syscall::mount(stack),
+        34 => syscall::umount(stack),
         _ => {
             log!(
                 crate::io::LogType::SYS,
@@ -159,10 +275,12 @@ pub unsafe fn register_interrupt(vector: usize, handler_addr: usize, is_ring3: b
 pub unsafe fn init_idt() {
     IDT.breakpoint.set_handler_fn(breakpoint_isr);
     IDT.double_fault
-        .set_handler_fn(double_fault_isr)
+        .set_handler_addr(VirtAddr::new(double_fault_trampoline as u64))
         .set_stack_index(DOUBLE_FAULT_STACK_INDEX as u16);
-    IDT.general_protection_fault.set_handler_fn(gpf_isr);
-    IDT.page_fault.set_handler_fn(page_fault_isr);
+    IDT.general_protection_fault
+        .set_handler_addr(VirtAddr::new(gpf_trampoline as u64));
+    IDT.page_fault
+        .set_handler_addr(VirtAddr::new(page_fault_trampoline as u64));
 
     IDT[IRQ0 as usize]
         .set_handler_addr(VirtAddr::new(timer_trampoline as u64))
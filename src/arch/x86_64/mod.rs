@@ -2,7 +2,9 @@ pub mod acpi;
 pub mod gdt;
 pub mod idt;
 pub mod pit;
+pub mod random;
 pub mod registers;
 pub mod rtc;
 pub mod timer_isr;
 pub mod trampoline;
+pub mod user_access;
@@ -0,0 +1,73 @@
+use core::arch::x86_64::{__cpuid, _rdrand64_step, _rdtsc};
+
+use spin::Mutex;
+
+/// CPUID leaf 1 ECX bit telling whether the RDRAND instruction exists.
+const CPUID_RDRAND: u32 = 1 << 30;
+
+/// RDRAND may fail while its entropy source refills, it's retried this
+/// many times before giving up.
+const RDRAND_RETRIES: usize = 10;
+
+/// State of the xorshift generator, never 0 once seeded.
+static STATE: Mutex<u64> = Mutex::new(0);
+
+/// Seeds the generator from RDRAND when the CPU has it, and from the
+/// time stamp counter otherwise.
+pub fn init() {
+    let mut seed = unsafe { _rdtsc() };
+    if let Some(value) = rdrand() {
+        seed ^= value;
+    }
+
+    // xorshift gets stuck at 0
+    *STATE.lock() = seed.max(1);
+}
+
+/// Whether the CPU supports the RDRAND instruction.
+pub fn has_rdrand() -> bool {
+    let leaf = unsafe { __cpuid(1) };
+    leaf.ecx & CPUID_RDRAND != 0
+}
+
+/// Next pseudo random number. Not suitable for cryptography, it only has
+/// to make addresses hard to guess.
+pub fn next_u64() -> u64 {
+    let mut state = STATE.lock();
+    if *state == 0 {
+        drop(state);
+        init();
+        state = STATE.lock();
+    }
+
+    // xorshift64*
+    let mut x = *state;
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    *state = x;
+
+    x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+}
+
+/// Reads a hardware random number.
+///
+/// ## Returns
+/// The random number, or `None` when the CPU lacks RDRAND or it kept
+/// failing.
+fn rdrand() -> Option<u64> {
+    if !has_rdrand() {
+        return None;
+    }
+
+    (0..RDRAND_RETRIES).find_map(|_| {
+        let mut value = 0;
+        let ok = unsafe { rdrand_step(&mut value) };
+        (ok == 1).then_some(value)
+    })
+}
+
+#[target_feature(enable = "rdrand")]
+unsafe fn rdrand_step(value: &mut u64) -> i32 {
+    _rdrand64_step(value)
+}
@@ -59,7 +59,7 @@ fn bcd_to_binary(value: u8) -> u8 {
 /// Days since the Unix epoch for a Gregorian calendar date.
 ///
 /// Uses Howard Hinnant's `days_from_civil` algorithm.
-fn days_from_civil(year: i64, month: u64, day: u64) -> i64 {
+pub fn days_from_civil(year: i64, month: u64, day: u64) -> i64 {
     let adjusted_year = if month <= 2 { year - 1 } else { year };
     let era = adjusted_year.div_euclid(400);
     let year_of_era = (adjusted_year - era * 400) as u64;
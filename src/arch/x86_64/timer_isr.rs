@@ -12,6 +12,7 @@ use crate::{
 use super::registers::FullInterruptStackFrame;
 
 #[naked]
+#[link_section = ".text.entry"]
 pub extern "x86-interrupt" fn timer_trampoline() {
     interrupt_trampoline!(timer_isr);
 }
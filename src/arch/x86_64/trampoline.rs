@@ -1,11 +1,30 @@
+use crate::mem::kpti;
+
+/// Saves the registers of the interrupted code into a
+/// `FullInterruptStackFrame` and calls an `extern "C"` handler with a
+/// pointer to it, then returns through [`interrupt_return`].
+///
+/// With `error_code` the handler is meant for an exception that pushes an
+/// error code, it is passed to the handler as the second argument.
+///
+/// Functions using it must be placed in the `.text.entry` section, they
+/// run before the kernel page table is switched to.
 #[macro_export]
 macro_rules! interrupt_trampoline {
     ($isr:path) => {
+        $crate::interrupt_trampoline!(@entry $isr, "push rax", "")
+    };
+    ($isr:path, error_code) => {
+        // the error code sits right below the interrupt frame, where
+        // rax is saved, swapping them leaves the error code in rax
+        $crate::interrupt_trampoline!(@entry $isr, "xchg rax, [rsp]", "mov rsi, rax")
+    };
+    (@entry $isr:path, $save_rax:literal, $error_code:literal) => {
         unsafe {
             core::arch::naked_asm!(
                 "cli",
 
-                "push rax",
+                $save_rax,
                 "push rbx",
                 "push rcx",
                 "push rdx",
@@ -20,31 +39,75 @@ macro_rules! interrupt_trampoline {
                 "push r10",
                 "push r9",
                 "push r8",
+                $error_code,
+
+                // coming from ring 3 with page table isolation, only the
+                // entry code is mapped until the kernel table is active
+                "test qword ptr [rsp + 0x80], 3",
+                "jz 2f",
+                "mov rax, qword ptr [rip + {kernel_cr3}]",
+                "test rax, rax",
+                "jz 2f",
+                "mov cr3, rax",
+                "2:",
 
                 "mov rdi, rsp",
                 "call {isr}",
-
-                "sti",
-
-                "pop r8",
-                "pop r9",
-                "pop r10",
-                "pop r11",
-                "pop r12",
-                "pop r13",
-                "pop r14",
-                "pop r15",
-                "pop rbp",
-                "pop rdi",
-                "pop rsi",
-                "pop rdx",
-                "pop rcx",
-                "pop rbx",
-                "pop rax",
-                "iretq",
+                "jmp {interrupt_return}",
 
                 isr = sym $isr,
+                kernel_cr3 = sym $crate::mem::kpti::KERNEL_CR3,
+                interrupt_return = sym $crate::arch::x86_64::trampoline::interrupt_return,
             );
         }
     };
 }
+
+/// Restores the registers saved in a `FullInterruptStackFrame` on top of
+/// the stack and returns from the interrupt.
+///
+/// When returning to ring 3 with page table isolation, the user page table
+/// of the process is synced and switched to first.
+#[naked]
+#[link_section = ".text.entry"]
+pub unsafe extern "C" fn interrupt_return() -> ! {
+    core::arch::naked_asm!(
+        "test qword ptr [rsp + 0x80], 3",
+        "jz 2f",
+        "cmp qword ptr [rip + {user_cr3}], 0",
+        "je 2f",
+
+        // the stack is 16 byte aligned here, the saved registers
+        // are restored below anyway
+        "call {sync_user_table}",
+        "mov rax, qword ptr [rip + {user_cr3}]",
+        "mov cr3, rax",
+
+        // later returns keep the TLB entries of the user table,
+        // until they are invalidated again
+        "mov rax, qword ptr [rip + {noflush}]",
+        "or qword ptr [rip + {user_cr3}], rax",
+        "2:",
+
+        "pop r8",
+        "pop r9",
+        "pop r10",
+        "pop r11",
+        "pop r12",
+        "pop r13",
+        "pop r14",
+        "pop r15",
+        "pop rbp",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rbx",
+        "pop rax",
+        "iretq",
+
+        user_cr3 = sym kpti::USER_CR3,
+        noflush = sym kpti::CR3_NOFLUSH,
+        sync_user_table = sym kpti::sync_user_table,
+    );
+}
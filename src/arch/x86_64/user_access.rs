@@ -0,0 +1,158 @@
+use core::{
+    arch::{asm, global_asm, x86_64::__cpuid_count},
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// CPUID leaf 7 EBX bit telling whether SMEP exists.
+const CPUID_SMEP: u32 = 1 << 7;
+
+/// CPUID leaf 7 EBX bit telling whether SMAP exists.
+const CPUID_SMAP: u32 = 1 << 20;
+
+// copies rdx bytes from rsi to rdi. A page fault inside the copy
+// continues at user_copy_end through the exception table, so the
+// return value is the number of bytes left uncopied.
+global_asm!(
+    r#"
+    .section .text
+    .global user_copy
+user_copy:
+    mov rcx, rdx
+user_copy_start:
+    rep movsb
+user_copy_end:
+    mov rax, rcx
+    ret
+"#
+);
+
+extern "C" {
+    fn user_copy(dst: *mut u8, src: *const u8, count: usize) -> usize;
+
+    static user_copy_start: u8;
+    static user_copy_end: u8;
+}
+
+/// Instructions that may fault on user memory, and the address execution
+/// continues at when they do.
+struct ExceptionTableEntry {
+    start: usize,
+    end: usize,
+    fixup: usize,
+}
+
+fn exception_table() -> [ExceptionTableEntry; 1] {
+    [ExceptionTableEntry {
+        start: ptr::addr_of!(user_copy_start) as usize,
+        end: ptr::addr_of!(user_copy_end) as usize,
+        fixup: ptr::addr_of!(user_copy_end) as usize,
+    }]
+}
+
+/// Set once SMAP is on, `stac` and `clac` don't exist on CPUs without it.
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the CPU supports SMEP and SMAP.
+///
+/// ## Returns
+/// A tuple of the SMEP and SMAP support.
+pub fn supported() -> (bool, bool) {
+    let leaf = unsafe { __cpuid_count(7, 0) };
+    (leaf.ebx & CPUID_SMEP != 0, leaf.ebx & CPUID_SMAP != 0)
+}
+
+/// Makes the copy helpers open and close user access windows, must be
+/// called right after SMAP was turned on in CR4.
+pub fn set_smap_enabled() {
+    SMAP_ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// An open window in which the kernel may touch user pages, it's closed
+/// again when dropped.
+struct UserAccessWindow;
+
+impl UserAccessWindow {
+    fn open() -> Self {
+        if SMAP_ENABLED.load(Ordering::Relaxed) {
+            unsafe { asm!("stac", options(nostack)) };
+        }
+
+        UserAccessWindow
+    }
+}
+
+impl Drop for UserAccessWindow {
+    fn drop(&mut self) {
+        close_user_access();
+    }
+}
+
+/// Closes any open user access window.
+///
+/// Ring 3 can set the AC flag itself and interrupt gates keep it, so
+/// kernel entry points taking user pointers call this first.
+pub fn close_user_access() {
+    if SMAP_ENABLED.load(Ordering::Relaxed) {
+        unsafe { asm!("clac", options(nostack)) };
+    }
+}
+
+/// Copies bytes out of user memory. Pages that aren't mapped yet are
+/// faulted in, a fault that can't be resolved ends the copy early.
+///
+/// ## Safety
+///
+/// The range must belong to the memory areas of the current process, it
+/// is not checked against kernel memory.
+///
+/// ## Arguments
+///
+/// - `src` the user address to copy from
+/// - `buffer` the kernel buffer to fill, its length is the copy size
+///
+/// ## Returns
+/// `None` when a page of the range couldn't be read.
+pub unsafe fn copy_from_user(src: usize, buffer: &mut [u8]) -> Option<()> {
+    let _window = UserAccessWindow::open();
+    let left = user_copy(buffer.as_mut_ptr(), src as *const u8, buffer.len());
+    (left == 0).then_some(())
+}
+
+/// Copies bytes into user memory. Pages that aren't mapped yet are
+/// faulted in, a fault that can't be resolved ends the copy early.
+///
+/// ## Safety
+///
+/// The range must belong to the memory areas of the current process, it
+/// is not checked against kernel memory.
+///
+/// ## Arguments
+///
+/// - `dst` the user address to copy to
+/// - `bytes` the bytes to copy
+///
+/// ## Returns
+/// `None` when a page of the range couldn't be written.
+pub unsafe fn copy_to_user(dst: usize, bytes: &[u8]) -> Option<()> {
+    let _window = UserAccessWindow::open();
+    let left = user_copy(dst as *mut u8, bytes.as_ptr(), bytes.len());
+    (left == 0).then_some(())
+}
+
+/// Finds where execution continues after a page fault in kernel code
+/// that touches user memory.
+///
+/// ## Arguments
+///
+/// - `rip` the address of the faulting instruction
+///
+/// ## Returns
+/// The fixup address, or `None` when the instruction isn't allowed to
+/// fault.
+pub fn find_fixup(rip: usize) -> Option<usize> {
+    exception_table()
+        .into_iter()
+        .find(|entry| (entry.start..entry.end).contains(&rip))
+        .map(|entry| entry.fixup)
+}
@@ -1,10 +1,42 @@
+use core::mem::size_of;
+
 use alloc::sync::Arc;
 use spin::Mutex;
 
-use crate::{io::LogType, log, mem::Region, scheduling::process::ProcessEntry};
+use crate::{
+    io::LogType,
+    log,
+    mem::{aslr, Region},
+    scheduling::{process::ProcessEntry, vma::VmaTree},
+};
 
 use super::{ElfProgramHeaderFlags, ElfRegion};
 
+/// ELF type of an executable linked to a fixed address.
+const ET_EXEC: u16 = 2;
+
+/// ELF type of a position independent executable.
+const ET_DYN: u16 = 3;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+
+const DT_NULL: i64 = 0;
+const DT_RELA: i64 = 7;
+const DT_RELASZ: i64 = 8;
+const DT_RELAENT: i64 = 9;
+const DT_REL: i64 = 17;
+
+const R_X86_64_NONE: u32 = 0;
+const R_X86_64_RELATIVE: u32 = 8;
+
+/// Lowest load address of a position independent executable.
+const PIE_BASE: usize = 0x0000_7000_0000_0000;
+
+/// Number of page offsets the load address of a position independent
+/// executable is randomly slid by.
+const PIE_RANDOM_PAGES: usize = 0x4_0000; // 1 GiB
+
 #[repr(C)]
 /// Represents a 32-bit ELF Header.
 /// `ph` stands for the program header.
@@ -38,7 +70,160 @@ struct ElfProgramHeader64 {
     align: u64,
 }
 
-fn load_ph_headers(header: &ElfHeader64, elf: &Region) -> Option<Arc<Mutex<ElfRegion>>> {
+#[repr(C)]
+struct ElfDynamic64 {
+    tag: i64,
+    value: u64,
+}
+
+#[repr(C)]
+struct ElfRela64 {
+    offset: u64,
+    info: u64,
+    addend: i64,
+}
+
+/// Reads a structure from the ELF file, which may not be aligned for it.
+fn read_at<T>(elf: &Region, offset: usize) -> Option<T> {
+    let end = offset.checked_add(size_of::<T>())?;
+    if end > elf.size {
+        return None;
+    }
+
+    Some(unsafe { (elf.get_ptr::<u8>().add(offset) as *const T).read_unaligned() })
+}
+
+/// Iterates the program headers, the table must already be known to lie
+/// inside the file.
+fn program_headers<'a>(
+    header: &'a ElfHeader64,
+    elf: &'a Region,
+) -> impl Iterator<Item = ElfProgramHeader64> + 'a {
+    (0..header.ph_num as usize).filter_map(|index| {
+        let offset = header.ph_offset as usize + index * header.ph_entry_size as usize;
+        read_at::<ElfProgramHeader64>(elf, offset)
+    })
+}
+
+/// Finds where a range of virtual addresses is stored in the file.
+///
+/// ## Arguments
+///
+/// - `addr` the unrelocated virtual address
+/// - `size` the number of bytes that have to be file-backed
+///
+/// ## Returns
+/// The file offset, or `None` when the range isn't covered by the file
+/// contents of a LOAD segment.
+fn file_offset(header: &ElfHeader64, elf: &Region, addr: usize, size: usize) -> Option<usize> {
+    let end = addr.checked_add(size)?;
+
+    program_headers(header, elf)
+        .filter(|entry| entry.ph_type == PT_LOAD)
+        .find(|entry| {
+            let start = entry.virt_addr as usize;
+            addr >= start && end <= start.saturating_add(entry.file_size as usize)
+        })
+        .map(|entry| entry.offset as usize + (addr - entry.virt_addr as usize))
+}
+
+/// Applies the relative relocations of a position independent executable
+/// to the file contents, before they are copied to the load address.
+///
+/// ## Arguments
+///
+/// - `header` the ELF header
+/// - `elf` the raw ELF file contents, patched in place
+/// - `base` the address the executable is loaded at
+fn relocate(header: &ElfHeader64, elf: &Region, base: usize) -> Option<()> {
+    let Some(dynamic) = program_headers(header, elf).find(|entry| entry.ph_type == PT_DYNAMIC)
+    else {
+        // nothing to relocate
+        return Some(());
+    };
+
+    let mut rela_addr = None;
+    let mut rela_size = 0;
+    let mut rela_entry_size = size_of::<ElfRela64>();
+
+    let dynamic_count = dynamic.file_size as usize / size_of::<ElfDynamic64>();
+    for index in 0..dynamic_count {
+        let offset = dynamic.offset as usize + index * size_of::<ElfDynamic64>();
+        let entry = read_at::<ElfDynamic64>(elf, offset)?;
+
+        match entry.tag {
+            DT_NULL => break,
+            DT_RELA => rela_addr = Some(entry.value as usize),
+            DT_RELASZ => rela_size = entry.value as usize,
+            DT_RELAENT => rela_entry_size = entry.value as usize,
+            DT_REL => {
+                log!(
+                    LogType::ERR,
+                    "elf_loader: REL relocations are not supported"
+                );
+                return None;
+            }
+            _ => {}
+        }
+    }
+
+    let Some(rela_addr) = rela_addr else {
+        return Some(());
+    };
+
+    if rela_entry_size < size_of::<ElfRela64>() {
+        log!(
+            LogType::ERR,
+            "elf_loader: invalid RELA entry size {}",
+            rela_entry_size
+        );
+
+        return None;
+    }
+
+    let table_offset = file_offset(header, elf, rela_addr, rela_size)?;
+    for index in 0..rela_size / rela_entry_size {
+        let rela = read_at::<ElfRela64>(elf, table_offset + index * rela_entry_size)?;
+
+        match rela.info as u32 {
+            R_X86_64_NONE => {}
+            R_X86_64_RELATIVE => {
+                let Some(target) = file_offset(header, elf, rela.offset as usize, size_of::<u64>())
+                else {
+                    log!(
+                        LogType::ERR,
+                        "elf_loader: relocation target 0x{:X} is not backed by the file",
+                        rela.offset
+                    );
+
+                    return None;
+                };
+
+                let value = (base as u64).wrapping_add(rela.addend as u64);
+                unsafe {
+                    (elf.get_ptr::<u8>().add(target) as *mut u64).write_unaligned(value);
+                }
+            }
+            kind => {
+                log!(
+                    LogType::ERR,
+                    "elf_loader: unsupported relocation type {}",
+                    kind
+                );
+
+                return None;
+            }
+        }
+    }
+
+    Some(())
+}
+
+fn load_ph_headers(
+    header: &ElfHeader64,
+    elf: &Region,
+    base: usize,
+) -> Option<Arc<Mutex<ElfRegion>>> {
     let elf_ptr = elf.get_ptr::<u8>();
     let ph_table_size = (header.ph_num as usize).checked_mul(header.ph_entry_size as usize)?;
     let ph_table_end = (header.ph_offset as usize).checked_add(ph_table_size)?;
@@ -64,12 +249,11 @@ fn load_ph_headers(header: &ElfHeader64, elf: &Region) -> Option<Arc<Mutex<ElfRe
         let entry_ptr = unsafe { ph_ptr.add(ph_offset) };
 
         let entry = unsafe { &*(entry_ptr as *mut ElfProgramHeader64) };
-        if entry.ph_type != 1 {
-            // not a LOAD entry
+        if entry.ph_type != PT_LOAD {
             continue;
         }
 
-        let addr = entry.virt_addr as usize;
+        let addr = (entry.virt_addr as usize).checked_add(base)?;
         let size = entry.memory_size as usize;
         if size == 0 {
             log!(
@@ -165,17 +349,40 @@ pub fn load(elf: Region) -> Option<ProcessEntry> {
 
     // TODO: Do further ELF validation
 
-    let Some(start_region) = load_ph_headers(header, &elf) else {
+    // position independent executables are placed at a random base,
+    // everything else where it was linked to
+    let base = match header.elf_type {
+        ET_EXEC => 0,
+        ET_DYN => PIE_BASE + aslr::random_page_offset(PIE_RANDOM_PAGES),
+        elf_type => {
+            log!(
+                LogType::ERR,
+                "elf_loader: unsupported ELF type {}",
+                elf_type
+            );
+
+            return None;
+        }
+    };
+
+    let Some(start_region) = load_ph_headers(header, &elf, base) else {
         log!(LogType::ERR, "elf_loader: failed to load program headers");
         return None;
     };
 
-    let entry = header.entry_addr as usize;
+    if header.elf_type == ET_DYN && relocate(header, &elf, base).is_none() {
+        log!(LogType::ERR, "elf_loader: failed to relocate executable");
+        return None;
+    }
+
+    let entry = (header.entry_addr as usize).checked_add(base)?;
     Some(ProcessEntry {
         entry: entry,
         start_region: start_region,
         ring3_page_table: None,
+        user_page_table: None,
         stack: None,
         initial_rsp: 0,
+        vmas: VmaTree::new(),
     })
 }
@@ -1,18 +1,33 @@
 use crate::{
+    arch::x86_64::user_access,
     io::LogType,
     log,
     mem::{
-        paging::{entry::EntryFlags, Page},
-        MemoryController, Region, Stack, GLOBAL_MEMORY_CONTROLLER,
+        aslr, kpti,
+        paging::{entry::EntryFlags, Page, PageTable},
+        MemoryController, Region, Stack, GLOBAL_MEMORY_CONTROLLER, PAGE_SIZE,
+    },
+    scheduling::{
+        process::ProcessEntry,
+        vma::{Vma, VmaBacking, VmaTree},
     },
-    scheduling::process::ProcessEntry,
 };
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
 use spin::Mutex;
 
 mod loader;
 
-const USER_STACK_PAGES: usize = 32;
+/// Number of pages reserved for a user stack, they are mapped on
+/// first access as the stack grows down.
+const USER_STACK_PAGES: usize = 256;
+
+/// Number of pages at the top of a user stack that are mapped up front,
+/// they hold the argument frame.
+const USER_STACK_INITIAL_PAGES: usize = 8;
+
+/// Number of page offsets the top of a user stack is randomly slid down
+/// by, they are reserved on top of the usable stack pages.
+const USER_STACK_RANDOM_PAGES: usize = 64;
 
 bitflags! {
     #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -102,52 +117,17 @@ impl ElfRegion {
     }
 }
 
-pub fn unmap(start_region: &Arc<Mutex<ElfRegion>>) {
-    let mut mem_controller = GLOBAL_MEMORY_CONTROLLER.lock();
-    let Some(mem_controller) = mem_controller.as_mut() else {
-        log!(
-            LogType::ERR,
-            "elf_unmap: memory controller is not initialized"
-        );
-        return;
-    };
-
-    unmap_regions_from_active_table(mem_controller, start_region);
-}
-
-fn unmap_regions_from_active_table(
-    mem_controller: &mut MemoryController,
-    start_region: &Arc<Mutex<ElfRegion>>,
-) {
-    let iter = ElfRegionIterator::from(start_region.clone());
-    for region in iter {
-        let region = region.lock();
-        if region.region.size == 0 {
-            continue;
-        }
-
-        unsafe {
-            let ptr = region.region.get_ptr::<u8>();
-            core::ptr::write_bytes(ptr, 0, region.region.size);
-        }
-
-        let start_page = Page::for_address(region.region.addr);
-        let end_page = Page::for_address((region.region.addr + region.region.size - 1) as usize);
-
-        mem_controller.unmap(start_page, end_page);
-    }
-}
-
 /// Loads an ELF binary into a fresh process address space.
 ///
 /// ## Arguments
 ///
 /// - `elf` the raw ELF file contents
 /// - `argv` the process arguments, starting with the program name
+/// - `envp` the process environment as `KEY=VALUE` entries
 ///
 /// ## Returns
 /// A process entry ready to be deployed.
-pub fn load(elf: Region, argv: &[&str]) -> Option<ProcessEntry> {
+pub fn load(elf: Region, argv: &[&str], envp: &[&str]) -> Option<ProcessEntry> {
     let Some(mut entry) = loader::load(elf) else {
         log!(LogType::ERR, "elf_load: loader::load failed");
         return None;
@@ -177,38 +157,69 @@ pub fn load(elf: Region, argv: &[&str]) -> Option<ProcessEntry> {
             ring3_table.addr
         );
 
+        mc.free_address_space(&ring3_table, None);
         return None;
     };
 
+    // map the pages holding file contents up front, the zero-filled rest
+    // of every segment is mapped on first access
+    let mut vmas = VmaTree::new();
+    let mut mapped_end = 0;
     let iter = ElfRegionIterator::from(start_region.clone());
     for region in iter {
         let region = region.lock();
 
         let addr = region.region.addr;
         let size = region.region.size;
+        let file_size = region.origin_buffer.size.min(size);
+        if size == 0 {
+            continue;
+        }
 
-        let start_page = Page::for_address(addr);
-        let end_page = Page::for_address(addr + size - 1);
-
+        // segments may share a page, which then belongs to the first one
         let flags = region.flags.to_entry_flags();
-        ring3_table.map_range(
-            start_page,
-            end_page,
-            flags,
-            &mut mc.frame_allocator,
-            &mut mc.slot_allocator,
-            &mut mc.temp_mapper,
-        );
+        let start = Page::for_address(addr).start_address().max(mapped_end);
+        let file_end = if file_size > 0 {
+            Page::for_address(addr + file_size - 1).start_address() + PAGE_SIZE
+        } else {
+            start
+        };
 
-        // inspect mapped pages
-        /*
-        print!("\n");
+        if start < file_end {
+            // insert the area first, so a partial mapping is discarded too
+            vmas.insert(Vma {
+                start: start,
+                end: file_end,
+                flags: flags,
+                backing: VmaBacking::Image,
+            });
+
+            let mapped = mc.map_user(
+                &mut ring3_table,
+                Page::for_address(start),
+                Page::for_address(file_end - 1),
+                flags,
+            );
 
-        for page in Page::range(start_page, end_page) {
-            ring3_table.inspect_page(page, &mut mc.temp_mapper);
-            print!("\n");
+            if mapped.is_none() {
+                log!(LogType::ERR, "elf_load: out of memory");
+                discard(mc, &mut vmas, &mut ring3_table, &prev_table);
+                return None;
+            }
         }
-        */
+
+        let lazy_start = file_end.max(start);
+        let lazy_end = Page::for_address(addr + size - 1).start_address() + PAGE_SIZE;
+        if lazy_start < lazy_end {
+            vmas.insert(Vma {
+                start: lazy_start,
+                end: lazy_end,
+                flags: flags,
+                backing: VmaBacking::Anonymous,
+            });
+        }
+
+        mapped_end = mapped_end.max(lazy_end);
     }
 
     // load elf regions
@@ -217,8 +228,7 @@ pub fn load(elf: Region, argv: &[&str]) -> Option<ProcessEntry> {
         let region = region.lock();
 
         // load entry into memory
-        let ph_file_src = region.origin_buffer.addr as *mut u8;
-        let destination_ptr = region.region.addr as *mut u8;
+        let destination = region.region.addr;
 
         let ph_file_size = region.origin_buffer.size;
         let size = region.region.size;
@@ -232,49 +242,99 @@ pub fn load(elf: Region, argv: &[&str]) -> Option<ProcessEntry> {
                 size
             );
 
-            unmap_regions_from_active_table(mc, &start_region);
-            mc.switch_table(&prev_table);
+            discard(mc, &mut vmas, &mut ring3_table, &prev_table);
             return None;
         }
 
-        unsafe {
-            core::ptr::copy_nonoverlapping(ph_file_src, destination_ptr, ph_file_size);
+        // the first page may be shared with the lazy tail of the segment
+        // before, the BSS needs no clearing, fresh pages are zeroed
+        if vmas
+            .populate(&mut ring3_table, mc, region.region.addr, ph_file_size)
+            .is_none()
+        {
+            log!(LogType::ERR, "elf_load: out of memory");
+            discard(mc, &mut vmas, &mut ring3_table, &prev_table);
+            return None;
         }
 
-        // check if BSS exists
-        let bss_size = (size as i64) - (ph_file_size as i64);
-        if bss_size > 0 {
-            // zero BSS
-            let bss_ptr = unsafe { destination_ptr.add(ph_file_size as usize) };
-            unsafe { core::ptr::write_bytes(bss_ptr, 0, bss_size as usize) };
+        if unsafe { user_access::copy_to_user(destination, region.origin_buffer.as_slice()) }
+            .is_none()
+        {
+            log!(
+                LogType::ERR,
+                "elf_load: failed to copy segment to 0x{:X}",
+                destination
+            );
+
+            discard(mc, &mut vmas, &mut ring3_table, &prev_table);
+            return None;
         }
     }
 
-    // allocate stack
-    let Some(stack) = mc.stack_allocator.alloc(
-        &mut ring3_table,
-        &mut mc.frame_allocator,
-        &mut mc.slot_allocator,
-        &mut mc.temp_mapper,
-        USER_STACK_PAGES,
-        EntryFlags::WRITABLE | EntryFlags::RING3_ACCESSIBLE,
-    ) else {
+    // reserve the stack, only its top is mapped for the argument frame
+    let stack_pages = USER_STACK_PAGES + USER_STACK_RANDOM_PAGES;
+    let Some(stack) = mc.user_stack_allocator.reserve(stack_pages) else {
         log!(LogType::ERR, "elf_load: failed to allocate user stack");
-        unmap_regions_from_active_table(mc, &start_region);
-        mc.switch_table(&prev_table);
+        discard(mc, &mut vmas, &mut ring3_table, &prev_table);
         return None;
     };
 
+    // the stack top is slid down by a random number of pages,
+    // and by a random 16 byte aligned offset within its page
+    let stack_top = stack.top - aslr::random_page_offset(USER_STACK_RANDOM_PAGES);
+    let stack_offset = aslr::random_below(PAGE_SIZE / 16) * 16;
+
+    let stack_flags = EntryFlags::WRITABLE | EntryFlags::RING3_ACCESSIBLE;
+    let initial_stack = Stack::new(
+        stack_top - stack_offset,
+        stack_top - USER_STACK_INITIAL_PAGES * PAGE_SIZE,
+    );
+
+    // the stack is unmapped together with the other areas
+    vmas.insert(Vma {
+        start: stack.bottom,
+        end: stack.top,
+        flags: stack_flags,
+        backing: VmaBacking::Stack,
+    });
+
+    let mapped = mc.map_user(
+        &mut ring3_table,
+        Page::for_address(initial_stack.bottom),
+        Page::for_address(stack_top - 1),
+        stack_flags,
+    );
+
+    if mapped.is_none() {
+        log!(LogType::ERR, "elf_load: out of memory");
+        discard(mc, &mut vmas, &mut ring3_table, &prev_table);
+        return None;
+    }
+
     // write the argument frame while the process page table
     // is still active
-    let Some(initial_rsp) = write_args_frame(&stack, argv) else {
+    let Some(initial_rsp) = write_args_frame(&initial_stack, argv, envp) else {
         log!(LogType::ERR, "elf_load: failed to write argument frame");
-        mc.free_stack(&stack);
-        unmap_regions_from_active_table(mc, &start_region);
-        mc.switch_table(&prev_table);
+        mc.user_stack_allocator.free(&stack);
+        discard(mc, &mut vmas, &mut ring3_table, &prev_table);
         return None;
     };
 
+    // with page table isolation the process runs on a table that only
+    // gets its user mappings
+    let user_table = if kpti::is_enabled() {
+        let Some(user_table) = kpti::create_user_table(mc) else {
+            log!(LogType::ERR, "elf_load: failed to allocate user page table");
+            mc.user_stack_allocator.free(&stack);
+            discard(mc, &mut vmas, &mut ring3_table, &prev_table);
+            return None;
+        };
+
+        Some(user_table)
+    } else {
+        None
+    };
+
     // Switch back to root table
     if mc.switch_table(&prev_table).is_none() {
         log!(
@@ -287,24 +347,46 @@ pub fn load(elf: Region, argv: &[&str]) -> Option<ProcessEntry> {
     }
 
     entry.ring3_page_table = Some(ring3_table);
+    entry.user_page_table = user_table;
     entry.stack = Some(stack);
     entry.initial_rsp = initial_rsp;
+    entry.vmas = vmas;
 
     Some(entry)
 }
 
+/// Drops a process that failed to load: unmaps its memory areas, switches
+/// back to the previous page table and frees the address space.
+///
+/// ## Arguments
+///
+/// - `mc` the memory controller
+/// - `vmas` the memory areas mapped so far
+/// - `table` the page table of the process
+/// - `prev_table` the page table that was active before loading
+fn discard(
+    mc: &mut MemoryController,
+    vmas: &mut VmaTree,
+    table: &mut PageTable,
+    prev_table: &PageTable,
+) {
+    vmas.unmap_all(table, mc);
+    mc.switch_table(prev_table);
+    mc.free_address_space(table, None);
+}
+
 /// Writes a System V style argument frame onto a fresh user stack.
 ///
-/// The argument strings are copied NUL-terminated to the very top of the
-/// stack, and below them sits the pointer frame the process starts with:
+/// The argument and environment strings are copied NUL-terminated to the
+/// very top of the stack, and below them sits the pointer frame the process
+/// starts with:
 ///
 /// ```text
-/// rsp -> [argc][argv[0]]..[argv[argc - 1]][NULL][NULL] .. [strings]
+/// rsp -> [argc][argv[0]]..[argv[argc - 1]][NULL][envp[0]]..[NULL] .. [strings]
 /// ```
 ///
-/// The final `NULL` terminates the (empty) environment list. The initial
-/// stack pointer is 16-byte aligned and points at `argc`, matching what a
-/// standard libc `_start` expects.
+/// The initial stack pointer is 16-byte aligned and points at `argc`,
+/// matching what a standard libc `_start` expects.
 ///
 /// Must be called while the process page table is active.
 ///
@@ -312,16 +394,22 @@ pub fn load(elf: Region, argv: &[&str]) -> Option<ProcessEntry> {
 ///
 /// - `stack` the user stack to write into
 /// - `argv` the process arguments, starting with the program name
+/// - `envp` the process environment as `KEY=VALUE` entries
 ///
 /// ## Returns
 /// The initial user stack pointer, or `None` when the frame does not fit
 /// into the stack.
-fn write_args_frame(stack: &Stack, argv: &[&str]) -> Option<usize> {
-    let string_bytes: usize = argv.iter().map(|arg| arg.len() + 1).sum();
+fn write_args_frame(stack: &Stack, argv: &[&str], envp: &[&str]) -> Option<usize> {
+    let string_bytes: usize = argv
+        .iter()
+        .chain(envp.iter())
+        .map(|string| string.len() + 1)
+        .sum();
     let strings_base = stack.top.checked_sub(string_bytes)?;
 
-    // argc + argv pointers + argv NULL terminator + envp NULL terminator
-    let frame_words = argv.len().checked_add(3)?;
+    // argc + argv pointers + argv NULL terminator + envp pointers
+    // + envp NULL terminator
+    let frame_words = argv.len().checked_add(envp.len())?.checked_add(3)?;
     let frame_size = frame_words.checked_mul(core::mem::size_of::<usize>())?;
     let frame_base = strings_base.checked_sub(frame_size)? & !0xF;
 
@@ -329,22 +417,30 @@ fn write_args_frame(stack: &Stack, argv: &[&str]) -> Option<usize> {
         return None;
     }
 
-    let frame = frame_base as *mut usize;
-    let mut string_addr = strings_base;
-
-    unsafe {
-        *frame = argv.len();
+    // the frame is built in kernel memory first,
+    // user pages are only touched by the copy helpers
+    let mut frame: Vec<usize> = Vec::with_capacity(frame_words);
+    let mut strings: Vec<u8> = Vec::with_capacity(string_bytes);
+    frame.push(argv.len());
+
+    // argv and envp are both NULL-terminated pointer lists,
+    // laid out back to back after argc
+    for list in [argv, envp] {
+        for string in list {
+            frame.push(strings_base + strings.len());
+            strings.extend_from_slice(string.as_bytes());
+            strings.push(0);
+        }
 
-        for (index, arg) in argv.iter().enumerate() {
-            *frame.add(1 + index) = string_addr;
+        frame.push(0);
+    }
 
-            core::ptr::copy_nonoverlapping(arg.as_ptr(), string_addr as *mut u8, arg.len());
-            *((string_addr + arg.len()) as *mut u8) = 0;
-            string_addr += arg.len() + 1;
-        }
+    let frame_bytes =
+        unsafe { core::slice::from_raw_parts(frame.as_ptr() as *const u8, frame_size) };
 
-        *frame.add(1 + argv.len()) = 0;
-        *frame.add(2 + argv.len()) = 0;
+    unsafe {
+        user_access::copy_to_user(frame_base, frame_bytes)?;
+        user_access::copy_to_user(strings_base, &strings)?;
     }
 
     Some(frame_base)
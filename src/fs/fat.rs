@@ -1,5 +1,7 @@
 use alloc::{format, string::String, vec::Vec};
 
+use crate::arch::x86_64::rtc::days_from_civil;
+
 /// Flag set in the order byte of the first physical long filename entry.
 pub const LFN_LAST_ENTRY_FLAG: u8 = 0x40;
 
@@ -117,6 +119,53 @@ impl DirectoryEntry {
         self.first_cluster_high = ((cluster >> 16) & 0xFFFF) as u16;
         self.first_cluster_low = (cluster & 0xFFFF) as u16;
     }
+
+    /// The creation time in seconds since the Unix epoch, or 0 if unset.
+    pub fn created_unix(&self) -> u64 {
+        let centiseconds = self.creation_time_centiseconds as u64;
+        fat_timestamp_to_unix(self.creation_date, self.creation_time)
+            .map(|seconds| seconds + centiseconds / 100)
+            .unwrap_or(0)
+    }
+
+    /// The last modification time in seconds since the Unix epoch, or 0 if
+    /// unset.
+    pub fn modified_unix(&self) -> u64 {
+        fat_timestamp_to_unix(self.modified_date, self.modified_time).unwrap_or(0)
+    }
+
+    /// The last access date in seconds since the Unix epoch, or 0 if unset.
+    ///
+    /// FAT only records the access date, so the time of day is midnight.
+    pub fn accessed_unix(&self) -> u64 {
+        fat_timestamp_to_unix(self.last_accessed_date, 0).unwrap_or(0)
+    }
+}
+
+/// Converts a FAT date and time pair into seconds since the Unix epoch.
+///
+/// ## Arguments
+///
+/// - `date` the packed date, `yyyyyyym mmmddddd` with years since 1980
+/// - `time` the packed time, `hhhhhmmm mmmsssss` with seconds halved
+///
+/// ## Returns
+/// The Unix time, or `None` when the date is unset or invalid.
+pub fn fat_timestamp_to_unix(date: u16, time: u16) -> Option<u64> {
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0x0F) as u64;
+    let day = (date & 0x1F) as u64;
+
+    if month == 0 || month > 12 || day == 0 {
+        return None;
+    }
+
+    let hours = (time >> 11) as u64;
+    let minutes = ((time >> 5) & 0x3F) as u64;
+    let seconds = ((time & 0x1F) * 2) as u64;
+
+    let days = days_from_civil(year, month, day);
+    Some(days as u64 * 86_400 + hours * 3_600 + minutes * 60 + seconds)
 }
 
 /// A long filename directory entry as stored on disk.
@@ -1,4 +1,4 @@
-use core::{alloc::Layout, cmp::min};
+use core::{alloc::Layout, any::Any, cmp::min};
 
 use alloc::{
     alloc::{alloc, dealloc},
@@ -22,7 +22,7 @@ use super::{
         get_filename_from_fat, lfn_checksum, DirectoryEntry, LongDirectoryEntry, LFN_MAX_ENTRIES,
         LFN_UNITS_PER_ENTRY,
     },
-    fs::{Directory, DirectoryItems, File},
+    fs::{Directory, DirectoryItems, File, FileType, Metadata},
 };
 
 const FAT_CLUSTER_FREE: u32 = 0x00000000;
@@ -41,6 +41,12 @@ pub struct DirectoryEntryLocation {
     pub offset: usize,
 }
 
+impl DirectoryEntryLocation {
+    pub fn is_same(&self, other: &DirectoryEntryLocation) -> bool {
+        self.cluster == other.cluster && self.index == other.index
+    }
+}
+
 /// A directory entry together with its on-disk location and decoded name.
 ///
 /// When the entry carries a long filename, `name` holds it and
@@ -90,7 +96,8 @@ impl Directory for FATDirectory {
         let files: Vec<Arc<RwLock<dyn File>>> = files
             .into_iter()
             .map(|f| {
-                let file = FATFile::new(f.entry, f.name, f.location, self.fs.clone());
+                let location = fs_guard.track_location(f.location);
+                let file = FATFile::new(f.entry, f.name, location, self.fs.clone());
                 Arc::new(RwLock::new(file)) as Arc<RwLock<dyn File>>
             })
             .collect();
@@ -98,15 +105,67 @@ impl Directory for FATDirectory {
         (directories, files)
     }
 
+    fn metadata(&self) -> Option<Metadata> {
+        let fs = self.fs.upgrade()?;
+        let fs_guard = fs.lock();
+        Some(fs_guard.entry_metadata(&self.entry))
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Directory>> {
+        // follow the `..` entry on disk, a rename may have moved this
+        // directory since it was looked up
+        let fs = self.fs.upgrade()?;
+        let mut fs_guard = fs.lock();
+        let cluster = fs_guard.directory_cluster(&self.entry);
+        if cluster == fs_guard.root().get_cluster() {
+            return None;
+        }
+
+        let parent_cluster = fs_guard.parent_cluster(cluster)?;
+        if parent_cluster == fs_guard.root().get_cluster() {
+            drop(fs_guard);
+            return Some(Arc::new(FATFileSystem::root_dir(fs)));
+        }
+
+        let parent = fs_guard.locate_directory(parent_cluster)?;
+        Some(Arc::new(FATDirectory::new(
+            parent.entry,
+            parent.name,
+            self.fs.clone(),
+        )))
+    }
+
+    fn find_directory(&self, name: &str) -> Option<Arc<dyn Directory>> {
+        // resolve dot entries through the parent, so the result keeps its
+        // real name and can still reconstruct its absolute path
+        match name {
+            "." => Some(Arc::new(self.clone())),
+            ".." => match self.parent() {
+                Some(parent) => Some(parent),
+                None => Some(Arc::new(self.clone())),
+            },
+            _ => {
+                let items = self.list_dir();
+                let directory = items
+                    .0
+                    .into_iter()
+                    .find(|d| d.name().eq_ignore_ascii_case(name))?;
+
+                Some(directory)
+            }
+        }
+    }
+
     fn create_file(&self, name: &str) -> Option<Arc<RwLock<dyn File>>> {
         let fs = self.fs.upgrade()?;
         let mut fs_guard = fs.lock();
         let file = fs_guard.create_file(&self.entry, name)?;
+        let location = fs_guard.track_location(file.location);
 
         Some(Arc::new(RwLock::new(FATFile::new(
             file.entry,
             file.name,
-            file.location,
+            location,
             self.fs.clone(),
         ))))
     }
@@ -128,6 +187,33 @@ impl Directory for FATDirectory {
         let mut fs_guard = fs.lock();
         fs_guard.remove_directory(&self.entry, name)
     }
+
+    fn rename(&self, name: &str, target: &dyn Directory, new_name: &str) -> Option<()> {
+        let target = target.as_any().downcast_ref::<FATDirectory>()?;
+        if !self.fs.ptr_eq(&target.fs) {
+            return None;
+        }
+
+        let fs = self.fs.upgrade()?;
+        let mut fs_guard = fs.lock();
+        fs_guard.rename(&self.entry, name, &target.entry, new_name)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn absolute_path(&self) -> String {
+        let Some(fs) = self.fs.upgrade() else {
+            return String::from("/");
+        };
+
+        let mut fs_guard = fs.lock();
+        let cluster = fs_guard.directory_cluster(&self.entry);
+        fs_guard
+            .directory_path(cluster)
+            .unwrap_or_else(|| String::from("/"))
+    }
 }
 
 impl FATDirectory {
@@ -144,7 +230,10 @@ impl FATDirectory {
 pub struct FATFile {
     entry: DirectoryEntry,
     name: String,
-    location: DirectoryEntryLocation,
+
+    /// Shared by all handles of the file, so they follow it when a rename
+    /// moves its directory entry.
+    location: Arc<Mutex<DirectoryEntryLocation>>,
     fs: Weak<Mutex<FATFileSystem>>,
 }
 
@@ -159,25 +248,41 @@ impl File for FATFile {
         };
 
         let mut fs_guard = fs.lock();
+        let location = *self.location.lock();
         fs_guard
-            .read_directory_entry(self.location)
+            .read_directory_entry(location)
             .map(|entry| entry.size as usize)
             .unwrap_or(self.entry.size as usize)
     }
 
+    fn metadata(&self) -> Option<Metadata> {
+        let fs = self.fs.upgrade()?;
+        let mut fs_guard = fs.lock();
+        let entry = fs_guard.read_directory_entry(*self.location.lock())?;
+        Some(fs_guard.entry_metadata(&entry))
+    }
+
     fn read(&self) -> Option<Region> {
         let fs = self.fs.upgrade().unwrap();
         let mut fs_guard = fs.lock();
-        let entry = fs_guard.read_directory_entry(self.location)?;
+        let entry = fs_guard.read_directory_entry(*self.location.lock())?;
         fs_guard.read_file(&entry)
     }
 
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Option<usize> {
+        let fs = self.fs.upgrade()?;
+        let mut fs_guard = fs.lock();
+        let entry = fs_guard.read_directory_entry(*self.location.lock())?;
+        fs_guard.read_file_range(&entry, offset, buffer)
+    }
+
     fn write(&self, offset: usize, bytes: &[u8]) -> Option<usize> {
         let fs = self.fs.upgrade().unwrap();
         let mut fs_guard = fs.lock();
-        let entry = fs_guard.read_directory_entry(self.location)?;
+        let location = *self.location.lock();
+        let entry = fs_guard.read_directory_entry(location)?;
         let bytes_written = fs_guard.write_existing_file(&entry, offset, bytes)?;
-        fs_guard.persist_directory_entry(self.location, &entry)?;
+        fs_guard.persist_directory_entry(location, &entry)?;
 
         Some(bytes_written)
     }
@@ -186,7 +291,8 @@ impl File for FATFile {
         let fs = self.fs.upgrade().unwrap();
         let mut fs_guard = fs.lock();
 
-        fs_guard.truncate_existing_file(&mut self.entry, self.location, size)
+        let location = *self.location.lock();
+        fs_guard.truncate_existing_file(&mut self.entry, location, size)
     }
 }
 
@@ -194,7 +300,7 @@ impl FATFile {
     pub fn new(
         entry: DirectoryEntry,
         name: String,
-        location: DirectoryEntryLocation,
+        location: Arc<Mutex<DirectoryEntryLocation>>,
         fs: Weak<Mutex<FATFileSystem>>,
     ) -> Self {
         Self {
@@ -211,21 +317,39 @@ pub struct FATFileSystem {
     fat: FatBuffer,
     bs: FatBootSector,
     bs_32: Fat32ExtendedBootSector,
+
+    /// The directory entry locations of the files handed out, updated when
+    /// a rename moves an entry.
+    live_locations: Vec<Weak<Mutex<DirectoryEntryLocation>>>,
 }
 
 impl FATFileSystem {
-    pub fn new(mut port: Box<AHCIPort>) -> Option<Self> {
-        let (bs, bs_32) = read_boot_sector(&mut *port)?;
-        let fat_buff = read_fat(&mut *port, &bs, &bs_32)?;
+    /// Reads the FAT volume of a disk.
+    ///
+    /// ## Arguments
+    ///
+    /// - `port` the port of the disk
+    ///
+    /// ## Returns
+    /// The filesystem, or the port back when the volume can't be read.
+    pub fn new(mut port: Box<AHCIPort>) -> Result<Self, Box<AHCIPort>> {
+        let Some((bs, bs_32)) = read_boot_sector(&mut *port) else {
+            return Err(port);
+        };
+
+        let Some(fat_buff) = read_fat(&mut *port, &bs, &bs_32) else {
+            return Err(port);
+        };
 
         let fs = FATFileSystem {
             port: port,
             fat: fat_buff,
             bs: bs,
             bs_32: bs_32,
+            live_locations: Vec::new(),
         };
 
-        Some(fs)
+        Ok(fs)
     }
 
     pub fn root_dir(self_arc: Arc<Mutex<FATFileSystem>>) -> FATDirectory {
@@ -234,6 +358,49 @@ impl FATFileSystem {
         FATDirectory::new(root, String::from("root"), fs_weak)
     }
 
+    /// Gives back the port of the volume once it is unmounted. Every change
+    /// was written through already.
+    pub fn into_port(self) -> Box<AHCIPort> {
+        self.port
+    }
+
+    /// Gives the shared location of a file entry, the same one for every
+    /// handle of the file that is still alive.
+    ///
+    /// ## Arguments
+    ///
+    /// - `location` the location of the short entry of the file
+    fn track_location(
+        &mut self,
+        location: DirectoryEntryLocation,
+    ) -> Arc<Mutex<DirectoryEntryLocation>> {
+        self.live_locations.retain(|live| live.strong_count() > 0);
+
+        let existing = self
+            .live_locations
+            .iter()
+            .filter_map(|live| live.upgrade())
+            .find(|live| live.lock().is_same(&location));
+
+        if let Some(existing) = existing {
+            return existing;
+        }
+
+        let live = Arc::new(Mutex::new(location));
+        self.live_locations.push(Arc::downgrade(&live));
+        live
+    }
+
+    /// Points the handles of a file at its new entry after a rename.
+    fn move_live_location(&mut self, from: DirectoryEntryLocation, to: DirectoryEntryLocation) {
+        for live in self.live_locations.iter().filter_map(|live| live.upgrade()) {
+            let mut location = live.lock();
+            if location.is_same(&from) {
+                *location = to;
+            }
+        }
+    }
+
     fn root(&self) -> DirectoryEntry {
         let root_cluster = self.bs_32.root_cluster;
         let root_name = get_fat_filename("root").unwrap();
@@ -439,6 +606,35 @@ impl FATFileSystem {
         })
     }
 
+    /// Builds the metadata for a short directory entry.
+    ///
+    /// ## Arguments
+    ///
+    /// - `entry` the short 8.3 entry of a file or directory
+    fn entry_metadata(&self, entry: &DirectoryEntry) -> Metadata {
+        let file_type = if entry.is_directory() {
+            FileType::Directory
+        } else {
+            FileType::Regular
+        };
+
+        let first_cluster = if entry.is_directory() {
+            self.directory_cluster(entry)
+        } else {
+            entry.get_cluster()
+        };
+
+        Metadata {
+            file_type: file_type,
+            size: entry.size as usize,
+            attributes: entry.attributes,
+            created: entry.created_unix(),
+            modified: entry.modified_unix(),
+            accessed: entry.accessed_unix(),
+            cluster_count: self.fat.chain_length(first_cluster),
+        }
+    }
+
     fn find_entry_by_name(
         &mut self,
         dir: &DirectoryEntry,
@@ -632,6 +828,189 @@ impl FATFileSystem {
         self.delete_directory_entry(directory)
     }
 
+    /// Renames an entry, moving it into another directory when the parents
+    /// differ.
+    ///
+    /// Only the name entries are rewritten; the data clusters stay in place.
+    /// The new entries are persisted before the old ones are deleted, so an
+    /// interrupted rename leaves the entry reachable under at least one name.
+    ///
+    /// ## Arguments
+    ///
+    /// - `source_dir` the directory currently containing the entry
+    /// - `name` the current name of the entry
+    /// - `target_dir` the directory to move the entry into
+    /// - `new_name` the new name of the entry
+    fn rename(
+        &mut self,
+        source_dir: &DirectoryEntry,
+        name: &str,
+        target_dir: &DirectoryEntry,
+        new_name: &str,
+    ) -> Option<()> {
+        let source_cluster = self.directory_cluster(source_dir);
+        let target_cluster = self.directory_cluster(target_dir);
+
+        let located = self.find_entry_by_name(source_dir, name)?;
+        let short_name = located.entry.name;
+        if Self::is_dot_name(&short_name) {
+            return None;
+        }
+
+        let (target_slots, _) = self.load_directory_slots(target_cluster)?;
+        let target_parsed = Self::parse_directory_entries(&target_slots);
+
+        if let Some(existing) = Self::find_parsed_entry(&target_parsed, new_name) {
+            // only a case change of the entry itself may reuse the name
+            let same_entry = existing.location.is_same(&located.location);
+
+            if !same_entry {
+                return None;
+            }
+        }
+
+        let is_directory = located.entry.is_directory();
+        let moves_parent = source_cluster != target_cluster;
+        if is_directory && moves_parent {
+            let moved_cluster = located.entry.get_cluster();
+            if self.is_same_or_descendant(target_cluster, moved_cluster)? {
+                return None;
+            }
+        }
+
+        let (new_short_name, lfn_entries) = Self::prepare_name_entries(&target_parsed, new_name)?;
+        let mut entry = located.entry.clone();
+        entry.name = new_short_name;
+
+        let (new_location, _) = self.persist_new_entry(target_cluster, &lfn_entries, &entry)?;
+        self.delete_name_entries(&located)?;
+
+        if !is_directory {
+            self.move_live_location(located.location, new_location);
+        }
+
+        if is_directory && moves_parent {
+            self.update_parent_link(located.entry.get_cluster(), target_cluster)?;
+        }
+
+        Some(())
+    }
+
+    /// Checks whether a directory is another directory or lies below it, by
+    /// following the `..` links up to the root.
+    ///
+    /// ## Arguments
+    ///
+    /// - `cluster` the first cluster of the directory to check
+    /// - `ancestor` the first cluster of the potential ancestor
+    fn is_same_or_descendant(&mut self, cluster: usize, ancestor: usize) -> Option<bool> {
+        let root_cluster = self.root().get_cluster();
+        let mut current = cluster;
+
+        // bound the walk so a corrupted, cyclic tree cannot hang the kernel
+        for _ in 0..self.fat.entries {
+            if current == ancestor {
+                return Some(true);
+            }
+
+            if current == root_cluster {
+                return Some(false);
+            }
+
+            current = self.parent_cluster(current)?;
+        }
+
+        None
+    }
+
+    /// Reads the `..` entry of a directory.
+    ///
+    /// ## Arguments
+    ///
+    /// - `cluster` the first cluster of a directory other than the root
+    ///
+    /// ## Returns
+    /// The first cluster of the parent directory.
+    fn parent_cluster(&mut self, cluster: usize) -> Option<usize> {
+        let (slots, _) = self.load_directory_slots(cluster)?;
+        let (parent, _) = slots
+            .iter()
+            .find(|(entry, _)| entry.name == Self::dot_name(true))?;
+
+        Some(self.directory_cluster(parent))
+    }
+
+    /// Finds the entry of a directory inside its parent, which its `..`
+    /// entry points at.
+    ///
+    /// ## Arguments
+    ///
+    /// - `cluster` the first cluster of a directory other than the root
+    fn locate_directory(&mut self, cluster: usize) -> Option<LocatedDirectoryEntry> {
+        let parent_cluster = self.parent_cluster(cluster)?;
+        let (slots, _) = self.load_directory_slots(parent_cluster)?;
+
+        Self::parse_directory_entries(&slots)
+            .into_iter()
+            .find(|parsed| {
+                parsed.entry.is_directory()
+                    && !Self::is_dot_name(&parsed.entry.name)
+                    && self.directory_cluster(&parsed.entry) == cluster
+            })
+    }
+
+    /// Builds the absolute path of a directory from the entries on disk.
+    ///
+    /// ## Arguments
+    ///
+    /// - `cluster` the first cluster of the directory
+    ///
+    /// ## Returns
+    /// The path, or `None` when the tree is corrupted.
+    fn directory_path(&mut self, cluster: usize) -> Option<String> {
+        let root_cluster = self.root().get_cluster();
+        let mut names: Vec<String> = Vec::new();
+        let mut current = cluster;
+
+        // bound the walk like `is_same_or_descendant` does
+        for _ in 0..self.fat.entries {
+            if current == root_cluster {
+                let mut path = String::new();
+                for name in names.iter().rev() {
+                    path.push('/');
+                    path.push_str(name);
+                }
+
+                if path.is_empty() {
+                    path.push('/');
+                }
+
+                return Some(path);
+            }
+
+            names.push(self.locate_directory(current)?.name);
+            current = self.parent_cluster(current)?;
+        }
+
+        None
+    }
+
+    /// Points the `..` entry of a directory at a new parent.
+    ///
+    /// ## Arguments
+    ///
+    /// - `cluster` the first cluster of the directory
+    /// - `parent_cluster` the first cluster of the new parent directory
+    fn update_parent_link(&mut self, cluster: usize, parent_cluster: usize) -> Option<()> {
+        let (slots, _) = self.load_directory_slots(cluster)?;
+        let (mut parent, location) = slots
+            .into_iter()
+            .find(|(entry, _)| entry.name == Self::dot_name(true))?;
+
+        parent.set_cluster(parent_cluster);
+        self.persist_directory_entry(location, &parent)
+    }
+
     /// Finds a run of consecutive free directory entry slots, extending the
     /// directory with new clusters when needed.
     ///
@@ -770,17 +1149,9 @@ impl FATFileSystem {
     }
 
     fn delete_directory_entry(&mut self, located: LocatedDirectoryEntry) -> Option<()> {
-        for lfn_location in &located.lfn_locations {
-            let mut lfn_entry = self.read_directory_entry(*lfn_location)?;
-            lfn_entry.mark_deleted();
-            self.persist_directory_entry(*lfn_location, &lfn_entry)?;
-        }
+        self.delete_name_entries(&located)?;
 
         let first_cluster = located.entry.get_cluster();
-        let mut deleted_entry = located.entry;
-        deleted_entry.mark_deleted();
-        self.persist_directory_entry(located.location, &deleted_entry)?;
-
         if first_cluster == 0 {
             return Some(());
         }
@@ -793,6 +1164,20 @@ impl FATFileSystem {
         Some(())
     }
 
+    /// Marks the short entry and the long filename entries of an entry as
+    /// deleted, without freeing its cluster chain.
+    fn delete_name_entries(&mut self, located: &LocatedDirectoryEntry) -> Option<()> {
+        for lfn_location in &located.lfn_locations {
+            let mut lfn_entry = self.read_directory_entry(*lfn_location)?;
+            lfn_entry.mark_deleted();
+            self.persist_directory_entry(*lfn_location, &lfn_entry)?;
+        }
+
+        let mut deleted_entry = located.entry.clone();
+        deleted_entry.mark_deleted();
+        self.persist_directory_entry(located.location, &deleted_entry)
+    }
+
     fn rollback_allocated_chain(&mut self, cluster: usize) {
         let _ = self.fat.free_chain(cluster);
         let _ = self.persist_fat();
@@ -902,6 +1287,58 @@ impl FATFileSystem {
         Some(region)
     }
 
+    fn read_file_range(
+        &mut self,
+        file: &DirectoryEntry,
+        offset: usize,
+        buffer: &mut [u8],
+    ) -> Option<usize> {
+        if file.attributes != 32 {
+            return None;
+        }
+
+        let filesize = file.size as usize;
+        if offset >= filesize || buffer.is_empty() {
+            return Some(0);
+        }
+
+        let first_cluster = file.get_cluster();
+        if first_cluster == 0 {
+            return None;
+        }
+
+        let size = min(buffer.len(), filesize - offset);
+        let cluster_size = self.cluster_size();
+        let mut cluster = self.cluster_at(first_cluster, offset / cluster_size)?;
+        let mut cluster_offset = offset % cluster_size;
+
+        let mut bytes_read = 0;
+        while bytes_read < size {
+            let region = self.read_cluster(cluster)?;
+            let readable_bytes = min(size - bytes_read, cluster_size - cluster_offset);
+
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    region.get_ptr::<u8>().add(cluster_offset),
+                    buffer.as_mut_ptr().add(bytes_read),
+                    readable_bytes,
+                );
+            }
+
+            Self::deallocate_region(&region);
+
+            bytes_read += readable_bytes;
+            if bytes_read == size {
+                break;
+            }
+
+            cluster = self.fat.next_cluster(cluster)?;
+            cluster_offset = 0;
+        }
+
+        Some(bytes_read)
+    }
+
     fn write_existing_file(
         &mut self,
         file: &DirectoryEntry,
@@ -1351,6 +1788,7 @@ fn read_boot_sector(port: &mut AHCIPort) -> Option<(FatBootSector, Fat32Extended
     let status = port.read(0, 1, buffer);
     if !status {
         log!(crate::io::LogType::FS, "Failed to read FAT32 Boot Sector");
+        unsafe { dealloc(buffer, layout) };
         None
     } else {
         let bs = unsafe { &*(buffer as *const FatBootSector) };
@@ -1370,6 +1808,7 @@ fn read_boot_sector(port: &mut AHCIPort) -> Option<(FatBootSector, Fat32Extended
 
         let bs = unsafe { core::ptr::read(bs) };
         let bs_32 = unsafe { core::ptr::read(bs_32) };
+        unsafe { dealloc(buffer, layout) };
 
         Some((bs, bs_32))
     }
@@ -1397,6 +1836,9 @@ fn read_fat(
 
     unsafe { core::ptr::write_bytes(buffer, 0, fat_size_bytes) }
 
+    // freed again when the read fails or the filesystem is unmounted
+    let fat_buffer = FatBuffer::new(buffer as *mut u32, layout);
+
     log!(
         crate::io::LogType::FS,
         "Reading fat with, start = 0x{:x}, sectors = 0x{:x}, buffer addr: 0x{:X}",
@@ -1410,8 +1852,6 @@ fn read_fat(
         log!(crate::io::LogType::FS, "Failed to read FAT");
         None
     } else {
-        let num_fat_entries = fat_size_bytes / core::mem::size_of::<u32>();
-        let fat_buffer = FatBuffer::new(buffer as *mut u32, num_fat_entries);
         Some(fat_buffer)
     }
 }
@@ -1419,6 +1859,9 @@ fn read_fat(
 struct FatBuffer {
     fat: *mut u32,
     entries: usize,
+
+    /// The layout the table was allocated with.
+    layout: Layout,
 }
 
 // WARNING: We need to implement Send because of the raw FAT pointer...
@@ -1426,10 +1869,17 @@ unsafe impl Send for FatBuffer {}
 unsafe impl Sync for FatBuffer {}
 
 impl FatBuffer {
-    pub fn new(fat_ptr: *mut u32, entries: usize) -> Self {
+    /// Takes over a table allocated on the heap, freeing it once dropped.
+    ///
+    /// ## Arguments
+    ///
+    /// - `fat_ptr` the start of the table
+    /// - `layout` the layout the table was allocated with
+    pub fn new(fat_ptr: *mut u32, layout: Layout) -> Self {
         Self {
             fat: fat_ptr,
-            entries: entries,
+            entries: layout.size() / core::mem::size_of::<u32>(),
+            layout: layout,
         }
     }
 
@@ -1527,6 +1977,32 @@ impl FatBuffer {
         }
     }
 
+    /// Counts the clusters in a chain.
+    ///
+    /// ## Arguments
+    ///
+    /// - `first_cluster` the first cluster of the chain, 0 for an empty chain
+    pub fn chain_length(&self, first_cluster: usize) -> usize {
+        if first_cluster < 2 || first_cluster >= self.entries {
+            return 0;
+        }
+
+        let mut length = 1;
+        let mut cluster = first_cluster;
+
+        // bound the walk so a corrupted, cyclic chain cannot hang the kernel
+        while let Some(next) = self.next_cluster(cluster) {
+            if length >= self.entries {
+                break;
+            }
+
+            length += 1;
+            cluster = next;
+        }
+
+        length
+    }
+
     fn find_free_cluster(&self) -> Option<usize> {
         for cluster in 2..self.entries {
             let entry = unsafe { *self.fat.add(cluster) };
@@ -1538,3 +2014,9 @@ impl FatBuffer {
         None
     }
 }
+
+impl Drop for FatBuffer {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.fat as *mut u8, self.layout) };
+    }
+}
@@ -1,3 +1,5 @@
+use core::any::Any;
+
 use alloc::{
     boxed::Box,
     string::{String, ToString},
@@ -10,10 +12,42 @@ use crate::{mem::Region, print};
 
 pub type DirectoryItems = (Vec<Arc<dyn Directory>>, Vec<Arc<RwLock<dyn File>>>);
 
+/// The kind of filesystem node described by [`Metadata`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+}
+
+/// Filesystem-independent metadata of a file or directory.
+///
+/// Timestamps are seconds since the Unix epoch, or 0 when the filesystem
+/// does not record them.
+#[derive(Clone, Copy, Debug)]
+pub struct Metadata {
+    pub file_type: FileType,
+    pub size: usize,
+
+    /// The raw FAT attribute byte.
+    pub attributes: u8,
+    pub created: u64,
+    pub modified: u64,
+    pub accessed: u64,
+
+    /// The number of data clusters allocated to the node.
+    pub cluster_count: usize,
+}
+
 pub trait Directory: DirectoryClone + Send + Sync {
     fn name(&self) -> String;
     fn list_dir(&self) -> DirectoryItems;
 
+    /// Reads the metadata of this directory.
+    fn metadata(&self) -> Option<Metadata>;
+
+    /// The directory containing this one, or `None` for the root.
+    fn parent(&self) -> Option<Arc<dyn Directory>>;
+
     /// Creates an empty regular file directly inside this directory.
     ///
     /// ## Arguments
@@ -58,6 +92,24 @@ pub trait Directory: DirectoryClone + Send + Sync {
     /// exist, is not a directory, or is not empty.
     fn remove_directory(&self, name: &str) -> Option<()>;
 
+    /// Renames or moves a file or directory directly inside this directory.
+    ///
+    /// ## Arguments
+    ///
+    /// - `name` the current name of the entry
+    /// - `target` the directory to move the entry into, may be `self`
+    /// - `new_name` the name of the entry inside `target`
+    ///
+    /// ## Returns
+    /// `Some(())` when the entry was renamed, or `None` when it does not
+    /// exist, the new name is invalid or taken, `target` belongs to another
+    /// filesystem, or a directory would be moved into itself.
+    fn rename(&self, name: &str, target: &dyn Directory, new_name: &str) -> Option<()>;
+
+    /// Exposes the concrete directory type, so filesystems can recognize
+    /// their own directories passed through the trait.
+    fn as_any(&self) -> &dyn Any;
+
     fn find_directory(&self, name: &str) -> Option<Arc<dyn Directory>> {
         // TODO: Use a method to only list subdirectories, so we save on performance
         // FAT filenames are case-insensitive
@@ -80,6 +132,35 @@ pub trait Directory: DirectoryClone + Send + Sync {
         Some(file.clone())
     }
 
+    /// Reconstructs the absolute path of this directory by following the
+    /// parent links up to the root.
+    fn absolute_path(&self) -> String {
+        let mut names = Vec::new();
+        let mut current = self.parent();
+        if current.is_some() {
+            names.push(self.name());
+        }
+
+        while let Some(directory) = current {
+            current = directory.parent();
+            if current.is_some() {
+                names.push(directory.name());
+            }
+        }
+
+        let mut path = String::new();
+        for name in names.iter().rev() {
+            path.push('/');
+            path.push_str(name);
+        }
+
+        if path.is_empty() {
+            path.push('/');
+        }
+
+        path
+    }
+
     fn find_directory_recursive(&self, path: &str) -> Option<Arc<dyn Directory>> {
         let components = normalize_path_components(path);
         self.find_directory_components(&components)
@@ -136,7 +217,22 @@ impl<'a> Clone for Box<dyn 'a + Directory> {
 pub trait File: FileClone + Send + Sync {
     fn name(&self) -> String;
     fn size(&self) -> usize;
+
+    /// Reads the metadata of this file.
+    fn metadata(&self) -> Option<Metadata>;
     fn read(&self) -> Option<Region>;
+
+    /// Reads part of the file without copying all of it.
+    ///
+    /// ## Arguments
+    ///
+    /// - `offset` the byte offset to start reading at
+    /// - `buffer` the buffer to fill
+    ///
+    /// ## Returns
+    /// The number of bytes read, less than the buffer size when the end of
+    /// the file is reached.
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Option<usize>;
     fn write(&self, offset: usize, bytes: &[u8]) -> Option<usize>;
     fn truncate(&mut self, size: usize) -> Option<()>;
 }
@@ -1,18 +1,34 @@
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
 use fat_fs::FATFileSystem;
 use fs::Directory;
 use spin::Mutex;
+use tmpfs::{TmpFileSystem, TMPFS_MAX_SIZE};
+use vfs::MountedFilesystem;
 
-use crate::{ahci::port::AHCIPort, print};
+use crate::{ahci::port::AHCIPort, io::LogType, log, print};
 
 pub mod fat;
 pub mod fat_fs;
 pub mod fs;
+pub mod tmpfs;
+pub mod vfs;
 
-// Need make sure the FS Arc lives statically so that the
-// weak references inside of individual directories and
-// files don't get invalidated
-static GLOBAL_FS: Mutex<Option<Arc<Mutex<FATFileSystem>>>> = Mutex::new(None);
+/// Filesystem type of FAT volumes, mounted from the disks `disk0`,
+/// `disk1` and so on.
+pub const FAT_FS_TYPE: &str = "fat";
+
+/// Filesystem type of in-memory filesystems, their source is only a label.
+pub const TMPFS_FS_TYPE: &str = "tmpfs";
+
+/// Where a tmpfs is mounted during boot, for scratch files that shouldn't
+/// end up on the disk.
+const TMP_DIRECTORY_NAME: &str = "tmp";
+
+const DISK_SOURCE_PREFIX: &str = "disk";
+
+/// The AHCI ports not mounted right now, by disk index. The FAT
+/// filesystems mounted from them own their ports until unmounted.
+static DISKS: Mutex<Vec<Option<AHCIPort>>> = Mutex::new(Vec::new());
 
 pub static GLOBAL_ROOT_DIR: Mutex<Option<Arc<dyn Directory + Send + Sync>>> = Mutex::new(None);
 
@@ -29,13 +45,145 @@ macro_rules! with_root_dir {
     }};
 }
 
-pub fn init(port: AHCIPort) {
-    let fs = FATFileSystem::new(Box::new(port)).unwrap();
-    let fs_arc = Arc::new(Mutex::new(fs));
-    let root = FATFileSystem::root_dir(fs_arc.clone());
+/// Mounts the FAT volume of the first disk as the root filesystem and a
+/// tmpfs at `/tmp`, the other disks can be mounted later on.
+///
+/// ## Arguments
+///
+/// - `ports` the AHCI ports of all disks
+pub fn init(ports: Vec<AHCIPort>) {
+    let mut ports = ports.into_iter();
+    let port = ports.next().expect("No disk for the root filesystem");
+
+    *DISKS.lock() = core::iter::once(None).chain(ports.map(Some)).collect();
+
+    let Ok(fs) = FATFileSystem::new(Box::new(port)) else {
+        panic!("No FAT volume on the root disk");
+    };
+    let filesystem = MountedFilesystem::Fat {
+        fs: Arc::new(Mutex::new(fs)),
+        disk: 0,
+    };
 
-    *GLOBAL_FS.lock() = Some(fs_arc);
+    let root = mount_root(&filesystem);
+    let root = vfs::mount(String::from("/"), root, filesystem).unwrap();
+
+    if mount(TMPFS_FS_TYPE, TMPFS_FS_TYPE, &root, TMP_DIRECTORY_NAME).is_none() {
+        log!(
+            LogType::ERR,
+            "fs: failed to mount a tmpfs at /{}",
+            TMP_DIRECTORY_NAME
+        );
+    }
 
     let mut guard = GLOBAL_ROOT_DIR.lock();
     *guard = Some(Arc::new(root));
 }
+
+/// Mounts a filesystem on a new entry of a directory, hiding a directory
+/// of that name while it is mounted.
+///
+/// ## Arguments
+///
+/// - `fs_type` the filesystem type, see [`FAT_FS_TYPE`] and [`TMPFS_FS_TYPE`]
+/// - `source` the disk holding the filesystem, like `disk1`, any label for
+///   a tmpfs
+/// - `parent` the directory to mount in
+/// - `name` the name the filesystem is reachable at inside `parent`
+///
+/// ## Returns
+/// `Some(())` when the filesystem was mounted, or `None` when the type or
+/// source is unknown, the source is in use, a file of that name exists or
+/// something is mounted there already.
+pub fn mount(fs_type: &str, source: &str, parent: &dyn Directory, name: &str) -> Option<()> {
+    if parent.find_file(name).is_some() {
+        return None;
+    }
+
+    let path = vfs::child_path(&parent.absolute_path(), name);
+    if vfs::is_mount_point(&path) {
+        return None;
+    }
+
+    let filesystem = match fs_type {
+        FAT_FS_TYPE => {
+            let disk: usize = source.strip_prefix(DISK_SOURCE_PREFIX)?.parse().ok()?;
+            let port = DISKS.lock().get_mut(disk)?.take()?;
+
+            let fs = match FATFileSystem::new(Box::new(port)) {
+                Ok(fs) => fs,
+                Err(port) => {
+                    // the disk can still be mounted once it holds a volume
+                    DISKS.lock()[disk] = Some(*port);
+                    log!(LogType::ERR, "fs: no FAT volume on {}", source);
+                    return None;
+                }
+            };
+
+            MountedFilesystem::Fat {
+                fs: Arc::new(Mutex::new(fs)),
+                disk: disk,
+            }
+        }
+        TMPFS_FS_TYPE => MountedFilesystem::Tmp {
+            fs: Arc::new(Mutex::new(TmpFileSystem::new(TMPFS_MAX_SIZE))),
+        },
+        _ => return None,
+    };
+
+    let root = mount_root(&filesystem);
+    vfs::mount(path.clone(), root, filesystem)?;
+
+    log!(
+        LogType::OK,
+        "fs: mounted {} {} at {}",
+        fs_type,
+        source,
+        path
+    );
+    Some(())
+}
+
+/// Unmounts the filesystem mounted at a path, a FAT volume gives its disk
+/// back to be mounted again and the files of a tmpfs are dropped.
+///
+/// ## Arguments
+///
+/// - `path` the normalized absolute path of the mount point
+///
+/// ## Returns
+/// `Some(())` when the filesystem was unmounted, or `None` when nothing is
+/// mounted there, it is the root filesystem, or it is still in use.
+pub fn umount(path: &str) -> Option<()> {
+    let filesystem = vfs::umount(path)?;
+    let source = match filesystem {
+        MountedFilesystem::Fat { fs, disk } => {
+            // the mount table checked for other references, should one
+            // have shown up since, the filesystem stays mounted
+            let fs = match Arc::try_unwrap(fs) {
+                Ok(fs) => fs,
+                Err(fs) => {
+                    let filesystem = MountedFilesystem::Fat { fs: fs, disk: disk };
+                    let root = mount_root(&filesystem);
+                    vfs::mount(String::from(path), root, filesystem)?;
+                    return None;
+                }
+            };
+
+            let port = fs.into_inner().into_port();
+            DISKS.lock()[disk] = Some(*port);
+            format!("{}{}", DISK_SOURCE_PREFIX, disk)
+        }
+        MountedFilesystem::Tmp { .. } => String::from(TMPFS_FS_TYPE),
+    };
+
+    log!(LogType::OK, "fs: unmounted {} from {}", source, path);
+    Some(())
+}
+
+fn mount_root(filesystem: &MountedFilesystem) -> Arc<dyn Directory> {
+    match filesystem {
+        MountedFilesystem::Fat { fs, .. } => Arc::new(FATFileSystem::root_dir(fs.clone())),
+        MountedFilesystem::Tmp { fs } => Arc::new(TmpFileSystem::root_dir(fs.clone())),
+    }
+}
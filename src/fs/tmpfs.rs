@@ -0,0 +1,534 @@
+use core::{
+    alloc::Layout,
+    any::Any,
+    cmp::min,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use alloc::{
+    alloc::alloc,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use spin::{Mutex, RwLock};
+
+use crate::{mem::Region, time};
+
+use super::fs::{Directory, DirectoryItems, File, FileType, Metadata};
+
+/// Bytes of file data a tmpfs holds at most, all of it lives on the kernel
+/// heap.
+pub const TMPFS_MAX_SIZE: usize = 64 * 1024 * 1024; // 64 MiB
+
+const MAX_NAME_LENGTH: usize = 255;
+
+// FAT attribute bytes reported in the metadata, so tools show files of
+// both filesystems alike
+const ATTRIBUTE_DIRECTORY: u8 = 0x10;
+const ATTRIBUTE_ARCHIVE: u8 = 0x20;
+
+/// A filesystem keeping its files in memory, its contents are gone once
+/// it is unmounted.
+///
+/// Changes to the directory tree lock the filesystem first, then the
+/// directories involved and last the files.
+pub struct TmpFileSystem {
+    root: Arc<RwLock<DirectoryNode>>,
+    capacity: Arc<Capacity>,
+}
+
+impl TmpFileSystem {
+    /// Creates an empty filesystem.
+    ///
+    /// ## Arguments
+    ///
+    /// - `capacity` the number of bytes its files may hold together
+    pub fn new(capacity: usize) -> Self {
+        let root = DirectoryNode::new(String::from("root"), Weak::new());
+        let capacity = Capacity {
+            used: AtomicUsize::new(0),
+            limit: capacity,
+        };
+
+        Self {
+            root: Arc::new(RwLock::new(root)),
+            capacity: Arc::new(capacity),
+        }
+    }
+
+    pub fn root_dir(self_arc: Arc<Mutex<TmpFileSystem>>) -> TmpDirectory {
+        let fs_weak = Arc::downgrade(&self_arc);
+        let root = self_arc.lock().root.clone();
+        TmpDirectory::new(root, fs_weak)
+    }
+}
+
+/// The bytes of file data a tmpfs holds, shared with its files so they
+/// give their space back once the last handle of an unlinked file is gone.
+struct Capacity {
+    used: AtomicUsize,
+    limit: usize,
+}
+
+impl Capacity {
+    /// Accounts for a file changing its size.
+    ///
+    /// ## Returns
+    /// `None` when the files would hold more than the limit together.
+    fn charge(&self, old_size: usize, new_size: usize) -> Option<()> {
+        self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                let used = used.checked_sub(old_size)?.checked_add(new_size)?;
+                (used <= self.limit).then_some(used)
+            })
+            .ok()?;
+
+        Some(())
+    }
+}
+
+struct DirectoryNode {
+    name: String,
+
+    /// The directory holding this one, updated when it is moved. Dangling
+    /// for the root.
+    parent: Weak<RwLock<DirectoryNode>>,
+    directories: Vec<Arc<RwLock<DirectoryNode>>>,
+    files: Vec<Arc<RwLock<FileNode>>>,
+    created: u64,
+    modified: u64,
+}
+
+impl DirectoryNode {
+    fn new(name: String, parent: Weak<RwLock<DirectoryNode>>) -> Self {
+        let now = now();
+
+        Self {
+            name: name,
+            parent: parent,
+            directories: Vec::new(),
+            files: Vec::new(),
+            created: now,
+            modified: now,
+        }
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.directories.iter().any(|d| d.read().name == name)
+            || self.files.iter().any(|f| f.read().name == name)
+    }
+
+    fn directory_index(&self, name: &str) -> Option<usize> {
+        self.directories.iter().position(|d| d.read().name == name)
+    }
+
+    fn file_index(&self, name: &str) -> Option<usize> {
+        self.files.iter().position(|f| f.read().name == name)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.directories.is_empty() && self.files.is_empty()
+    }
+}
+
+/// A file, its data counts towards the capacity until the file is gone,
+/// which for an unlinked file is once its last handle is closed.
+struct FileNode {
+    name: String,
+    bytes: Vec<u8>,
+    capacity: Arc<Capacity>,
+    created: u64,
+    modified: u64,
+}
+
+impl FileNode {
+    fn new(name: String, capacity: Arc<Capacity>) -> Self {
+        let now = now();
+
+        Self {
+            name: name,
+            bytes: Vec::new(),
+            capacity: capacity,
+            created: now,
+            modified: now,
+        }
+    }
+
+    fn resize(&mut self, size: usize) -> Option<()> {
+        let old_size = self.bytes.len();
+        self.capacity.charge(old_size, size)?;
+
+        // the heap may run out before the capacity does
+        if size > old_size && self.bytes.try_reserve_exact(size - old_size).is_err() {
+            self.capacity.charge(size, old_size);
+            return None;
+        }
+
+        self.bytes.resize(size, 0);
+        if size < self.bytes.capacity() / 2 {
+            self.bytes.shrink_to_fit();
+        }
+
+        self.modified = now();
+        Some(())
+    }
+}
+
+impl Drop for FileNode {
+    fn drop(&mut self) {
+        self.capacity.charge(self.bytes.len(), 0);
+    }
+}
+
+#[derive(Clone)]
+pub struct TmpDirectory {
+    node: Arc<RwLock<DirectoryNode>>,
+    fs: Weak<Mutex<TmpFileSystem>>,
+}
+
+impl TmpDirectory {
+    fn new(node: Arc<RwLock<DirectoryNode>>, fs: Weak<Mutex<TmpFileSystem>>) -> Self {
+        Self { node: node, fs: fs }
+    }
+
+    /// Whether this directory is a directory node or lies below it.
+    fn is_inside(&self, node: &Arc<RwLock<DirectoryNode>>) -> bool {
+        let mut current = Some(self.node.clone());
+        while let Some(directory) = current {
+            if Arc::ptr_eq(&directory, node) {
+                return true;
+            }
+
+            current = directory.read().parent.upgrade();
+        }
+
+        false
+    }
+}
+
+impl Directory for TmpDirectory {
+    fn name(&self) -> String {
+        self.node.read().name.clone()
+    }
+
+    fn list_dir(&self) -> DirectoryItems {
+        let node = self.node.read();
+
+        let directories: Vec<Arc<dyn Directory>> = node
+            .directories
+            .iter()
+            .map(|d| {
+                let dir = TmpDirectory::new(d.clone(), self.fs.clone());
+                Arc::new(dir) as Arc<dyn Directory>
+            })
+            .collect();
+
+        let files: Vec<Arc<RwLock<dyn File>>> = node
+            .files
+            .iter()
+            .map(|f| {
+                let file = TmpFile::new(f.clone(), self.fs.clone());
+                Arc::new(RwLock::new(file)) as Arc<RwLock<dyn File>>
+            })
+            .collect();
+
+        (directories, files)
+    }
+
+    fn metadata(&self) -> Option<Metadata> {
+        let node = self.node.read();
+
+        Some(Metadata {
+            file_type: FileType::Directory,
+            size: 0,
+            attributes: ATTRIBUTE_DIRECTORY,
+            created: node.created,
+            modified: node.modified,
+            accessed: node.modified,
+            cluster_count: 0,
+        })
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Directory>> {
+        let parent = self.node.read().parent.upgrade()?;
+        Some(Arc::new(TmpDirectory::new(parent, self.fs.clone())))
+    }
+
+    fn find_directory(&self, name: &str) -> Option<Arc<dyn Directory>> {
+        // names are case-sensitive, unlike on FAT
+        match name {
+            "." => Some(Arc::new(self.clone())),
+            ".." => match self.parent() {
+                Some(parent) => Some(parent),
+                None => Some(Arc::new(self.clone())),
+            },
+            _ => {
+                let node = self.node.read();
+                let directory = node.directories.get(node.directory_index(name)?)?;
+
+                Some(Arc::new(TmpDirectory::new(
+                    directory.clone(),
+                    self.fs.clone(),
+                )))
+            }
+        }
+    }
+
+    fn find_file(&self, name: &str) -> Option<Arc<RwLock<dyn File>>> {
+        let node = self.node.read();
+        let file = node.files.get(node.file_index(name)?)?;
+
+        Some(Arc::new(RwLock::new(TmpFile::new(
+            file.clone(),
+            self.fs.clone(),
+        ))))
+    }
+
+    fn create_file(&self, name: &str) -> Option<Arc<RwLock<dyn File>>> {
+        if !is_valid_name(name) {
+            return None;
+        }
+
+        let fs = self.fs.upgrade()?;
+        let fs_guard = fs.lock();
+        let mut node = self.node.write();
+        if node.contains(name) {
+            return None;
+        }
+
+        let file = FileNode::new(String::from(name), fs_guard.capacity.clone());
+        let file = Arc::new(RwLock::new(file));
+        node.files.push(file.clone());
+        node.modified = now();
+
+        Some(Arc::new(RwLock::new(TmpFile::new(file, self.fs.clone()))))
+    }
+
+    fn create_directory(&self, name: &str) -> Option<()> {
+        if !is_valid_name(name) {
+            return None;
+        }
+
+        let fs = self.fs.upgrade()?;
+        let _fs_guard = fs.lock();
+        let mut node = self.node.write();
+        if node.contains(name) {
+            return None;
+        }
+
+        let directory = DirectoryNode::new(String::from(name), Arc::downgrade(&self.node));
+        node.directories.push(Arc::new(RwLock::new(directory)));
+        node.modified = now();
+
+        Some(())
+    }
+
+    fn unlink_file(&self, name: &str) -> Option<()> {
+        let fs = self.fs.upgrade()?;
+        let _fs_guard = fs.lock();
+        let mut node = self.node.write();
+
+        // open descriptors keep the data, it is freed with the last one
+        let index = node.file_index(name)?;
+        node.files.remove(index);
+        node.modified = now();
+
+        Some(())
+    }
+
+    fn remove_directory(&self, name: &str) -> Option<()> {
+        let fs = self.fs.upgrade()?;
+        let _fs_guard = fs.lock();
+        let mut node = self.node.write();
+
+        let index = node.directory_index(name)?;
+        if !node.directories[index].read().is_empty() {
+            return None;
+        }
+
+        node.directories.remove(index);
+        node.modified = now();
+
+        Some(())
+    }
+
+    fn rename(&self, name: &str, target: &dyn Directory, new_name: &str) -> Option<()> {
+        let target = target.as_any().downcast_ref::<TmpDirectory>()?;
+        if !self.fs.ptr_eq(&target.fs) || !is_valid_name(new_name) {
+            return None;
+        }
+
+        // only one change to the tree at a time, so both directories can be
+        // locked in any order
+        let fs = self.fs.upgrade()?;
+        let _fs_guard = fs.lock();
+
+        if Arc::ptr_eq(&self.node, &target.node) {
+            let mut node = self.node.write();
+            if name == new_name {
+                return node.contains(name).then_some(());
+            }
+
+            if node.contains(new_name) {
+                return None;
+            }
+
+            if let Some(index) = node.directory_index(name) {
+                node.directories[index].write().name = String::from(new_name);
+            } else {
+                let index = node.file_index(name)?;
+                node.files[index].write().name = String::from(new_name);
+            }
+
+            node.modified = now();
+            return Some(());
+        }
+
+        // a directory can't be moved into itself, the walk reads the
+        // ancestors of the target so it runs before they are locked
+        let moved = {
+            let node = self.node.read();
+            let index = node.directory_index(name);
+            index.map(|index| node.directories[index].clone())
+        };
+
+        if moved.is_some_and(|directory| target.is_inside(&directory)) {
+            return None;
+        }
+
+        let mut node = self.node.write();
+        let mut target_node = target.node.write();
+        if target_node.contains(new_name) {
+            return None;
+        }
+
+        if let Some(index) = node.directory_index(name) {
+            let directory = node.directories.remove(index);
+            {
+                let mut directory = directory.write();
+                directory.name = String::from(new_name);
+                directory.parent = Arc::downgrade(&target.node);
+            }
+
+            target_node.directories.push(directory);
+        } else {
+            let index = node.file_index(name)?;
+            let file = node.files.remove(index);
+            file.write().name = String::from(new_name);
+            target_node.files.push(file);
+        }
+
+        let now = now();
+        node.modified = now;
+        target_node.modified = now;
+
+        Some(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[derive(Clone)]
+pub struct TmpFile {
+    node: Arc<RwLock<FileNode>>,
+
+    /// Keeps the filesystem from being unmounted while the file is open.
+    fs: Weak<Mutex<TmpFileSystem>>,
+}
+
+impl TmpFile {
+    fn new(node: Arc<RwLock<FileNode>>, fs: Weak<Mutex<TmpFileSystem>>) -> Self {
+        Self { node: node, fs: fs }
+    }
+}
+
+impl File for TmpFile {
+    fn name(&self) -> String {
+        self.node.read().name.clone()
+    }
+
+    fn size(&self) -> usize {
+        self.node.read().bytes.len()
+    }
+
+    fn metadata(&self) -> Option<Metadata> {
+        let node = self.node.read();
+
+        Some(Metadata {
+            file_type: FileType::Regular,
+            size: node.bytes.len(),
+            attributes: ATTRIBUTE_ARCHIVE,
+            created: node.created,
+            modified: node.modified,
+            accessed: node.modified,
+            cluster_count: 0,
+        })
+    }
+
+    fn read(&self) -> Option<Region> {
+        let node = self.node.read();
+        let size = node.bytes.len();
+        if size == 0 {
+            return Some(Region::new(0, 0));
+        }
+
+        // freed by the caller like the regions read from FAT
+        let layout = Layout::array::<u8>(size).ok()?;
+        let buffer = unsafe { alloc(layout) };
+        if buffer.is_null() {
+            return None;
+        }
+
+        unsafe { core::ptr::copy_nonoverlapping(node.bytes.as_ptr(), buffer, size) };
+        Some(Region::new(buffer as usize, size))
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Option<usize> {
+        let node = self.node.read();
+        if offset >= node.bytes.len() {
+            return Some(0);
+        }
+
+        let count = min(buffer.len(), node.bytes.len() - offset);
+        buffer[..count].copy_from_slice(&node.bytes[offset..offset + count]);
+
+        Some(count)
+    }
+
+    fn write(&self, offset: usize, bytes: &[u8]) -> Option<usize> {
+        let end = offset.checked_add(bytes.len())?;
+
+        let fs = self.fs.upgrade()?;
+        let _fs_guard = fs.lock();
+        let mut node = self.node.write();
+        if end > node.bytes.len() {
+            node.resize(end)?;
+        }
+
+        node.bytes[offset..end].copy_from_slice(bytes);
+        node.modified = now();
+
+        Some(bytes.len())
+    }
+
+    fn truncate(&mut self, size: usize) -> Option<()> {
+        let fs = self.fs.upgrade()?;
+        let _fs_guard = fs.lock();
+        self.node.write().resize(size)
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name != "."
+        && name != ".."
+        && !name.contains(['/', '\0'])
+}
+
+fn now() -> u64 {
+    time::realtime_timespec().tv_sec as u64
+}
@@ -0,0 +1,378 @@
+use core::any::Any;
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use spin::{Mutex, RwLock};
+
+use super::{
+    fat_fs::FATFileSystem,
+    fs::{Directory, DirectoryItems, File, Metadata},
+    tmpfs::TmpFileSystem,
+};
+
+/// The filesystems mounted into the directory tree, by absolute path. The
+/// root filesystem is mounted at `/`.
+static MOUNTS: RwLock<Vec<Mount>> = RwLock::new(Vec::new());
+
+struct Mount {
+    path: String,
+    root: Arc<dyn Directory>,
+    filesystem: MountedFilesystem,
+}
+
+/// What keeps a mounted filesystem alive, handed back once it is unmounted.
+pub enum MountedFilesystem {
+    /// A FAT volume on the disk with the given index.
+    Fat {
+        fs: Arc<Mutex<FATFileSystem>>,
+        disk: usize,
+    },
+
+    /// A tmpfs, its files are dropped once it is unmounted.
+    Tmp { fs: Arc<Mutex<TmpFileSystem>> },
+}
+
+impl MountedFilesystem {
+    /// Whether files or directories of the filesystem are still referenced
+    /// outside of the mount table.
+    fn is_busy(&self) -> bool {
+        let (strong, weak) = match self {
+            MountedFilesystem::Fat { fs, .. } => (Arc::strong_count(fs), Arc::weak_count(fs)),
+            MountedFilesystem::Tmp { fs } => (Arc::strong_count(fs), Arc::weak_count(fs)),
+        };
+
+        // every directory and file handed out holds a weak reference, the
+        // root directory in the mount table is the only one left
+        strong > 1 || weak > 1
+    }
+}
+
+/// Adds a filesystem to the mount table.
+///
+/// ## Arguments
+///
+/// - `path` the normalized absolute path to mount at
+/// - `root` the root directory of the filesystem
+/// - `filesystem` what keeps the filesystem alive while it is mounted
+///
+/// ## Returns
+/// The directory the filesystem is reachable at, or `None` when something
+/// is mounted at the path already.
+pub fn mount(
+    path: String,
+    root: Arc<dyn Directory>,
+    filesystem: MountedFilesystem,
+) -> Option<VfsDirectory> {
+    let mut mounts = MOUNTS.write();
+    if mounts.iter().any(|mount| mount.path == path) {
+        return None;
+    }
+
+    let directory = VfsDirectory {
+        inner: root.clone(),
+        mount: path.clone(),
+        is_root: true,
+    };
+
+    mounts.push(Mount {
+        path: path,
+        root: root,
+        filesystem: filesystem,
+    });
+
+    Some(directory)
+}
+
+/// Removes a filesystem from the mount table. The root filesystem, busy
+/// filesystems and those with other filesystems mounted inside stay.
+///
+/// ## Arguments
+///
+/// - `path` the normalized absolute path the filesystem is mounted at
+///
+/// ## Returns
+/// The unmounted filesystem, or `None` when it can't be unmounted.
+pub fn umount(path: &str) -> Option<MountedFilesystem> {
+    if path == "/" {
+        return None;
+    }
+
+    let mut mounts = MOUNTS.write();
+    if mounts.iter().any(|mount| is_below(&mount.path, path)) {
+        return None;
+    }
+
+    let index = mounts.iter().position(|mount| mount.path == path)?;
+    let mount = &mounts[index];
+    if Arc::strong_count(&mount.root) > 1 || mount.filesystem.is_busy() {
+        return None;
+    }
+
+    Some(mounts.remove(index).filesystem)
+}
+
+/// Joins a directory path and the name of an entry inside it.
+///
+/// ## Arguments
+///
+/// - `parent` the absolute path of the directory
+/// - `name` the name of the entry
+pub fn child_path(parent: &str, name: &str) -> String {
+    let mut path = parent.to_string();
+    if !path.ends_with('/') {
+        path.push('/');
+    }
+
+    path.push_str(name);
+    path
+}
+
+/// Whether a filesystem is mounted at a path, or anywhere below it, so the
+/// directory can't be removed or renamed.
+///
+/// ## Arguments
+///
+/// - `path` the absolute path of the directory
+pub fn is_mount_point(path: &str) -> bool {
+    MOUNTS
+        .read()
+        .iter()
+        .any(|mount| mount.path == path || is_below(&mount.path, path))
+}
+
+/// Looks up a directory by its normalized absolute path, starting at the
+/// root filesystem.
+fn lookup(path: &str) -> Option<Arc<dyn Directory>> {
+    let root = mounted_root("/")?;
+    let mut directory: Arc<dyn Directory> = Arc::new(VfsDirectory {
+        inner: root,
+        mount: String::from("/"),
+        is_root: true,
+    });
+
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        directory = directory.find_directory(name)?;
+    }
+
+    Some(directory)
+}
+
+fn mounted_root(path: &str) -> Option<Arc<dyn Directory>> {
+    let mounts = MOUNTS.read();
+    let mount = mounts.iter().find(|mount| mount.path == path)?;
+
+    Some(mount.root.clone())
+}
+
+/// The filesystems mounted right inside a directory, by entry name.
+fn mounts_inside(path: &str) -> Vec<(String, Arc<dyn Directory>)> {
+    MOUNTS
+        .read()
+        .iter()
+        .filter_map(|mount| {
+            let name = mount.path.rsplit_once('/')?.1;
+            let parent = &mount.path[..mount.path.len() - name.len()];
+            let parent = parent.strip_suffix('/').filter(|p| !p.is_empty());
+
+            (parent.unwrap_or("/") == path && !name.is_empty())
+                .then(|| (name.to_string(), mount.root.clone()))
+        })
+        .collect()
+}
+
+fn is_below(path: &str, ancestor: &str) -> bool {
+    let Some(rest) = path.strip_prefix(ancestor) else {
+        return false;
+    };
+
+    ancestor == "/" && !rest.is_empty() || rest.starts_with('/')
+}
+
+/// A directory of the tree all filesystems are mounted into. Looking up
+/// an entry that has a filesystem mounted on it gives its root directory,
+/// everything else is passed on to the directory of the filesystem.
+///
+/// The path of a directory is asked from its filesystem every time, so it
+/// stays right when a rename moves the directory or one above it.
+#[derive(Clone)]
+pub struct VfsDirectory {
+    inner: Arc<dyn Directory>,
+
+    /// The normalized absolute path its filesystem is mounted at.
+    mount: String,
+
+    /// Whether the directory is the root of its filesystem.
+    is_root: bool,
+}
+
+impl VfsDirectory {
+    fn child(&self, inner: Arc<dyn Directory>) -> VfsDirectory {
+        VfsDirectory {
+            inner: inner,
+            mount: self.mount.clone(),
+            is_root: false,
+        }
+    }
+
+    fn mounted_child(&self, name: &str, root: Arc<dyn Directory>) -> VfsDirectory {
+        VfsDirectory {
+            inner: root,
+            mount: child_path(&self.path(), name),
+            is_root: true,
+        }
+    }
+
+    /// The normalized absolute path of the directory.
+    fn path(&self) -> String {
+        if self.is_root {
+            return self.mount.clone();
+        }
+
+        let path = self.inner.absolute_path();
+        if path == "/" {
+            return self.mount.clone();
+        }
+
+        if self.mount == "/" {
+            return path;
+        }
+
+        let mut full_path = self.mount.clone();
+        full_path.push_str(&path);
+        full_path
+    }
+
+    fn is_mount_point(&self, name: &str) -> bool {
+        is_mount_point(&child_path(&self.path(), name))
+    }
+}
+
+impl Directory for VfsDirectory {
+    fn name(&self) -> String {
+        if !self.is_root {
+            return self.inner.name();
+        }
+
+        match self.mount.rsplit_once('/') {
+            Some((_, name)) if !name.is_empty() => name.to_string(),
+            _ => self.inner.name(),
+        }
+    }
+
+    fn list_dir(&self) -> DirectoryItems {
+        let (directories, files) = self.inner.list_dir();
+        let mounts = mounts_inside(&self.path());
+
+        // mounted filesystems hide the directories they are mounted on
+        let mut entries: Vec<Arc<dyn Directory>> = directories
+            .into_iter()
+            .filter(|directory| {
+                let name = directory.name();
+                !mounts.iter().any(|(mount_name, _)| *mount_name == name)
+            })
+            .map(|directory| Arc::new(self.child(directory)) as Arc<dyn Directory>)
+            .collect();
+
+        for (name, root) in mounts {
+            entries.push(Arc::new(self.mounted_child(&name, root)));
+        }
+
+        (entries, files)
+    }
+
+    fn metadata(&self) -> Option<Metadata> {
+        self.inner.metadata()
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Directory>> {
+        if !self.is_root {
+            let parent = self.inner.parent()?;
+            let is_root = parent.absolute_path() == "/";
+
+            return Some(Arc::new(VfsDirectory {
+                inner: parent,
+                mount: self.mount.clone(),
+                is_root: is_root,
+            }));
+        }
+
+        // the parent of a mount root is the directory it is mounted in
+        if self.mount == "/" {
+            return None;
+        }
+
+        let (parent, _) = self.mount.rsplit_once('/')?;
+        lookup(parent)
+    }
+
+    fn create_file(&self, name: &str) -> Option<Arc<RwLock<dyn File>>> {
+        if self.is_mount_point(name) {
+            return None;
+        }
+
+        self.inner.create_file(name)
+    }
+
+    fn create_directory(&self, name: &str) -> Option<()> {
+        if self.is_mount_point(name) {
+            return None;
+        }
+
+        self.inner.create_directory(name)
+    }
+
+    fn unlink_file(&self, name: &str) -> Option<()> {
+        self.inner.unlink_file(name)
+    }
+
+    fn remove_directory(&self, name: &str) -> Option<()> {
+        if self.is_mount_point(name) {
+            return None;
+        }
+
+        self.inner.remove_directory(name)
+    }
+
+    fn rename(&self, name: &str, target: &dyn Directory, new_name: &str) -> Option<()> {
+        let target_path = child_path(&target.absolute_path(), new_name);
+        if self.is_mount_point(name) || is_mount_point(&target_path) {
+            return None;
+        }
+
+        self.inner.rename(name, target, new_name)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        // lets filesystems recognize their own directories behind the VFS
+        self.inner.as_any()
+    }
+
+    fn find_directory(&self, name: &str) -> Option<Arc<dyn Directory>> {
+        match name {
+            "." => Some(Arc::new(self.clone())),
+            ".." => match self.parent() {
+                Some(parent) => Some(parent),
+                None => Some(Arc::new(self.clone())),
+            },
+            _ => {
+                if let Some(root) = mounted_root(&child_path(&self.path(), name)) {
+                    return Some(Arc::new(self.mounted_child(name, root)));
+                }
+
+                let directory = self.inner.find_directory(name)?;
+                Some(Arc::new(self.child(directory)))
+            }
+        }
+    }
+
+    fn find_file(&self, name: &str) -> Option<Arc<RwLock<dyn File>>> {
+        self.inner.find_file(name)
+    }
+
+    fn absolute_path(&self) -> String {
+        self.path()
+    }
+}
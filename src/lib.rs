@@ -15,9 +15,6 @@ extern crate spinning_top;
 #[macro_use]
 extern crate bitflags;
 
-#[macro_use]
-extern crate lazy_static;
-
 mod ahci;
 mod arch;
 mod elf;
@@ -33,10 +30,11 @@ mod utils;
 
 use ahci::init_ahci;
 use arch::x86_64::acpi::pci::PciDeviceClass;
+use arch::x86_64::user_access;
 use core::panic::PanicInfo;
 use io::serial::serial_init;
-use mem::heap::LinkedListHeap;
-use x86_64::registers::control::{Cr0, Cr0Flags};
+use mem::heap::SlabHeap;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
 use x86_64::registers::model_specific::{Efer, EferFlags};
 
 use crate::io::{print, LogType};
@@ -44,44 +42,29 @@ use crate::mem::heap;
 use crate::utils::safe;
 
 #[global_allocator]
-static mut HEAP_ALLOCATOR: safe::Safe<LinkedListHeap> = safe::Safe::new(LinkedListHeap::empty());
+static mut HEAP_ALLOCATOR: safe::Safe<SlabHeap> = safe::Safe::new(SlabHeap::empty());
 
 #[no_mangle]
 pub extern "C" fn rust_main(boot_info_addr: usize) {
-    let boot_info_load_res = unsafe {
-        multiboot2::BootInformation::load(
-            boot_info_addr as *const multiboot2::BootInformationHeader,
-        )
-    };
-
-    let boot_info = match boot_info_load_res {
-        Ok(info) => {
-            log!(
-                LogType::OK,
-                "Successfully loaded boot info at addr: 0x{:x}",
-                boot_info_addr
-            );
-
-            info
-        }
-        Err(e) => {
-            log!(
-                LogType::ERR,
-                "Couldn't load boot info at addr: 0x{:x}\nErr: {:?}",
-                boot_info_addr,
-                e
-            );
-
-            return;
-        }
+    let Some(boot_info) = load_boot_info(boot_info_addr) else {
+        return;
     };
 
     serial_init();
 
     enable_nxe_bit();
     enable_write_protect_bit();
+    enable_smep_smap_bits();
 
+    mem::aslr::init(&boot_info);
     mem::init(&boot_info);
+    mem::pcid::init();
+
+    // the boot page tables are gone, the boot information
+    // is only reachable through the direct map now
+    let Some(boot_info) = load_boot_info(mem::phys_to_virt(boot_info_addr)) else {
+        return;
+    };
 
     unsafe {
         heap::init_heap();
@@ -94,6 +77,8 @@ pub extern "C" fn rust_main(boot_info_addr: usize) {
     arch::x86_64::gdt::init_gdt();
     log!(LogType::OK, "Initialized kernel GDT");
 
+    mem::kpti::init(&boot_info);
+
     x86_64::instructions::interrupts::disable();
 
     arch::x86_64::idt::remap_pic();
@@ -118,9 +103,15 @@ pub extern "C" fn rust_main(boot_info_addr: usize) {
     let devices = arch::x86_64::acpi::init_acpi(&boot_info);
     let sata_controller = devices.get_device(PciDeviceClass::SATAController).unwrap();
 
-    let mut ports = init_ahci(sata_controller);
-    let port = ports.remove(0);
-    fs::init(port);
+    let ports = init_ahci(sata_controller);
+    fs::init(ports);
+
+    with_root_dir!(root, {
+        match root.find_file(mem::swap::SWAP_FILE_NAME) {
+            Some(file) => mem::swap::init(file),
+            None => log!(LogType::MEM, "No swap file found, swapping is disabled"),
+        }
+    });
 
     /*
     let ethernet = devices
@@ -159,7 +150,12 @@ pub extern "C" fn rust_main(boot_info_addr: usize) {
 
     log!(LogType::OK, "Read Shell ELF binary");
 
-    let shell_entry = elf::load(shell_binary, &["shell"]).unwrap();
+    let shell_entry = elf::load(
+        shell_binary,
+        &["shell"],
+        scheduling::process::DEFAULT_ENVIRONMENT,
+    )
+    .unwrap();
 
     x86_64::instructions::interrupts::enable();
     scheduling::deploy(shell_entry, false);
@@ -169,6 +165,39 @@ pub extern "C" fn rust_main(boot_info_addr: usize) {
     loop {}
 }
 
+/// Loads the multiboot information.
+///
+/// ## Arguments
+///
+/// - `addr` the address the multiboot information is reachable at
+fn load_boot_info(addr: usize) -> Option<multiboot2::BootInformation<'static>> {
+    let boot_info_load_res = unsafe {
+        multiboot2::BootInformation::load(addr as *const multiboot2::BootInformationHeader)
+    };
+
+    match boot_info_load_res {
+        Ok(info) => {
+            log!(
+                LogType::OK,
+                "Successfully loaded boot info at addr: 0x{:x}",
+                addr
+            );
+
+            Some(info)
+        }
+        Err(e) => {
+            log!(
+                LogType::ERR,
+                "Couldn't load boot info at addr: 0x{:x}\nErr: {:?}",
+                addr,
+                e
+            );
+
+            None
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn rust_main_test(boot_info_addr: usize) {
     test::run_tests(boot_info_addr);
@@ -213,3 +242,34 @@ fn enable_write_protect_bit() {
         Cr0::write(write_protect);
     }
 }
+
+fn enable_smep_smap_bits() {
+    // ring 0 faults when executing user pages (SMEP),
+    // or touching them outside of the user access
+    // windows opened by the copy helpers (SMAP).
+
+    let (smep, smap) = user_access::supported();
+    let mut flags = Cr4::read();
+    if smep {
+        flags |= Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION;
+    }
+
+    if smap {
+        flags |= Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION;
+    }
+
+    unsafe {
+        Cr4::write(flags);
+    }
+
+    if smap {
+        user_access::set_smap_enabled();
+    }
+
+    log!(
+        LogType::OK,
+        "Supervisor mode protection, SMEP: {}, SMAP: {}",
+        smep,
+        smap
+    );
+}
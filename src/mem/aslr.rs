@@ -0,0 +1,76 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use multiboot2::BootInformation;
+
+use crate::{arch::x86_64::random, io::LogType, log};
+
+use super::PAGE_SIZE;
+
+/// Kernel command line option that turns address randomization off,
+/// so addresses stay the same between boots while debugging.
+pub const ASLR_DISABLE_OPTION: &str = "noaslr";
+
+static ASLR_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Seeds the random generator and reads the ASLR boot option, must run
+/// before the kernel heap is placed.
+///
+/// ## Arguments
+///
+/// - `boot_info` the multiboot information holding the command line
+pub fn init(boot_info: &BootInformation) {
+    let disabled = boot_info
+        .command_line_tag()
+        .and_then(|tag| tag.cmdline().ok())
+        .is_some_and(|cmdline| {
+            cmdline
+                .split_ascii_whitespace()
+                .any(|option| option == ASLR_DISABLE_OPTION)
+        });
+
+    if disabled {
+        ASLR_ENABLED.store(false, Ordering::SeqCst);
+        log!(LogType::MEM, "ASLR disabled by the boot command line");
+        return;
+    }
+
+    random::init();
+    let source = if random::has_rdrand() {
+        "RDRAND"
+    } else {
+        "RDTSC"
+    };
+
+    log!(LogType::OK, "ASLR enabled, seeded from {}", source);
+}
+
+/// Whether addresses are randomized.
+pub fn is_enabled() -> bool {
+    ASLR_ENABLED.load(Ordering::SeqCst)
+}
+
+/// A random number below `limit`, or 0 when ASLR is disabled.
+///
+/// ## Arguments
+///
+/// - `limit` the exclusive upper bound
+pub fn random_below(limit: usize) -> usize {
+    if !is_enabled() || limit == 0 {
+        return 0;
+    }
+
+    (random::next_u64() % limit as u64) as usize
+}
+
+/// A random page-aligned offset to slide an area by.
+///
+/// ## Arguments
+///
+/// - `max_pages` the number of possible slides, the offset stays below
+///   `max_pages` pages
+///
+/// ## Returns
+/// The offset in bytes, 0 when ASLR is disabled.
+pub fn random_page_offset(max_pages: usize) -> usize {
+    random_below(max_pages) * PAGE_SIZE
+}
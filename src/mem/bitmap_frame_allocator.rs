@@ -0,0 +1,396 @@
+use core::{
+    ptr::addr_of_mut,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use multiboot2::{BootInformation, MemoryAreaType};
+
+use super::{kernel_phys_addr, PageFrame, PageFrameAllocator, PAGE_SIZE};
+
+/// Highest physical address the allocator can track, memory above it is
+/// left unused.
+const MAX_PHYSICAL_MEMORY: usize = 16 * 1024 * 1024 * 1024; // 16 GiB
+
+/// One bit per 4 KiB frame.
+const BITMAP_WORDS: usize = MAX_PHYSICAL_MEMORY / 4096 / 64;
+
+/// Frames below 1 MiB hold the BIOS data area, the VGA buffer and option
+/// ROMs, so they are never handed out.
+const LOW_MEMORY_END: usize = 0x10_0000;
+
+const MAX_USABLE_AREAS: usize = 32;
+const MAX_RESERVED_RANGES: usize = 16;
+
+static mut FRAME_BITMAP: [u64; BITMAP_WORDS] = [0; BITMAP_WORDS];
+static FRAME_BITMAP_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Hands out the static frame bitmap storage, at most once.
+pub fn take_frame_bitmap() -> Option<&'static mut [u64]> {
+    if FRAME_BITMAP_TAKEN.swap(true, Ordering::SeqCst) {
+        return None;
+    }
+
+    let bitmap = addr_of_mut!(FRAME_BITMAP) as *mut u64;
+    Some(unsafe { core::slice::from_raw_parts_mut(bitmap, BITMAP_WORDS) })
+}
+
+/// Half-open range of frame numbers.
+#[derive(Clone, Copy, Debug)]
+struct FrameRange {
+    start: usize,
+    end: usize,
+}
+
+impl FrameRange {
+    const fn empty() -> Self {
+        Self { start: 0, end: 0 }
+    }
+
+    fn contains(&self, frame_number: usize) -> bool {
+        self.start <= frame_number && frame_number < self.end
+    }
+}
+
+/// Physical frame allocator backed by a bitmap over the multiboot
+/// memory map.
+///
+/// A set bit marks a frame as used or unavailable. Only frames fully
+/// inside an available memory area, and outside of every reserved range,
+/// are ever handed out.
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+
+    /// Number of frames covered by the bitmap, starting at frame 0.
+    frame_count: usize,
+    usable_areas: [FrameRange; MAX_USABLE_AREAS],
+    usable_area_count: usize,
+    reserved: [FrameRange; MAX_RESERVED_RANGES],
+    reserved_count: usize,
+    total_frames: usize,
+    free_frames: usize,
+
+    /// Word index the next single frame search starts at.
+    next_word: usize,
+}
+
+impl BitmapFrameAllocator {
+    /// Creates an allocator with every frame marked as unavailable.
+    ///
+    /// ## Arguments
+    ///
+    /// - `bitmap` the storage for the frame bitmap
+    pub fn new(bitmap: &'static mut [u64]) -> Self {
+        bitmap.fill(u64::MAX);
+
+        Self {
+            bitmap: bitmap,
+            frame_count: 0,
+            usable_areas: [FrameRange::empty(); MAX_USABLE_AREAS],
+            usable_area_count: 0,
+            reserved: [FrameRange::empty(); MAX_RESERVED_RANGES],
+            reserved_count: 0,
+            total_frames: 0,
+            free_frames: 0,
+            next_word: 0,
+        }
+    }
+
+    /// Creates an allocator from the multiboot memory map, excluding the
+    /// kernel image, the multiboot information and boot modules.
+    ///
+    /// ## Arguments
+    ///
+    /// - `boot_info` the multiboot boot information
+    /// - `bitmap` the storage for the frame bitmap
+    pub fn from_boot_info(boot_info: &BootInformation, bitmap: &'static mut [u64]) -> Self {
+        let mut allocator = Self::new(bitmap);
+
+        let elf_sections = boot_info.elf_sections_tag().unwrap();
+        for section in elf_sections.sections().filter(|s| s.is_allocated()) {
+            allocator.reserve(
+                kernel_phys_addr(section.start_address() as usize),
+                kernel_phys_addr(section.end_address() as usize),
+            );
+        }
+
+        // the frame right after the multiboot information has always
+        // been skipped during boot, keep it out of the pool
+        let multiboot_start = boot_info.start_address();
+        let multiboot_end = boot_info.end_address() + PAGE_SIZE;
+        allocator.reserve(multiboot_start, multiboot_end);
+
+        for module in boot_info.module_tags() {
+            allocator.reserve(
+                module.start_address() as usize,
+                module.end_address() as usize,
+            );
+        }
+
+        let map_tag = boot_info.memory_map_tag().unwrap();
+        for area in map_tag.memory_areas() {
+            if MemoryAreaType::from(area.typ()) != MemoryAreaType::Available {
+                continue;
+            }
+
+            allocator.add_area(area.start_address() as usize, area.end_address() as usize);
+        }
+
+        allocator
+    }
+
+    /// Excludes a physical address range from allocation, must be called
+    /// before the areas overlapping it are added. Ranges that overlap or
+    /// touch an already reserved one are merged into it.
+    ///
+    /// ## Arguments
+    ///
+    /// - `start` the first address of the range
+    /// - `end` the address right after the range
+    pub fn reserve(&mut self, start: usize, end: usize) {
+        if end <= start {
+            return;
+        }
+
+        let mut range = FrameRange {
+            start: start / PAGE_SIZE,
+            end: end.div_ceil(PAGE_SIZE),
+        };
+
+        // merging may make the range touch another one, so look again
+        while let Some(index) = self.reserved[..self.reserved_count]
+            .iter()
+            .position(|other| other.start <= range.end && range.start <= other.end)
+        {
+            let other = self.reserved[index];
+            range.start = range.start.min(other.start);
+            range.end = range.end.max(other.end);
+
+            self.reserved_count -= 1;
+            self.reserved[index] = self.reserved[self.reserved_count];
+        }
+
+        // handing out a reserved frame would corrupt the kernel or modules
+        if self.reserved_count == MAX_RESERVED_RANGES {
+            panic!("Too many reserved physical memory ranges");
+        }
+
+        self.reserved[self.reserved_count] = range;
+        self.reserved_count += 1;
+    }
+
+    /// Makes the whole frames inside a usable memory area available.
+    ///
+    /// ## Arguments
+    ///
+    /// - `start` the first address of the area
+    /// - `end` the address right after the area
+    pub fn add_area(&mut self, start: usize, end: usize) {
+        let start = start.max(LOW_MEMORY_END).div_ceil(PAGE_SIZE);
+        let end = (end / PAGE_SIZE).min(self.bitmap.len() * 64);
+        if end <= start || self.usable_area_count == MAX_USABLE_AREAS {
+            return;
+        }
+
+        let area = FrameRange {
+            start: start,
+            end: end,
+        };
+
+        self.usable_areas[self.usable_area_count] = area;
+        self.usable_area_count += 1;
+        self.frame_count = self.frame_count.max(end);
+
+        for frame_number in start..end {
+            if self.is_reserved(frame_number) || !self.is_used(frame_number) {
+                continue;
+            }
+
+            self.set_used(frame_number, false);
+            self.total_frames += 1;
+            self.free_frames += 1;
+        }
+    }
+
+    /// Number of frames that can be handed out in total.
+    pub fn total_count(&self) -> usize {
+        self.total_frames
+    }
+
+    /// Number of frames that are currently free.
+    pub fn free_count(&self) -> usize {
+        self.free_frames
+    }
+
+    /// Number of frames that are currently allocated.
+    pub fn used_count(&self) -> usize {
+        self.total_frames - self.free_frames
+    }
+
+    /// Allocates physically contiguous frames, as needed by DMA buffers.
+    ///
+    /// ## Arguments
+    ///
+    /// - `count` the number of frames
+    ///
+    /// ## Returns
+    /// The first frame of the run, or `None` when no run is long enough.
+    pub fn falloc_contiguous(&mut self, count: usize) -> Option<PageFrame> {
+        self.falloc_contiguous_aligned(count, 1)
+    }
+
+    /// Allocates physically contiguous frames starting at an aligned frame
+    /// number, as needed by huge pages.
+    ///
+    /// ## Arguments
+    ///
+    /// - `count` the number of frames
+    /// - `align` the alignment of the first frame number, in frames
+    ///
+    /// ## Returns
+    /// The first frame of the run, or `None` when no aligned run is long
+    /// enough.
+    pub fn falloc_contiguous_aligned(&mut self, count: usize, align: usize) -> Option<PageFrame> {
+        if count == 0 || align == 0 || count > self.free_frames {
+            return None;
+        }
+
+        let mut run_start = 0;
+        let mut run_length = 0;
+
+        for frame_number in 0..self.frame_count {
+            if self.is_used(frame_number) {
+                run_length = 0;
+                continue;
+            }
+
+            if run_length == 0 {
+                if frame_number % align != 0 {
+                    continue;
+                }
+
+                run_start = frame_number;
+            }
+
+            run_length += 1;
+            if run_length == count {
+                for frame_number in run_start..run_start + count {
+                    self.set_used(frame_number, true);
+                }
+
+                self.free_frames -= count;
+                return Some(PageFrame {
+                    frame_number: run_start,
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Frees frames allocated by [`Self::falloc_contiguous`].
+    ///
+    /// ## Arguments
+    ///
+    /// - `start` the first frame of the run
+    /// - `count` the number of frames
+    pub fn free_contiguous(&mut self, start: PageFrame, count: usize) {
+        for frame_number in start.frame_number..start.frame_number + count {
+            self.free(PageFrame {
+                frame_number: frame_number,
+            });
+        }
+    }
+
+    /// Whether a frame was handed out by this allocator and not freed yet.
+    ///
+    /// ## Arguments
+    ///
+    /// - `frame` the frame to check
+    pub fn is_allocated(&self, frame: &PageFrame) -> bool {
+        let frame_number = frame.frame_number;
+        frame_number < self.frame_count
+            && self.is_allocatable(frame_number)
+            && self.is_used(frame_number)
+    }
+
+    fn is_allocatable(&self, frame_number: usize) -> bool {
+        let usable = self.usable_areas[..self.usable_area_count]
+            .iter()
+            .any(|area| area.contains(frame_number));
+
+        usable && !self.is_reserved(frame_number)
+    }
+
+    fn is_reserved(&self, frame_number: usize) -> bool {
+        self.reserved[..self.reserved_count]
+            .iter()
+            .any(|range| range.contains(frame_number))
+    }
+
+    fn is_used(&self, frame_number: usize) -> bool {
+        self.bitmap[frame_number / 64] & (1 << (frame_number % 64)) != 0
+    }
+
+    fn set_used(&mut self, frame_number: usize, used: bool) {
+        let word = &mut self.bitmap[frame_number / 64];
+        let bit = 1 << (frame_number % 64);
+
+        if used {
+            *word |= bit;
+        } else {
+            *word &= !bit;
+        }
+    }
+}
+
+impl PageFrameAllocator for BitmapFrameAllocator {
+    fn falloc(&mut self) -> Option<PageFrame> {
+        if self.free_frames == 0 {
+            return None;
+        }
+
+        let word_count = self.frame_count.div_ceil(64);
+        for offset in 0..word_count {
+            let word_index = (self.next_word + offset) % word_count;
+            let word = self.bitmap[word_index];
+            if word == u64::MAX {
+                continue;
+            }
+
+            let frame_number = word_index * 64 + (!word).trailing_zeros() as usize;
+            if frame_number >= self.frame_count {
+                continue;
+            }
+
+            self.set_used(frame_number, true);
+            self.free_frames -= 1;
+            self.next_word = word_index;
+
+            return Some(PageFrame {
+                frame_number: frame_number,
+            });
+        }
+
+        None
+    }
+
+    fn free(&mut self, frame: PageFrame) {
+        let frame_number = frame.frame_number;
+
+        // ignore frames that never came from this allocator, like
+        // identity mapped device memory, and double frees
+        if frame_number >= self.frame_count
+            || !self.is_allocatable(frame_number)
+            || !self.is_used(frame_number)
+        {
+            return;
+        }
+
+        self.set_used(frame_number, false);
+        self.free_frames += 1;
+
+        if frame_number / 64 < self.next_word {
+            self.next_word = frame_number / 64;
+        }
+    }
+}
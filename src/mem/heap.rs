@@ -1,187 +1,334 @@
 use core::{
     alloc::{AllocError, Allocator, GlobalAlloc, Layout},
-    ptr::NonNull,
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
+use crate::mem::{
+    aslr,
+    paging::{entry::EntryFlags, Page},
+    GLOBAL_MEMORY_CONTROLLER, PAGE_SIZE,
+};
+use crate::utils::safe::Safe;
 use crate::HEAP_ALLOCATOR;
-use crate::{print, utils::safe::Safe};
 
-pub const HEAP_START: usize = 0o_000_020_000_000_0000;
+/// Lowest address the kernel heap can start at.
+pub const HEAP_START: usize = 0o_177777_600_000_000_000_0000;
+
+/// Number of page offsets the heap start is randomly slid by.
+pub const HEAP_RANDOM_PAGES: usize = 0x4_0000; // 1 GiB
+
+/// Actual start of the kernel heap, set once during boot.
+static HEAP_BASE: AtomicUsize = AtomicUsize::new(HEAP_START);
+
+/// Size of the heap mapped during boot.
 pub const HEAP_SIZE: usize = 16 * 1024 * 1024; // 16 MiB
 
-struct Block {
-    next: Option<&'static mut Block>,
-    used: bool,
-    size: usize,
-    address: usize,
+/// Size the heap can grow to by mapping more pages on demand.
+pub const HEAP_MAX_SIZE: usize = 1024 * 1024 * 1024; // 1 GiB
+
+/// Minimum number of bytes mapped at once when the heap grows, the heap
+/// also grows ahead of time once less than this is left.
+const HEAP_GROWTH: usize = 1024 * 1024; // 1 MiB
+
+/// Object sizes of the slab caches. Slabs are page aligned, so every
+/// object is aligned to its own size.
+const SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// Number of bytes a slab cache takes from the region allocator at once.
+const SLAB_SIZE: usize = PAGE_SIZE;
+
+/// Granularity of the region allocator, every free region must be able
+/// to hold a `FreeRegion`.
+const REGION_ALIGN: usize = 16;
+
+/// A free object of a slab cache, stored in the object itself.
+struct FreeObject {
+    next: *mut FreeObject,
 }
 
-impl Block {
-    const fn new(address: usize, size: usize) -> Block {
-        Block {
-            next: None,
-            used: false,
-            size: size,
-            address: address,
-        }
-    }
+/// A free region of the heap, stored in the region itself.
+struct FreeRegion {
+    size: usize,
+    next: *mut FreeRegion,
 }
 
-pub struct LinkedListHeap {
+/// Kernel heap allocator.
+///
+/// Small allocations are served from per size class free lists that are
+/// refilled a slab at a time, everything else comes from an address
+/// sorted list of free regions that are merged on free. When no region
+/// is large enough, more pages are mapped at the end of the heap.
+pub struct SlabHeap {
     heap_start: usize,
     heap_end: usize,
-    size: usize,
 
-    /// this is not the head reference,
-    /// this block's .next is the actual
-    /// head, rust disallows mutable references
-    /// in cosnt functions.
-    head: Block,
+    /// The heap never grows beyond this address.
+    max_end: usize,
+
+    slabs: [*mut FreeObject; SIZE_CLASSES.len()],
+    regions: *mut FreeRegion,
+
+    /// Number of bytes in the free region list.
+    free_bytes: usize,
 }
 
-impl LinkedListHeap {
+/// Snapshot of the kernel heap usage in bytes.
+#[derive(Clone, Copy, Debug)]
+pub struct HeapStats {
+    /// Bytes currently mapped for the heap.
+    pub size: usize,
+    pub used: usize,
+    pub free: usize,
+
+    /// Size of the largest free region, the largest allocation that
+    /// fits without growing the heap.
+    pub largest_free: usize,
+}
+
+// the raw pointers only ever point into the heap itself,
+// which is guarded by the allocator lock
+unsafe impl Send for SlabHeap {}
+
+impl SlabHeap {
     pub const fn empty() -> Self {
-        let head = Block::new(0, 0);
         Self {
             heap_start: 0,
             heap_end: 0,
-            size: 0,
-            head: head,
+            max_end: 0,
+            slabs: [ptr::null_mut(); SIZE_CLASSES.len()],
+            regions: ptr::null_mut(),
+            free_bytes: 0,
         }
     }
 
-    fn init(&mut self, heap_start: usize, heap_end: usize) {
-        let heap_size = heap_end - heap_start;
-        // let mut start_block = Block::new(heap_start, heap_size);
-        // let start_block_ptr = get_block_ptr(&mut start_block);
-        let start_block = match create_block(heap_start, heap_size) {
-            Some(b) => b,
-            None => unreachable!(),
-        };
+    /// Hands the mapped memory between `heap_start` and `heap_end` to
+    /// the heap.
+    ///
+    /// ## Arguments
+    ///
+    /// - `heap_start` the first address of the heap
+    /// - `heap_end` the end of the currently mapped heap memory
+    /// - `max_end` the address the heap may grow up to by mapping pages
+    pub fn init(&mut self, heap_start: usize, heap_end: usize, max_end: usize) {
+        let heap_start = align_up(heap_start, REGION_ALIGN);
+        let heap_end = align_down(heap_end, REGION_ALIGN);
 
         self.heap_start = heap_start;
         self.heap_end = heap_end;
-        self.size = heap_size;
+        self.max_end = max_end.max(heap_end);
 
-        self.head.next = Some(start_block);
+        unsafe { self.insert_region(heap_start, heap_end - heap_start) };
     }
 
-    fn allocate_internal(&mut self, size: usize, _align: usize) -> Result<*mut u8, AllocError> {
-        let mut head = &mut self.head;
-        loop {
-            let Some(ref mut block) = head.next else {
-                break;
-            };
-
-            if !block.used && block.size == size {
-                block.used = true;
-
-                let addr = block.address + core::mem::size_of::<Block>();
-                return Ok(addr as *mut u8);
+    /// Current usage of the heap. Free slab objects count as free, so
+    /// the used bytes are the ones handed out to callers, including the
+    /// rounding up to their size class.
+    pub fn stats(&self) -> HeapStats {
+        let mut free = self.free_bytes;
+        for (class, &object_size) in SIZE_CLASSES.iter().enumerate() {
+            let mut object = self.slabs[class];
+            while !object.is_null() {
+                free += object_size;
+                object = unsafe { (*object).next };
             }
+        }
 
-            if !block.used && block.size > size {
-                // found block, split up
-                let remainder_size = block.size - size;
-                let remainder_addr = block.address + size;
-
-                let remainder_next = match create_block(remainder_addr, remainder_size) {
-                    Some(b) => b,
-                    None => {
-                        // this previously threw an allocator error...
-                        // idk why it was done that way, but just switching
-                        // to the next block seems logical to me
-                        head = head.next.as_mut().unwrap();
-                        continue;
-                    }
-                };
-
-                remainder_next.next = block.next.take();
-                block.next = Some(remainder_next);
-
-                block.used = true;
-                block.size = size;
-
-                let addr = block.address + core::mem::size_of::<Block>();
-                return Ok(addr as *mut u8);
+        let mut largest_free = 0;
+        let mut region = self.regions;
+        while !region.is_null() {
+            unsafe {
+                largest_free = largest_free.max((*region).size);
+                region = (*region).next;
             }
+        }
 
-            head = head.next.as_mut().unwrap();
+        let size = self.heap_end - self.heap_start;
+        HeapStats {
+            size: size,
+            used: size - free,
+            free: free,
+            largest_free: largest_free,
         }
+    }
 
-        return Err(AllocError);
+    fn allocate_internal(&mut self, layout: Layout) -> Result<*mut u8, AllocError> {
+        match size_class(layout) {
+            Some(class) => self.allocate_object(class),
+            None => self.allocate_region(layout.size(), layout.align()),
+        }
     }
 
-    fn dealloc_internal(&mut self, addr: usize, _size: usize) {
-        let block_size = core::mem::size_of::<Block>();
-        let block_addr = addr - block_size;
+    fn dealloc_internal(&mut self, addr: usize, layout: Layout) {
+        match size_class(layout) {
+            Some(class) => unsafe {
+                let object = addr as *mut FreeObject;
+                (*object).next = self.slabs[class];
+                self.slabs[class] = object;
+            },
+            None => unsafe { self.insert_region(addr, region_size(layout.size())) },
+        }
+    }
 
-        let block_ptr = block_addr as *mut Block;
-        let block = unsafe { &mut *block_ptr };
+    fn allocate_object(&mut self, class: usize) -> Result<*mut u8, AllocError> {
+        if self.slabs[class].is_null() {
+            self.refill_slab(class)?;
+        }
 
-        block.used = false;
-        self.merge_right(block);
+        let object = self.slabs[class];
+        self.slabs[class] = unsafe { (*object).next };
 
-        // TODO: Maybe we should also merge left?
+        Ok(object as *mut u8)
+    }
 
-        // move head to current free block
-        if block.address >= self.head.address {
-            return;
+    /// Carves a fresh slab into free objects of a size class.
+    fn refill_slab(&mut self, class: usize) -> Result<(), AllocError> {
+        let slab = self.allocate_region(SLAB_SIZE, SLAB_SIZE)? as usize;
+        let object_size = SIZE_CLASSES[class];
+
+        for addr in (slab..slab + SLAB_SIZE).step_by(object_size).rev() {
+            let object = addr as *mut FreeObject;
+            unsafe { (*object).next = self.slabs[class] };
+            self.slabs[class] = object;
         }
 
-        self.head.next = Some(block);
+        Ok(())
     }
 
-    /// Merges a free block with the next block if it's free
-    fn merge_right(&mut self, block: &mut Block) {
-        let Some(ref mut next) = block.next else {
-            return;
+    fn allocate_region(&mut self, size: usize, align: usize) -> Result<*mut u8, AllocError> {
+        let size = region_size(size);
+        let align = align.max(REGION_ALIGN);
+
+        let addr = match unsafe { self.take_region(size, align) } {
+            Some(addr) => addr,
+            None => {
+                // worst case padding to reach the alignment
+                let needed = size.checked_add(align).ok_or(AllocError)?;
+                self.grow(needed)?;
+
+                unsafe { self.take_region(size, align) }.ok_or(AllocError)?
+            }
         };
 
-        if next.used {
-            return;
+        if self.free_bytes < HEAP_GROWTH {
+            // grow while the memory controller is likely free, instead of
+            // failing later on while it's held by the allocating code
+            let _ = self.grow(HEAP_GROWTH);
+        }
+
+        Ok(addr as *mut u8)
+    }
+
+    /// Removes the first region that fits `size` bytes at `align` from
+    /// the free list, returning the padding around it to the list.
+    unsafe fn take_region(&mut self, size: usize, align: usize) -> Option<usize> {
+        let mut previous: *mut FreeRegion = ptr::null_mut();
+        let mut current = self.regions;
+
+        while !current.is_null() {
+            let start = current as usize;
+            let end = start + (*current).size;
+            let next = (*current).next;
+
+            let addr = align_up(start, align);
+            if addr + size <= end {
+                if previous.is_null() {
+                    self.regions = next;
+                } else {
+                    (*previous).next = next;
+                }
+
+                self.free_bytes -= end - start;
+
+                if addr > start {
+                    self.insert_region(start, addr - start);
+                }
+
+                if addr + size < end {
+                    self.insert_region(addr + size, end - (addr + size));
+                }
+
+                return Some(addr);
+            }
+
+            previous = current;
+            current = next;
         }
 
-        // merge
-        let new_size = block.size + next.size;
-        block.size = new_size;
+        None
+    }
 
-        // set new next
-        let Some(ref mut new_next) = next.next else {
-            block.next = None;
+    /// Returns a region to the free list, merging it with its neighbours.
+    unsafe fn insert_region(&mut self, start: usize, size: usize) {
+        if size == 0 {
             return;
-        };
+        }
 
-        let new_next_ptr = new_next.address as *mut Block;
-        let new_next_ref = unsafe { &mut *new_next_ptr };
-        block.next = Some(new_next_ref);
+        let mut previous: *mut FreeRegion = ptr::null_mut();
+        let mut current = self.regions;
+
+        while !current.is_null() && (current as usize) < start {
+            previous = current;
+            current = (*current).next;
+        }
+
+        let region = start as *mut FreeRegion;
+        (*region).size = size;
+        (*region).next = current;
+
+        if !current.is_null() && start + size == current as usize {
+            (*region).size += (*current).size;
+            (*region).next = (*current).next;
+        }
+
+        if previous.is_null() {
+            self.regions = region;
+        } else if previous as usize + (*previous).size == start {
+            (*previous).size += (*region).size;
+            (*previous).next = (*region).next;
+        } else {
+            (*previous).next = region;
+        }
+
+        self.free_bytes += size;
     }
 
-    /// Aligns a layout such that that the allocated memory region
-    /// is also capable of holding the block structure.
-    fn block_align_size(&self, layout: Layout) -> (usize, usize) {
-        let layout = layout
-            .align_to(core::mem::align_of::<Block>())
-            .expect("Couldn't align block")
-            .pad_to_align();
+    /// Maps at least `needed` more bytes at the end of the heap.
+    fn grow(&mut self, needed: usize) -> Result<(), AllocError> {
+        let size = align_up(needed.max(HEAP_GROWTH), PAGE_SIZE);
+        let start = align_up(self.heap_end, PAGE_SIZE);
+        if start + size > self.max_end {
+            return Err(AllocError);
+        }
 
-        let size = layout.size() + core::mem::size_of::<Block>();
-        (size, layout.align())
+        // the allocating code may hold the memory controller itself,
+        // in which case waiting for it would never return
+        let mut guard = GLOBAL_MEMORY_CONTROLLER.try_lock().ok_or(AllocError)?;
+        let mc = guard.as_mut().ok_or(AllocError)?;
+        if mc.frame_allocator.free_count() < size / PAGE_SIZE {
+            return Err(AllocError);
+        }
+
+        mc.map_large(
+            Page::for_address(start),
+            Page::for_address(start + size - 1),
+            EntryFlags::WRITABLE,
+        );
+        drop(guard);
+
+        let old_end = self.heap_end;
+        self.heap_end = start + size;
+        unsafe { self.insert_region(old_end, self.heap_end - old_end) };
+
+        Ok(())
     }
 }
 
-unsafe impl<'a> Allocator for Safe<LinkedListHeap> {
+unsafe impl<'a> Allocator for Safe<SlabHeap> {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
         let mut allocator = self.lock();
+        let block = allocator.allocate_internal(layout)?;
 
-        let (size, align) = allocator.block_align_size(layout);
-        let block = allocator.allocate_internal(size, align);
-
-        if let Err(_) = block {
-            return Err(AllocError);
-        }
-
-        let block = block.unwrap();
         let start_ptr = NonNull::<u8>::new(block).unwrap();
         let slice = NonNull::slice_from_raw_parts(start_ptr, layout.size());
         Ok(slice)
@@ -189,18 +336,15 @@ unsafe impl<'a> Allocator for Safe<LinkedListHeap> {
 
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
         let mut allocator = self.lock();
-        allocator.dealloc_internal(ptr.addr().get(), layout.size());
+        allocator.dealloc_internal(ptr.addr().get(), layout);
     }
 }
 
-unsafe impl GlobalAlloc for Safe<LinkedListHeap> {
+unsafe impl GlobalAlloc for Safe<SlabHeap> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
 
-        let (size, align) = allocator.block_align_size(layout);
-        let block = allocator.allocate_internal(size, align);
-
-        match block {
+        match allocator.allocate_internal(layout) {
             Ok(ptr) => ptr,
             Err(_) => core::ptr::null_mut(),
         }
@@ -208,29 +352,20 @@ unsafe impl GlobalAlloc for Safe<LinkedListHeap> {
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
-        allocator.dealloc_internal(ptr as usize, layout.size());
+        allocator.dealloc_internal(ptr as usize, layout);
     }
 }
 
-fn create_block(addr: usize, size: usize) -> Option<&'static mut Block> {
-    let is_correct_align = align_up(addr, core::mem::align_of::<Block>()) == addr;
-    let can_hold_structure = size >= core::mem::size_of::<Block>();
-
-    if !is_correct_align || !can_hold_structure {
-        return None;
-    }
-
-    // something fishy is happening with the
-    // pointers, when reading out of the
-    // reference, the addr is suddenly zero
-
-    let block = Block::new(addr, size);
-
-    let block_ptr = addr as *mut Block;
-    unsafe { block_ptr.write(block) };
+/// Index of the smallest size class that fits the layout, both in size
+/// and alignment, or `None` when it needs a region of its own.
+fn size_class(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align());
+    SIZE_CLASSES.iter().position(|&class| class >= size)
+}
 
-    let reference = unsafe { &mut *block_ptr };
-    Some(reference)
+/// Number of bytes the region allocator hands out for `size` bytes.
+fn region_size(size: usize) -> usize {
+    align_up(size.max(1), REGION_ALIGN)
 }
 
 /// Align downwards. Returns the greatest x with alignment `align`
@@ -251,7 +386,29 @@ pub fn align_up(addr: usize, align: usize) -> usize {
     align_down(addr + align - 1, align)
 }
 
+/// Start address of the kernel heap.
+pub fn heap_start() -> usize {
+    HEAP_BASE.load(Ordering::SeqCst)
+}
+
+/// Slides the kernel heap start by a random number of pages, must be
+/// called before the heap is mapped.
+pub fn randomize_heap_start() {
+    let start = HEAP_START + aslr::random_page_offset(HEAP_RANDOM_PAGES);
+    HEAP_BASE.store(start, Ordering::SeqCst);
+}
+
+/// Current usage of the kernel heap.
+pub fn heap_stats() -> HeapStats {
+    unsafe { (*ptr::addr_of!(HEAP_ALLOCATOR)).lock().stats() }
+}
+
 pub unsafe fn init_heap() {
     let mut allocator = HEAP_ALLOCATOR.lock();
-    allocator.init(HEAP_START, HEAP_START + HEAP_SIZE);
+    let heap_start = heap_start();
+    allocator.init(
+        heap_start,
+        heap_start + HEAP_SIZE,
+        heap_start + HEAP_MAX_SIZE,
+    );
 }
@@ -0,0 +1,261 @@
+use core::{
+    ops::Range,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
+
+use multiboot2::BootInformation;
+use spin::Once;
+
+use crate::{arch::x86_64::gdt, io::LogType, log};
+
+use super::{
+    paging::{entry::EntryFlags, Page, PageTable},
+    pcid::{self, CR3_NOFLUSH as NOFLUSH_BIT, USER_PCID_BIT},
+    MemoryController, GLOBAL_MEMORY_CONTROLLER, PAGE_SIZE,
+};
+
+/// Kernel command line option that turns kernel page table isolation on,
+/// processes then run on a page table that doesn't map the kernel.
+pub const KPTI_ENABLE_OPTION: &str = "kpti";
+
+/// Top level entries copied from the kernel page table of a process into
+/// its user page table, the lower half. The upper half holds the kernel,
+/// the user page table has the entry mappings there instead.
+const USER_ENTRIES: Range<usize> = 0..256;
+
+static KPTI_ENABLED: AtomicBool = AtomicBool::new(false);
+
+// read by the interrupt entry and exit code, partly while the user page
+// table is active, 0 when processes run on their kernel page table
+#[link_section = ".data.entry"]
+pub static KERNEL_CR3: AtomicU64 = AtomicU64::new(0);
+
+#[link_section = ".data.entry"]
+pub static USER_CR3: AtomicU64 = AtomicU64::new(0);
+
+/// `NOFLUSH_BIT` when the process has its own PCID, or-ed into `USER_CR3`
+/// once the user page table was switched to.
+#[link_section = ".data.entry"]
+pub static CR3_NOFLUSH: AtomicU64 = AtomicU64::new(0);
+
+// the page tables of the running process, by their virtual addresses
+static KERNEL_TABLE: AtomicUsize = AtomicUsize::new(0);
+static USER_TABLE: AtomicUsize = AtomicUsize::new(0);
+
+/// The page table user page tables are copied from, it only maps the
+/// entry code, its data and the stacks the CPU switches to.
+static ENTRY_TABLE: Once<PageTable> = Once::new();
+
+extern "C" {
+    // defined in linker.ld, both are page aligned
+    static __entry_text_start: u8;
+    static __entry_text_end: u8;
+    static __entry_data_start: u8;
+    static __entry_data_end: u8;
+}
+
+/// Reads the page table isolation boot option and sets up the entry
+/// mappings, must run after the GDT is initialized.
+///
+/// ## Arguments
+///
+/// - `boot_info` the multiboot information holding the command line
+pub fn init(boot_info: &BootInformation) {
+    let enabled = boot_info
+        .command_line_tag()
+        .and_then(|tag| tag.cmdline().ok())
+        .is_some_and(|cmdline| {
+            cmdline
+                .split_ascii_whitespace()
+                .any(|option| option == KPTI_ENABLE_OPTION)
+        });
+
+    if !enabled {
+        return;
+    }
+
+    let entry_table = {
+        let mut mc = GLOBAL_MEMORY_CONTROLLER.lock();
+        let Some(mc) = mc.as_mut() else {
+            log!(LogType::ERR, "kpti: memory controller is not initialized");
+            return;
+        };
+
+        create_entry_table(mc)
+    };
+
+    let Some(entry_table) = entry_table else {
+        log!(LogType::ERR, "kpti: failed to map the entry area");
+        return;
+    };
+
+    ENTRY_TABLE.call_once(|| entry_table);
+    KPTI_ENABLED.store(true, Ordering::SeqCst);
+
+    log!(LogType::OK, "Kernel page table isolation enabled");
+}
+
+/// Whether processes run on user page tables.
+pub fn is_enabled() -> bool {
+    KPTI_ENABLED.load(Ordering::SeqCst)
+}
+
+/// Maps the entry code, its data and the entry stacks into a fresh page
+/// table at the same addresses as in the kernel page table.
+fn create_entry_table(mc: &mut MemoryController) -> Option<PageTable> {
+    let slot = mc
+        .slot_allocator
+        .alloc(&mut mc.frame_allocator, &mut mc.temp_mapper)?;
+    let mut table = PageTable::new(slot);
+
+    let text_start = ptr::addr_of!(__entry_text_start) as usize;
+    let text_end = ptr::addr_of!(__entry_text_end) as usize;
+    let data_start = ptr::addr_of!(__entry_data_start) as usize;
+    let data_end = ptr::addr_of!(__entry_data_end) as usize;
+
+    let data_flags = EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE;
+    let mut areas = [(0, 0, EntryFlags::empty()); 6];
+    areas[0] = (text_start, text_end, EntryFlags::empty());
+    areas[1] = (data_start, data_end, data_flags);
+
+    let stack_size = gdt::ENTRY_STACK_PAGES * PAGE_SIZE;
+    for (index, top) in gdt::entry_stack_tops().into_iter().enumerate() {
+        let top = top as usize;
+        areas[index + 2] = (top - stack_size, top, data_flags);
+    }
+
+    for (start, end, flags) in areas {
+        if start >= end {
+            continue;
+        }
+
+        for page in Page::range(Page::for_address(start), Page::for_address(end - 1)) {
+            let frame = mc
+                .kernel_table
+                .translate_to_phys(page.start_address(), &mut mc.temp_mapper)?;
+
+            table.map_to(
+                page,
+                frame,
+                flags,
+                &mut mc.frame_allocator,
+                &mut mc.slot_allocator,
+                &mut mc.temp_mapper,
+            );
+        }
+    }
+
+    Some(table)
+}
+
+/// Creates the user page table of a process, without any user entries
+/// yet, they are copied from its kernel page table on every return to
+/// ring 3.
+///
+/// ## Arguments
+///
+/// - `mc` the memory controller to allocate the table slot from
+///
+/// ## Returns
+/// The table, or `None` when page table isolation is disabled or no slot
+/// is left.
+pub fn create_user_table(mc: &mut MemoryController) -> Option<PageTable> {
+    let entry_table = ENTRY_TABLE.get()?;
+    let slot = mc
+        .slot_allocator
+        .alloc(&mut mc.frame_allocator, &mut mc.temp_mapper)?;
+
+    let entry_ptr = entry_table.addr as *mut [u8; PAGE_SIZE];
+    let new_ptr = slot as *mut [u8; PAGE_SIZE];
+    unsafe { entry_ptr.copy_to_nonoverlapping(new_ptr, 1) };
+
+    Some(PageTable::new(slot))
+}
+
+/// Sets the page tables the interrupt entry and exit code switch between,
+/// the kernel page table must be active already.
+///
+/// Both tables are tagged with the PCID of the process, the user page table
+/// with `USER_PCID_BIT` set. Processes without their own PCID flush the TLB
+/// on every switch, so kernel entries never outlive the kernel page table.
+///
+/// ## Arguments
+///
+/// - `mc` the memory controller
+/// - `kernel_table` the page table of the process holding all mappings
+/// - `user_table` the user page table of the process, `None` keeps the
+///   process on its kernel page table
+pub fn activate(
+    mc: &mut MemoryController,
+    kernel_table: &PageTable,
+    user_table: Option<&PageTable>,
+) {
+    let tables = user_table.filter(|_| is_enabled()).and_then(|user_table| {
+        let kernel_frame = mc
+            .active_table
+            .translate_to_phys(kernel_table.addr, &mut mc.temp_mapper)?;
+        let user_frame = mc
+            .active_table
+            .translate_to_phys(user_table.addr, &mut mc.temp_mapper)?;
+
+        Some((kernel_frame, user_frame, user_table))
+    });
+
+    let Some((kernel_frame, user_frame, user_table)) = tables else {
+        KERNEL_CR3.store(0, Ordering::SeqCst);
+        USER_CR3.store(0, Ordering::SeqCst);
+        USER_TABLE.store(0, Ordering::SeqCst);
+        return;
+    };
+
+    let tagged = kernel_table.pcid != 0;
+    let kernel_pcid = kernel_table.pcid;
+    let user_pcid = if tagged {
+        kernel_pcid | USER_PCID_BIT
+    } else {
+        0
+    };
+
+    // the user PCID keeps its entries from the last time the process ran,
+    // unless its mappings changed since
+    let user_flush = mc.pcid_allocator.take_stale(user_pcid);
+
+    KERNEL_TABLE.store(kernel_table.addr, Ordering::SeqCst);
+    USER_TABLE.store(user_table.addr, Ordering::SeqCst);
+    KERNEL_CR3.store(
+        pcid::cr3_value(kernel_frame.start_address(), kernel_pcid, tagged),
+        Ordering::SeqCst,
+    );
+    USER_CR3.store(
+        pcid::cr3_value(user_frame.start_address(), user_pcid, !user_flush),
+        Ordering::SeqCst,
+    );
+
+    let noflush = if tagged && pcid::is_enabled() {
+        NOFLUSH_BIT
+    } else {
+        0
+    };
+    CR3_NOFLUSH.store(noflush, Ordering::SeqCst);
+}
+
+/// Makes the next switch to the user page table flush its TLB entries,
+/// after user pages were unmapped or lost permissions.
+pub fn invalidate_user_tlb() {
+    USER_CR3.fetch_and(!NOFLUSH_BIT, Ordering::SeqCst);
+}
+
+/// Copies the user entries of the running process from its kernel page
+/// table into its user page table, called by the exit code right before
+/// switching to it.
+pub extern "C" fn sync_user_table() {
+    let kernel_table = KERNEL_TABLE.load(Ordering::SeqCst);
+    let user_table = USER_TABLE.load(Ordering::SeqCst);
+    if kernel_table == 0 || user_table == 0 {
+        return;
+    }
+
+    let kernel_table = PageTable::new(kernel_table);
+    PageTable::new(user_table).copy_user_entries(&kernel_table, USER_ENTRIES);
+}
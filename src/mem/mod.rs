@@ -1,11 +1,20 @@
+pub mod aslr;
+mod bitmap_frame_allocator;
 pub mod heap;
+pub mod kpti;
 mod linked_list_allocator;
 mod page_frame;
 pub mod paging;
+pub mod pcid;
 mod region;
-mod simple_page_frame_allocator;
 mod stack;
 mod stack_allocator;
+pub mod stats;
+pub mod swap;
+#[cfg(debug_assertions)]
+pub mod teardown_check;
+
+use core::ptr;
 
 use multiboot2::BootInformation;
 use spin::Mutex;
@@ -14,59 +23,116 @@ use x86_64::{
     instructions::tlb,
     registers::control::{Cr3, Cr3Flags},
     structures::paging::PhysFrame,
-    PhysAddr,
+    PhysAddr, VirtAddr,
 };
 
 use crate::log;
 use crate::{
     mem::{
-        heap::{HEAP_SIZE, HEAP_START},
+        heap::{HEAP_MAX_SIZE, HEAP_RANDOM_PAGES, HEAP_SIZE, HEAP_START},
         paging::{
-            entry::EntryFlags, map_kernel, slot_allocator::PageTableSlotAllocator,
-            temp_mapper::TempMapper, Page, PageTable,
+            entry::{EntryFlags, PageTableEntry},
+            map_kernel,
+            slot_allocator::PageTableSlotAllocator,
+            temp_mapper::TempMapper,
+            Page, PageTable, HUGE_PAGE_SIZE, PAGES_PER_HUGE_PAGE,
         },
+        pcid::PcidAllocator,
     },
     print,
 };
 
+pub use self::bitmap_frame_allocator::{take_frame_bitmap, BitmapFrameAllocator};
 pub use self::page_frame::{PageFrame, PageFrameAllocator, PAGE_SIZE};
 pub use self::region::Region;
-pub use self::simple_page_frame_allocator::SimplePageFrameAllocator;
-pub use self::stack::Stack;
+pub use self::stack::{find_guard_page_hit, register_kernel_stack, Stack};
 
 pub type VirtualAddress = usize;
 pub type PhysicalAddress = usize;
 
+extern "C" {
+    // defined in boot.s, the boot stack sits right above its guard page
+    static boot_stack_guard: u8;
+    static stack_top: u8;
+}
+
 pub static GLOBAL_MEMORY_CONTROLLER: Mutex<Option<MemoryController>> = Mutex::new(None);
 
-pub const PAGE_TABLE_REGION_START: usize = 0x0000_6BCF_0000_0000;
-const STACK_ALLOCATOR_PAGES: usize = 4096;
+// Kernel address space layout, all in the upper half:
+//
+// - `PHYS_MAP_START` all physical memory, mapped with huge pages, device
+//   memory is added once a driver maps it
+// - `HEAP_START` the kernel heap, slid by up to `HEAP_RANDOM_PAGES` and
+//   growing up to `HEAP_MAX_SIZE`
+// - `STACK_AREA_START` kernel stacks, each one right above an unmapped
+//   guard page
+// - `PAGE_TABLE_REGION_START` the page table slots
+// - `KERNEL_OFFSET` the kernel image and boot stack, in the top 2 GiB
+//
+// the whole lower half belongs to user space, images, heap, mmap areas and
+// `USER_STACK_AREA_START` the user stacks
+pub const PHYS_MAP_START: usize = 0xFFFF_8000_0000_0000;
+pub const PAGE_TABLE_REGION_START: usize = 0xFFFF_E000_0000_0000;
+pub const STACK_AREA_START: usize = HEAP_START + HEAP_RANDOM_PAGES * PAGE_SIZE + HEAP_MAX_SIZE;
+const STACK_ALLOCATOR_PAGES: usize = 65536;
+pub const KERNEL_OFFSET: usize = 0xFFFF_FFFF_8000_0000;
+pub const USER_STACK_AREA_START: usize = 0x0000_7400_0000_0000;
+const USER_STACK_ALLOCATOR_PAGES: usize = 0x100_0000; // 64 GiB
+
+/// Page tables that mapping a single page may have to create below the
+/// PML4, they are allocated from the same frames as the page itself.
+const MAX_NEW_TABLES_PER_PAGE: usize = 3;
+
+/// The address a physical address is reachable at through the direct map.
+///
+/// ## Arguments
+///
+/// - `addr` the physical address
+pub fn phys_to_virt(addr: PhysicalAddress) -> VirtualAddress {
+    PHYS_MAP_START + addr
+}
+
+/// The physical address of an address in the kernel image. The boot code
+/// below `KERNEL_OFFSET` is linked at its physical address.
+///
+/// ## Arguments
+///
+/// - `addr` the address in the kernel image
+pub fn kernel_phys_addr(addr: VirtualAddress) -> PhysicalAddress {
+    addr.checked_sub(KERNEL_OFFSET).unwrap_or(addr)
+}
 
 pub struct MemoryController {
     pub active_table: PageTable,
     pub kernel_table: PageTable,
-    pub frame_allocator: SimplePageFrameAllocator,
+    pub frame_allocator: BitmapFrameAllocator,
     pub stack_allocator: StackAllocator,
+    pub user_stack_allocator: StackAllocator,
     pub slot_allocator: PageTableSlotAllocator,
     pub temp_mapper: TempMapper,
+    pub pcid_allocator: PcidAllocator,
 }
 
 impl MemoryController {
     fn new(
         active_table: PageTable,
         kernel_table: PageTable,
-        frame_allocator: SimplePageFrameAllocator,
+        frame_allocator: BitmapFrameAllocator,
         stack_allocator: StackAllocator,
+        user_stack_allocator: StackAllocator,
         slot_allocator: PageTableSlotAllocator,
         temp_mapper: TempMapper,
+        pcid_allocator: PcidAllocator,
     ) -> MemoryController {
         MemoryController {
             active_table: active_table,
             kernel_table: kernel_table,
             frame_allocator: frame_allocator,
             stack_allocator: stack_allocator,
+            user_stack_allocator: user_stack_allocator,
             slot_allocator: slot_allocator,
             temp_mapper: temp_mapper,
+            pcid_allocator: pcid_allocator,
         }
     }
 
@@ -87,104 +153,476 @@ impl MemoryController {
         )
     }
 
-    /// Maps a range of pages to their exact corresponding page frames
+    pub fn translate_to_physical(&mut self, addr: usize) -> Option<PageFrame> {
+        self.active_table
+            .translate_to_phys(addr, &mut self.temp_mapper)
+    }
+
+    /// Maps a range of pages to unused page frames, using 2 MiB huge
+    /// pages for the parts of the range they fit in.
     ///
     /// ## Arguments
     ///
     /// - `start` the start page
     /// - `end` the end page
     /// - `flags` the page table entry flags to be applied
-    pub fn identity_map(&mut self, start: PageFrame, end: PageFrame, flags: EntryFlags) {
-        let allocator = &mut self.frame_allocator;
-        let temp_mapper = &mut self.temp_mapper;
-        let table = &mut self.active_table;
-
-        let page = Page::for_address(start.start_address());
-        let unused = table.is_unused(page, temp_mapper);
-        if !unused {
-            // already mapped
-            return;
+    pub fn map_large(&mut self, start: Page, end: Page, flags: EntryFlags) {
+        map_range_large(
+            &mut self.active_table,
+            start,
+            end,
+            flags,
+            &mut self.frame_allocator,
+            &mut self.slot_allocator,
+            &mut self.temp_mapper,
+        );
+    }
+
+    /// Maps a range of physical memory, like device registers, into the
+    /// direct map. Uses 2 MiB huge pages where the surrounding 2 MiB
+    /// aren't mapped yet, memory already in the direct map is left as is.
+    ///
+    /// ## Arguments
+    ///
+    /// - `addr` the physical start address
+    /// - `size` the size of the range in bytes
+    /// - `flags` the page table entry flags to be applied
+    ///
+    /// ## Returns
+    /// The virtual address of `addr` in the direct map.
+    pub fn map_physical(
+        &mut self,
+        addr: PhysicalAddress,
+        size: usize,
+        flags: EntryFlags,
+    ) -> VirtualAddress {
+        let start = PageFrame::from_address(addr);
+        let end = PageFrame::from_address(addr + size.max(1) - 1);
+        let first_chunk = start.frame_number - start.frame_number % PAGES_PER_HUGE_PAGE;
+
+        for chunk in (first_chunk..=end.frame_number).step_by(PAGES_PER_HUGE_PAGE) {
+            let frame = PageFrame {
+                frame_number: chunk,
+            };
+            let page = Page::for_address(phys_to_virt(frame.start_address()));
+
+            let mapped = self.active_table.map_huge_to(
+                page,
+                frame,
+                flags,
+                &mut self.frame_allocator,
+                &mut self.slot_allocator,
+                &mut self.temp_mapper,
+            );
+
+            if mapped.is_some() {
+                continue;
+            }
+
+            // parts of the 2 MiB are mapped already, map the
+            // requested frames one by one
+            let chunk_start = PageFrame {
+                frame_number: chunk.max(start.frame_number),
+            };
+            let chunk_end = PageFrame {
+                frame_number: (chunk + PAGES_PER_HUGE_PAGE - 1).min(end.frame_number),
+            };
+
+            for frame in PageFrame::range(chunk_start, chunk_end) {
+                let page = Page::for_address(phys_to_virt(frame.start_address()));
+                if !self.active_table.is_unused(page, &mut self.temp_mapper) {
+                    continue;
+                }
+
+                self.active_table.map_to(
+                    page,
+                    frame,
+                    flags,
+                    &mut self.frame_allocator,
+                    &mut self.slot_allocator,
+                    &mut self.temp_mapper,
+                );
+            }
         }
 
-        let range = PageFrame::range(start, end);
-        for frame in range {
-            table.map_identity(
+        phys_to_virt(addr)
+    }
+
+    /// Maps a range of pages to physically contiguous page frames,
+    /// as needed by device DMA buffers
+    ///
+    /// ## Arguments
+    ///
+    /// - `start` the start page
+    /// - `end` the end page
+    /// - `flags` the page table entry flags to be applied
+    ///
+    /// ## Returns
+    /// The first page frame backing `start`, or `None` when there is no
+    /// contiguous run of free frames large enough.
+    pub fn map_contiguous(
+        &mut self,
+        start: Page,
+        end: Page,
+        flags: EntryFlags,
+    ) -> Option<PageFrame> {
+        let count = Page::range(start, end).count();
+        let first_frame = self.frame_allocator.falloc_contiguous(count)?;
+
+        for (index, page) in Page::range(start, end).enumerate() {
+            let frame = PageFrame {
+                frame_number: first_frame.frame_number + index,
+            };
+
+            self.active_table.map_to(
+                page,
                 frame,
                 flags,
-                allocator,
+                &mut self.frame_allocator,
                 &mut self.slot_allocator,
                 &mut self.temp_mapper,
             );
         }
+
+        Some(first_frame)
     }
 
-    pub fn translate_to_physical(&mut self, addr: usize) -> Option<PageFrame> {
-        self.active_table
-            .translate_to_phys(addr, &mut self.temp_mapper)
+    /// Allocates zeroed, physically contiguous page frames for device DMA,
+    /// the kernel reaches them through the direct map
+    ///
+    /// ## Arguments
+    ///
+    /// - `count` the number of page frames
+    ///
+    /// ## Returns
+    /// The first page frame, [`phys_to_virt`] gives its virtual address.
+    pub fn alloc_dma(&mut self, count: usize) -> Option<PageFrame> {
+        let first_frame = self.frame_allocator.falloc_contiguous(count)?;
+        let addr = phys_to_virt(first_frame.start_address());
+
+        unsafe {
+            core::ptr::write_bytes(addr as *mut u8, 0, count * PAGE_SIZE);
+        }
+
+        Some(first_frame)
     }
 
-    /// Maps a range of pages to unused page frames
+    /// Maps a range of pages into a process page table, backed by
+    /// fresh zeroed page frames.
+    ///
+    /// The frames are zeroed before they are mapped, so the table doesn't
+    /// need to be active and read-only pages can be mapped as well.
     ///
     /// ## Arguments
     ///
+    /// - `table` the process page table to map into
     /// - `start` the start page
     /// - `end` the end page
     /// - `flags` the page table entry flags to be applied
-    pub fn map(&mut self, start: Page, end: Page, flags: EntryFlags) {
-        let allocator = &mut self.frame_allocator;
-        let table = &mut self.active_table;
-
+    ///
+    /// ## Returns
+    /// `None` when the frames ran out, the pages mapped until then stay
+    /// mapped and are freed together with the range.
+    pub fn map_user(
+        &mut self,
+        table: &mut PageTable,
+        start: Page,
+        end: Page,
+        flags: EntryFlags,
+    ) -> Option<()> {
         for page in Page::range(start, end) {
-            let frame = allocator.falloc().expect("Out of memory");
+            // creating the page tables can't fail, so leave room for them
+            if self.frame_allocator.free_count() <= MAX_NEW_TABLES_PER_PAGE {
+                return None;
+            }
+
+            let frame = self.frame_allocator.falloc()?;
+            let temp_addr = self.temp_mapper.set(frame.clone());
+            unsafe { core::ptr::write_bytes(temp_addr as *mut u8, 0, PAGE_SIZE) };
+
             table.map_to(
                 page,
                 frame,
                 flags,
-                allocator,
+                &mut self.frame_allocator,
                 &mut self.slot_allocator,
                 &mut self.temp_mapper,
             );
         }
+
+        Some(())
+    }
+
+    /// Allocates a zeroed page frame that isn't mapped anywhere yet.
+    ///
+    /// ## Returns
+    /// The frame, or `None` when there are no free frames left.
+    pub fn alloc_zeroed_frame(&mut self) -> Option<PageFrame> {
+        let frame = self.frame_allocator.falloc()?;
+        let temp_addr = self.temp_mapper.set(frame.clone());
+        unsafe { core::ptr::write_bytes(temp_addr as *mut u8, 0, PAGE_SIZE) };
+
+        Some(frame)
+    }
+
+    /// Maps a page of a process page table to a frame that is owned
+    /// elsewhere, like the frames of shared memory objects.
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table to map into
+    /// - `page` the page to map
+    /// - `frame` the frame to map the page to
+    /// - `flags` the page table entry flags to be applied
+    pub fn map_user_frame(
+        &mut self,
+        table: &mut PageTable,
+        page: Page,
+        frame: PageFrame,
+        flags: EntryFlags,
+    ) {
+        table.map_to(
+            page,
+            frame,
+            flags,
+            &mut self.frame_allocator,
+            &mut self.slot_allocator,
+            &mut self.temp_mapper,
+        );
+    }
+
+    /// Unmaps a page of a process page table without freeing its frame,
+    /// the counterpart of [`Self::map_user_frame`].
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table to unmap from
+    /// - `page` the page to unmap
+    ///
+    /// ## Returns
+    /// The frame the page was mapped to, or `None` when it wasn't mapped.
+    pub fn unmap_user_frame(&mut self, table: &mut PageTable, page: Page) -> Option<PageFrame> {
+        let frame = table.unmap(page, &mut self.temp_mapper)?;
+        self.flush_user_page(table, page.start_address());
+
+        Some(frame)
+    }
+
+    /// Maps a 2 MiB huge page into a process page table, backed by 512
+    /// fresh zeroed page frames.
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table to map into
+    /// - `page` the first page of the huge page, 2 MiB aligned
+    /// - `flags` the page table entry flags to be applied
+    ///
+    /// ## Returns
+    /// `None` when there is no aligned run of free frames, or part of the
+    /// 2 MiB range is already mapped.
+    pub fn map_user_huge(
+        &mut self,
+        table: &mut PageTable,
+        page: Page,
+        flags: EntryFlags,
+    ) -> Option<()> {
+        if !page.is_huge_aligned() || table.get_entry(page, &mut self.temp_mapper).is_some() {
+            return None;
+        }
+
+        let first_frame = self
+            .frame_allocator
+            .falloc_contiguous_aligned(PAGES_PER_HUGE_PAGE, PAGES_PER_HUGE_PAGE)?;
+
+        for frame_number in 0..PAGES_PER_HUGE_PAGE {
+            let frame = PageFrame {
+                frame_number: first_frame.frame_number + frame_number,
+            };
+            let temp_addr = self.temp_mapper.set(frame);
+            unsafe { core::ptr::write_bytes(temp_addr as *mut u8, 0, PAGE_SIZE) };
+        }
+
+        let mapped = table.map_huge_to(
+            page,
+            first_frame.clone(),
+            flags,
+            &mut self.frame_allocator,
+            &mut self.slot_allocator,
+            &mut self.temp_mapper,
+        );
+
+        if mapped.is_none() {
+            self.frame_allocator
+                .free_contiguous(first_frame, PAGES_PER_HUGE_PAGE);
+        }
+
+        mapped
     }
 
-    /// Unmaps a range of pages and frees page frames
+    /// Unmaps a range of pages from a process page table and frees their
+    /// page frames, or the swap slots of pages that were swapped out.
+    /// Huge pages that are only partly inside the range are split first.
     ///
     /// ## Arguments
     ///
+    /// - `table` the process page table to unmap from
     /// - `start` the start page
     /// - `end` the end page
-    pub fn unmap(&mut self, start: Page, end: Page) {
-        let temp_mapper = &mut self.temp_mapper;
-        let table = &mut self.active_table;
+    pub fn unmap_user(&mut self, table: &mut PageTable, start: Page, end: Page) {
+        let mut pages = Page::range(start, end);
+        while let Some(page) = pages.next() {
+            let huge = table
+                .get_entry(page, &mut self.temp_mapper)
+                .is_some_and(|entry| entry.flags().contains(EntryFlags::HUGE_PAGE));
+
+            if huge {
+                let huge_start = page.huge_page_start();
+                let huge_end = huge_start + (PAGES_PER_HUGE_PAGE - 1);
+                let huge_addr = huge_start.start_address();
+
+                if huge_start >= start && huge_end <= end {
+                    if let Some(frame) = table.unmap_huge(huge_start, &mut self.temp_mapper) {
+                        self.flush_user_page(table, huge_addr);
+                        self.frame_allocator
+                            .free_contiguous(frame, PAGES_PER_HUGE_PAGE);
+                    }
+
+                    pages = Page::range(huge_end + 1, end);
+                    continue;
+                }
+
+                table.split_huge(
+                    page,
+                    &mut self.frame_allocator,
+                    &mut self.slot_allocator,
+                    &mut self.temp_mapper,
+                );
+                self.flush_user_page(table, huge_addr);
+            }
 
-        for page in Page::range(start, end) {
-            if let Some(frame) = table.unmap(page, temp_mapper) {
+            if let Some(frame) = table.unmap(page, &mut self.temp_mapper) {
                 self.frame_allocator.free(frame);
+                self.flush_user_page(table, page.start_address());
+                continue;
+            }
+
+            // a swapped out page only holds on to its swap slot
+            let slot = table
+                .get_entry(page, &mut self.temp_mapper)
+                .and_then(|entry| entry.swap_slot());
+
+            if let Some(slot) = slot {
+                table.replace_entry(page, PageTableEntry { entry: 0 }, &mut self.temp_mapper);
+                swap::free_slot(slot);
             }
         }
     }
 
-    /// Frees a stack that was allocated through the stack allocator.
+    /// Takes the frame of a mapped user page away, leaving behind the
+    /// entry of a swapped out page, or an unused entry when the page can
+    /// be read from its file again.
+    ///
+    /// ## Arguments
     ///
-    /// The stack memory is zeroed before its mapped pages are unmapped and
-    /// its virtual range is returned to the stack allocator.
+    /// - `table` the process page table the page is mapped in
+    /// - `page` the mapped page
+    /// - `slot` the swap slot the page contents were written to
+    ///
+    /// ## Returns
+    /// `None` when the page is not mapped.
+    pub fn evict_user_page(
+        &mut self,
+        table: &mut PageTable,
+        page: Page,
+        slot: Option<usize>,
+    ) -> Option<()> {
+        let entry = match slot {
+            Some(slot) => PageTableEntry::swapped(slot),
+            None => PageTableEntry { entry: 0 },
+        };
+
+        let previous = table.get_entry(page, &mut self.temp_mapper)?;
+        let frame = previous.get_frame()?;
+        table.replace_entry(page, entry, &mut self.temp_mapper)?;
+
+        self.frame_allocator.free(frame);
+        self.flush_user_page(table, page.start_address());
+
+        Some(())
+    }
+
+    /// Clears flags of a mapped user page and flushes it from the TLB.
     ///
     /// ## Arguments
     ///
-    /// - `stack` the stack range to be freed
-    pub fn free_stack(&mut self, stack: &Stack) {
-        if stack.top <= stack.bottom {
-            return;
-        }
+    /// - `table` the process page table the page is mapped in
+    /// - `page` the mapped page
+    /// - `flags` the flags to be cleared
+    ///
+    /// ## Returns
+    /// The flags of the page before clearing, or `None` when it is not mapped.
+    pub fn clear_user_flags(
+        &mut self,
+        table: &mut PageTable,
+        page: Page,
+        flags: EntryFlags,
+    ) -> Option<EntryFlags> {
+        let old_flags = table.clear_flags(page, flags, &mut self.temp_mapper)?;
+        self.flush_user_page(table, page.start_address());
+
+        Some(old_flags)
+    }
 
-        unsafe {
-            core::ptr::write_bytes(stack.bottom as *mut u8, 0, stack.top - stack.bottom);
-        }
+    /// Copies bytes to the start of a mapped user page through its frame,
+    /// so pages that are read-only for the kernel can be filled as well.
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table the page is mapped in
+    /// - `page` the mapped page
+    /// - `bytes` at most a page of bytes to copy
+    ///
+    /// ## Returns
+    /// `None` when the page is not mapped.
+    pub fn fill_user_page(
+        &mut self,
+        table: &mut PageTable,
+        page: Page,
+        bytes: &[u8],
+    ) -> Option<()> {
+        let frame = table.translate_to_phys(page.start_address(), &mut self.temp_mapper)?;
+        let size = bytes.len().min(PAGE_SIZE);
+        let temp_addr = self.temp_mapper.set(frame);
+
+        unsafe { core::ptr::copy_nonoverlapping(bytes.as_ptr(), temp_addr as *mut u8, size) };
+        Some(())
+    }
 
-        let start = Page::for_address(stack.bottom);
-        let end = Page::for_address(stack.top - 1);
-        self.unmap(start, end);
-        self.stack_allocator.free(stack);
+    /// Copies the start of a mapped user page through its frame, so pages
+    /// of tables that aren't active can be read as well.
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table the page is mapped in
+    /// - `page` the mapped page
+    /// - `buffer` the buffer to fill, at most a page is copied
+    ///
+    /// ## Returns
+    /// `None` when the page is not mapped.
+    pub fn read_user_page(
+        &mut self,
+        table: &mut PageTable,
+        page: Page,
+        buffer: &mut [u8],
+    ) -> Option<()> {
+        let frame = table.translate_to_phys(page.start_address(), &mut self.temp_mapper)?;
+        let size = buffer.len().min(PAGE_SIZE);
+        let temp_addr = self.temp_mapper.set(frame);
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(temp_addr as *const u8, buffer.as_mut_ptr(), size)
+        };
+        Some(())
     }
 
     /// Clones the kernel base page table, keeping all
@@ -198,10 +636,81 @@ impl MemoryController {
         let new_ptr = slot as *mut [u8; PAGE_SIZE];
         unsafe { kernel_ptr.copy_to_nonoverlapping(new_ptr, 1) };
 
-        let new_table = PageTable::new(slot);
+        let mut new_table = PageTable::new(slot);
+        if pcid::is_enabled() {
+            new_table.pcid = self.pcid_allocator.alloc().unwrap_or(0);
+        }
+
         Some(new_table)
     }
 
+    /// Tears down the address space of a process once its memory areas are
+    /// unmapped. Pages still mapped outside of them are freed, then the
+    /// child page tables, the table slots and the PCID.
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table
+    /// - `user_table` the user page table of the process, if it has one
+    ///
+    /// ## Returns
+    /// The number of pages that were still mapped, huge pages count once.
+    pub fn free_address_space(
+        &mut self,
+        table: &PageTable,
+        user_table: Option<&PageTable>,
+    ) -> usize {
+        // leave the tables before their slots can be handed out again, a
+        // process reusing them must not be taken for this one
+        if table.addr == self.active_table.addr {
+            let kernel_table = self.kernel_table.clone();
+            self.switch_table(&kernel_table);
+            kpti::activate(self, &kernel_table, None);
+        }
+
+        let mut table = table.clone();
+        let leaves = table.user_leaf_entries(&mut self.temp_mapper);
+        for (page, entry) in &leaves {
+            let flags = entry.flags();
+            let end = if flags.contains(EntryFlags::PRESENT | EntryFlags::HUGE_PAGE) {
+                *page + (PAGES_PER_HUGE_PAGE - 1)
+            } else {
+                *page
+            };
+
+            self.unmap_user(&mut table, *page, end);
+        }
+
+        table.free_user_subtables(&mut self.slot_allocator, &mut self.temp_mapper);
+        self.slot_allocator.free(table.addr);
+        self.pcid_allocator.free(table.pcid);
+
+        // its entries are shared with the process page table
+        if let Some(user_table) = user_table {
+            self.slot_allocator.free(user_table.addr);
+        }
+
+        leaves.len()
+    }
+
+    /// Flushes a user page of a process page table from the TLB, for both
+    /// its kernel and its user page table. The TLB entries of a table that
+    /// isn't active are flushed when it is switched to next.
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table the page is mapped in
+    /// - `addr` the address of the page
+    fn flush_user_page(&mut self, table: &PageTable, addr: VirtualAddress) {
+        if table.addr != self.active_table.addr {
+            self.pcid_allocator.invalidate(table.pcid);
+            return;
+        }
+
+        tlb::flush(VirtAddr::new(addr as u64));
+        kpti::invalidate_user_tlb();
+    }
+
     /// Switches the active page table into a the provided page table.
     /// Also changes the active table reference in the memory controller
     /// to the new table.
@@ -226,18 +735,13 @@ impl MemoryController {
             return None;
         };
 
-        let phys_addr = phys_frame.start_address() as u64;
-        let phys_addr = PhysAddr::new(phys_addr);
-        let phys_frame = PhysFrame::from_start_address(phys_addr)
-            .expect("Cannot create cr3 new frame swap address.");
-
-        // print!("Switching cr3 to 0x{:X}\n", addr);
-        unsafe { Cr3::write(phys_frame, Cr3Flags::empty()) };
-
-        // TODO: In the future, think about how to optimize the TLB here, maybe
-        // we don't have to flush the entire thing, just the user-sections,
-        // assuming this is switching between kernel->user tables
-        tlb::flush_all();
+        // the TLB entries tagged with the PCID of the table survive while
+        // other tables are active, unless its mappings changed meanwhile
+        let flush = self.pcid_allocator.take_stale(new_table.pcid);
+        unsafe { pcid::write_cr3(phys_frame.start_address(), new_table.pcid, !flush) };
+        if !flush {
+            self.temp_mapper.flush();
+        }
 
         let old_table = self.active_table.clone();
         self.active_table = new_table.clone();
@@ -246,8 +750,56 @@ impl MemoryController {
     }
 }
 
+/// Maps a range of pages to unused page frames, with a 2 MiB huge page
+/// for every aligned 2 MiB the range covers and 4 KiB pages for the rest.
+/// Falls back to 4 KiB pages when no aligned run of frames is free.
+///
+/// ## Arguments
+///
+/// - `table` the page table to map into
+/// - `start` the start page
+/// - `end` the end page
+/// - `flags` the page table entry flags to be applied
+fn map_range_large(
+    table: &mut PageTable,
+    start: Page,
+    end: Page,
+    flags: EntryFlags,
+    allocator: &mut BitmapFrameAllocator,
+    slot_allocator: &mut PageTableSlotAllocator,
+    temp_mapper: &mut TempMapper,
+) {
+    let mut pages = Page::range(start, end);
+    while let Some(page) = pages.next() {
+        let huge_end = page + (PAGES_PER_HUGE_PAGE - 1);
+        if page.is_huge_aligned() && huge_end <= end {
+            let frame =
+                allocator.falloc_contiguous_aligned(PAGES_PER_HUGE_PAGE, PAGES_PER_HUGE_PAGE);
+
+            if let Some(frame) = frame {
+                let mapped = table.map_huge_to(
+                    page,
+                    frame.clone(),
+                    flags,
+                    allocator,
+                    slot_allocator,
+                    temp_mapper,
+                );
+
+                if mapped.is_some() {
+                    pages = Page::range(huge_end + 1, end);
+                    continue;
+                }
+
+                allocator.free_contiguous(frame, PAGES_PER_HUGE_PAGE);
+            }
+        }
+
+        table.map(page, flags, allocator, slot_allocator, temp_mapper);
+    }
+}
+
 pub fn init(boot_info: &BootInformation) {
-    let map_tag = boot_info.memory_map_tag().unwrap();
     log!(
         crate::io::LogType::OK,
         "Kernel Init Done, Entered Rust 64-Bit Mode"
@@ -256,12 +808,12 @@ pub fn init(boot_info: &BootInformation) {
     let elf_sections = boot_info.elf_sections().unwrap();
     let kernel_start = elf_sections
         .clone()
-        .map(|s| s.start_address())
+        .map(|s| kernel_phys_addr(s.start_address() as usize))
         .min()
         .unwrap();
     let kernel_end = elf_sections
         .clone()
-        .map(|s| s.start_address() + s.size())
+        .map(|s| kernel_phys_addr((s.start_address() + s.size()) as usize))
         .max()
         .unwrap();
 
@@ -282,23 +834,15 @@ pub fn init(boot_info: &BootInformation) {
         multiboot_end
     );
 
-    // memory
-
-    // for some reason when getting the last memory area,
-    // it's always padded to 4GB, the second last area
-    // actually corresponds to the memory available
-
-    let mem_areas = map_tag.memory_areas();
-    let memory_end = mem_areas[mem_areas.len() - 2].end_address();
+    let bitmap = take_frame_bitmap().expect("Frame bitmap is already in use");
+    let mut allocator = BitmapFrameAllocator::from_boot_info(boot_info, bitmap);
 
-    log!(crate::io::LogType::OK, "Memory end: 0x{:x}", memory_end);
-
-    let mut allocator = SimplePageFrameAllocator::new(multiboot_end as usize, memory_end as usize);
-
-    // for some reason I have to allocate
-    // and empty page here or else it
-    // panics and faults
-    let _ = allocator.falloc().unwrap();
+    log!(
+        crate::io::LogType::OK,
+        "Frame allocator: {} usable frames ({} MiB)",
+        allocator.total_count(),
+        allocator.total_count() * PAGE_SIZE / (1024 * 1024)
+    );
 
     let mut slot_allocator = PageTableSlotAllocator::new(PAGE_TABLE_REGION_START);
     let (mut pml4, mut temp) = slot_allocator.alloc_master_table(&mut allocator);
@@ -311,6 +855,45 @@ pub fn init(boot_info: &BootInformation) {
         &mut temp,
     );
 
+    // the direct map covers every area of the memory map, holes between
+    // them are mapped by drivers on demand
+    let map_tag = boot_info.memory_map_tag().unwrap();
+    for area in map_tag.memory_areas() {
+        let start = area.start_address() as usize;
+        let end = area.end_address() as usize;
+        if end <= start {
+            continue;
+        }
+
+        let first_chunk = start / HUGE_PAGE_SIZE * PAGES_PER_HUGE_PAGE;
+        let last_frame = (end - 1) / PAGE_SIZE;
+        for chunk in (first_chunk..=last_frame).step_by(PAGES_PER_HUGE_PAGE) {
+            let frame = PageFrame {
+                frame_number: chunk,
+            };
+            let page = Page::for_address(phys_to_virt(frame.start_address()));
+
+            // chunks shared with the previous area are mapped already
+            pml4.map_huge_to(
+                page,
+                frame,
+                EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+                &mut allocator,
+                &mut slot_allocator,
+                &mut temp,
+            );
+        }
+    }
+
+    // the boot stack keeps being used until the scheduler starts,
+    // give it a guard page like every other kernel stack
+    let boot_stack = Stack::new(
+        ptr::addr_of!(stack_top) as usize,
+        ptr::addr_of!(boot_stack_guard) as usize + PAGE_SIZE,
+    );
+    pml4.unmap(Page::for_address(boot_stack.guard_page()), &mut temp);
+    register_kernel_stack("boot", &boot_stack);
+
     // switch to new pml4
     let phys_addr = PhysAddr::new(pml4.addr as u64);
     let phys_frame = PhysFrame::from_start_address(phys_addr)
@@ -326,34 +909,46 @@ pub fn init(boot_info: &BootInformation) {
     pml4.addr = PAGE_TABLE_REGION_START;
 
     // map heap pages
-    let heap_start = Page::for_address(HEAP_START);
-    let heap_end = Page::for_address(HEAP_START + HEAP_SIZE - 1);
+    heap::randomize_heap_start();
+    let heap_start = Page::for_address(heap::heap_start());
+    let heap_end = Page::for_address(heap::heap_start() + HEAP_SIZE - 1);
 
-    for page in Page::range(heap_start, heap_end) {
-        pml4.map(
-            page,
-            EntryFlags::WRITABLE,
-            &mut allocator,
-            &mut slot_allocator,
-            &mut temp,
-        );
-    }
+    map_range_large(
+        &mut pml4,
+        heap_start,
+        heap_end,
+        EntryFlags::WRITABLE,
+        &mut allocator,
+        &mut slot_allocator,
+        &mut temp,
+    );
 
+    // stacks live past the region the heap can grow into
     let stack_allocator = {
-        let stack_start = heap_end + 1;
+        let stack_start = Page::for_address(STACK_AREA_START);
         let stack_end = stack_start + (STACK_ALLOCATOR_PAGES - 1);
         let stack_range = Page::range(stack_start, stack_end);
 
         StackAllocator::new(stack_range)
     };
 
+    let user_stack_allocator = {
+        let stack_start = Page::for_address(USER_STACK_AREA_START);
+        let stack_end = stack_start + (USER_STACK_ALLOCATOR_PAGES - 1);
+        let stack_range = Page::range(stack_start, stack_end);
+
+        StackAllocator::new(stack_range)
+    };
+
     let controller = MemoryController::new(
         pml4.clone(),
         pml4,
         allocator,
         stack_allocator,
+        user_stack_allocator,
         slot_allocator,
         temp,
+        PcidAllocator::new(),
     );
 
     let mut guard = GLOBAL_MEMORY_CONTROLLER.lock();
@@ -2,7 +2,7 @@ use core::ptr;
 
 use multiboot2::{ElfSection, ElfSectionFlags};
 
-use crate::mem::PageFrame;
+use crate::mem::{PageFrame, PAGE_SIZE};
 
 bitflags! {
     #[derive(Clone, Copy, Debug)]
@@ -16,6 +16,10 @@ bitflags! {
         const DIRTY = 1 << 6;
         const HUGE_PAGE = 1 << 7;
         const GLOBAL = 1 << 8;
+
+        // available to the kernel, marks the non-present entry of
+        // a page that was swapped out
+        const SWAPPED = 1 << 9;
         const NO_EXECUTE = 1 << 63;
     }
 }
@@ -59,6 +63,27 @@ impl PageTableEntry {
         EntryFlags::from_bits_truncate(self.entry)
     }
 
+    /// Creates the non-present entry of a page that was swapped out.
+    ///
+    /// ## Arguments
+    ///
+    /// - `slot` the swap slot holding the page contents
+    pub fn swapped(slot: usize) -> Self {
+        Self {
+            entry: ((slot as u64) << 12) | EntryFlags::SWAPPED.bits(),
+        }
+    }
+
+    /// The swap slot of a page that was swapped out.
+    pub fn swap_slot(&self) -> Option<usize> {
+        let flags = self.flags();
+        if flags.contains(EntryFlags::PRESENT) || !flags.contains(EntryFlags::SWAPPED) {
+            return None;
+        }
+
+        Some(((self.entry & 0x000FFFFF_FFFFF000) >> 12) as usize)
+    }
+
     pub fn get_frame(&self) -> Option<PageFrame> {
         if self.flags().contains(EntryFlags::PRESENT) {
             Some(PageFrame::from_address(
@@ -69,6 +94,23 @@ impl PageTableEntry {
         }
     }
 
+    /// The entry a 4 KiB page inside a huge page would have, so callers
+    /// can look at single pages without caring how they are mapped. The
+    /// `HUGE_PAGE` flag is kept.
+    ///
+    /// ## Arguments
+    ///
+    /// - `index` the index of the 4 KiB page inside the huge page
+    pub fn huge_page_part(&self, index: usize) -> PageTableEntry {
+        // bit 12 of a huge page entry is the PAT bit, not an address bit
+        let base = self.entry & 0x000FFFFF_FFE00000;
+        let flags = self.entry & !0x000FFFFF_FFFFF000;
+
+        PageTableEntry {
+            entry: (base + (index * PAGE_SIZE) as u64) | flags,
+        }
+    }
+
     pub fn set(&mut self, frame: PageFrame, flags: EntryFlags) {
         // make sure we have a valid address, if not, this is an os bug
         let addr = frame.start_address();
@@ -1,8 +1,9 @@
 use core::ops::Add;
 
 use crate::mem::{
+    kernel_phys_addr,
     paging::{entry::EntryFlags, slot_allocator::PageTableSlotAllocator, temp_mapper::TempMapper},
-    PageFrame, PageFrameAllocator, VirtualAddress, PAGE_SIZE,
+    PageFrame, PageFrameAllocator, VirtualAddress, KERNEL_OFFSET, PAGE_SIZE,
 };
 use multiboot2::BootInformation;
 pub use page_table::PageTable;
@@ -12,6 +13,12 @@ mod page_table;
 pub mod slot_allocator;
 pub mod temp_mapper;
 
+/// Number of 4 KiB pages covered by a huge page.
+pub const PAGES_PER_HUGE_PAGE: usize = 512;
+
+/// Size of a huge page, mapped by a single PML2 entry.
+pub const HUGE_PAGE_SIZE: usize = PAGES_PER_HUGE_PAGE * 4096; // 2 MiB
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct Page {
     page_number: usize,
@@ -52,6 +59,18 @@ impl Page {
         (self.page_number >> 0) & 0o777
     }
 
+    /// The first page of the huge page containing this page.
+    pub fn huge_page_start(&self) -> Page {
+        Page {
+            page_number: self.page_number & !(PAGES_PER_HUGE_PAGE - 1),
+        }
+    }
+
+    /// Whether the page can start a huge page.
+    pub fn is_huge_aligned(&self) -> bool {
+        self.page_number % PAGES_PER_HUGE_PAGE == 0
+    }
+
     pub fn range(start: Page, end: Page) -> PageIter {
         PageIter {
             start: start,
@@ -88,8 +107,15 @@ impl Iterator for PageIter {
         self.start.page_number += 1;
         Some(frame)
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = (self.end.page_number + 1).saturating_sub(self.start.page_number);
+        (len, Some(len))
+    }
 }
 
+impl ExactSizeIterator for PageIter {}
+
 pub fn map_kernel<A>(
     allocator: &mut A,
     slot_allocator: &mut PageTableSlotAllocator,
@@ -99,18 +125,6 @@ pub fn map_kernel<A>(
 ) where
     A: PageFrameAllocator,
 {
-    let multiboot_start = PageFrame::from_address(boot_info.start_address());
-    let multiboot_end = PageFrame::from_address(boot_info.end_address() - 1);
-
-    pml4.map_range_identity(
-        multiboot_start,
-        multiboot_end,
-        EntryFlags::PRESENT,
-        allocator,
-        slot_allocator,
-        temp_mapper,
-    );
-
     let elf_sections = boot_info.elf_sections().unwrap();
     for section in elf_sections {
         if !section.is_allocated() {
@@ -118,25 +132,29 @@ pub fn map_kernel<A>(
             continue;
         }
 
+        if (section.start_address() as usize) < KERNEL_OFFSET {
+            // the boot code is only needed until the
+            // kernel runs in the upper half
+            continue;
+        }
+
         // check page alignment
         let aligned = (section.start_address() as usize) % PAGE_SIZE == 0;
         assert!(aligned, "ELF Sections need to be aligned to the page size");
 
-        // need to offset the end frame by one to prevent having the end frame
-        // and the starting frame of the next elf section from being the same
+        // need to offset the end page by one to prevent having the end page
+        // and the starting page of the next elf section from being the same
         // and the page already being used, thus failing an assert when mapping...
 
         let flags = EntryFlags::from_elf_section_flags(&section);
-        let start_frame = PageFrame::from_address(section.start_address() as usize);
-        let end_frame = PageFrame::from_address((section.end_address() - 1) as usize);
-
-        pml4.map_range_identity(
-            start_frame,
-            end_frame,
-            flags,
-            allocator,
-            slot_allocator,
-            temp_mapper,
-        );
+        let start_page = Page::for_address(section.start_address() as usize);
+        let end_page = Page::for_address((section.end_address() - 1) as usize);
+
+        for page in Page::range(start_page, end_page) {
+            let phys = kernel_phys_addr(page.start_address());
+            let frame = PageFrame::from_address(phys);
+
+            pml4.map_to(page, frame, flags, allocator, slot_allocator, temp_mapper);
+        }
     }
 }
@@ -1,3 +1,5 @@
+use core::ops::Range;
+
 use alloc::vec::Vec;
 
 use crate::log;
@@ -7,9 +9,9 @@ use crate::{
             entry::{EntryFlags, PageTableEntry},
             slot_allocator::PageTableSlotAllocator,
             temp_mapper::TempMapper,
-            Page,
+            Page, PAGES_PER_HUGE_PAGE,
         },
-        PageFrame, PageFrameAllocator, PAGE_TABLE_REGION_START,
+        pcid, PageFrame, PageFrameAllocator, PAGE_SIZE, PAGE_TABLE_REGION_START,
     },
     print,
 };
@@ -17,84 +19,17 @@ use crate::{
 #[derive(Clone)]
 pub struct PageTable {
     pub addr: usize,
+
+    /// The PCID its TLB entries are tagged with, 0 for tables without
+    /// their own one.
+    pub pcid: u16,
 }
 
 impl PageTable {
     pub fn new(addr: usize) -> Self {
-        Self { addr: addr }
-    }
-
-    /// Maps a page to its exact corresponding page frame
-    ///
-    /// ## Arguments
-    ///
-    /// - `frame` the page frame for the page to be mapped on to
-    /// - `flags` the page table entry flags to be used
-    /// - `allocator` needs a page frame allocator to create
-    /// page tables
-    pub fn map_identity<A>(
-        &mut self,
-        frame: PageFrame,
-        flags: EntryFlags,
-        alloc: &mut A,
-        slot_alloc: &mut PageTableSlotAllocator,
-        temp_mapper: &mut TempMapper,
-    ) where
-        A: PageFrameAllocator,
-    {
-        let page = Page::for_address(frame.start_address());
-        self.map_to(page, frame, flags, alloc, slot_alloc, temp_mapper);
-    }
-
-    /// Maps a range of pages to unused page frames
-    ///
-    /// ## Arguments
-    ///
-    /// - `start` the start page frame for the mapping
-    /// - `end` the end page frame for the mapping
-    /// - `flags` the page table entry flags to be used
-    /// - `allocator` needs a page frame allocator to create
-    /// page tables
-    pub fn map_range<A>(
-        &mut self,
-        start: Page,
-        end: Page,
-        flags: EntryFlags,
-        alloc: &mut A,
-        slot_alloc: &mut PageTableSlotAllocator,
-        temp_mapper: &mut TempMapper,
-    ) where
-        A: PageFrameAllocator,
-    {
-        let range = Page::range(start, end);
-        for page in range {
-            self.map(page, flags, alloc, slot_alloc, temp_mapper);
-        }
-    }
-
-    /// Maps a range of pages to its exact corresponding page frame
-    ///
-    /// ## Arguments
-    ///
-    /// - `start` the start page frame for the mapping
-    /// - `end` the end page frame for the mapping
-    /// - `flags` the page table entry flags to be used
-    /// - `allocator` needs a page frame allocator to create
-    /// page tables
-    pub fn map_range_identity<A>(
-        &mut self,
-        start: PageFrame,
-        end: PageFrame,
-        flags: EntryFlags,
-        alloc: &mut A,
-        slot_alloc: &mut PageTableSlotAllocator,
-        temp_mapper: &mut TempMapper,
-    ) where
-        A: PageFrameAllocator,
-    {
-        let range = PageFrame::range(start, end);
-        for frame in range {
-            self.map_identity(frame, flags, alloc, slot_alloc, temp_mapper);
+        Self {
+            addr: addr,
+            pcid: 0,
         }
     }
 
@@ -192,8 +127,73 @@ impl PageTable {
         }
     }
 
+    /// Maps a 2 MiB huge page to 512 contiguous page frames with a single
+    /// PML2 entry.
+    ///
+    /// ## Arguments
+    ///
+    /// - `page` the first page of the huge page, 2 MiB aligned
+    /// - `frame` the first frame to map to, 2 MiB aligned
+    /// - `flags` the page table entry flags to be used
+    /// - `allocator` needs a page frame allocator to create
+    /// page tables
+    ///
+    /// ## Returns
+    /// `None` when the page or frame isn't aligned, or part of the 2 MiB
+    /// range is already mapped with 4 KiB pages.
+    pub fn map_huge_to<A>(
+        &mut self,
+        page: Page,
+        frame: PageFrame,
+        flags: EntryFlags,
+        alloc: &mut A,
+        slot_alloc: &mut PageTableSlotAllocator,
+        temp_mapper: &mut TempMapper,
+    ) -> Option<()>
+    where
+        A: PageFrameAllocator,
+    {
+        if !page.is_huge_aligned() || frame.frame_number % PAGES_PER_HUGE_PAGE != 0 {
+            return None;
+        }
+
+        let is_phys = self.is_phys_identity();
+
+        let mut table_flags = EntryFlags::PRESENT | EntryFlags::WRITABLE;
+        if flags.contains(EntryFlags::RING3_ACCESSIBLE) {
+            table_flags |= EntryFlags::RING3_ACCESSIBLE;
+        }
+
+        let mut pml3 = self.next_table_create(
+            page.p4_index(),
+            is_phys,
+            alloc,
+            slot_alloc,
+            temp_mapper,
+            table_flags,
+        );
+
+        let mut pml2 = pml3.next_table_create(
+            page.p3_index(),
+            is_phys,
+            alloc,
+            slot_alloc,
+            temp_mapper,
+            table_flags,
+        );
+
+        let entry = &mut pml2.entries_mut()[page.p2_index()];
+        if !entry.is_unused() {
+            return None;
+        }
+
+        entry.set(frame, flags | EntryFlags::PRESENT | EntryFlags::HUGE_PAGE);
+        Some(())
+    }
+
     /// Removes the page mapping, frees all frames contained
-    /// in the page
+    /// in the page. Pages inside a huge page are left alone, those are
+    /// unmapped with [`Self::unmap_huge`] or split first.
     ///
     /// ## Arguments
     ///
@@ -214,10 +214,95 @@ impl PageTable {
         let entry = &mut pml1.entries_mut()[p1_index];
         let frame = entry.get_frame()?;
         entry.set_to_unused();
+        pcid::mapping_changed(page.start_address());
 
         Some(frame)
     }
 
+    /// Removes a huge page mapping, the TLB entry of the page must be
+    /// flushed afterwards.
+    ///
+    /// ## Arguments
+    ///
+    /// - `page` any page inside the huge page
+    /// - `temp_mapper` a reference to the global temporary page mapping manager
+    ///
+    /// ## Returns
+    /// The first of the 512 frames the huge page was mapped to, or `None`
+    /// when the page isn't part of a huge page.
+    pub fn unmap_huge(&mut self, page: Page, temp_mapper: &mut TempMapper) -> Option<PageFrame> {
+        let pml3 = self.next_table_temp(page.p4_index(), temp_mapper)?;
+        let mut pml2 = pml3.next_table_temp(page.p3_index(), temp_mapper)?;
+
+        let entry = &mut pml2.entries_mut()[page.p2_index()];
+        if !Self::is_huge_entry(entry) {
+            return None;
+        }
+
+        let frame = entry.huge_page_part(0).get_frame()?;
+        entry.set_to_unused();
+        pcid::mapping_changed(page.start_address());
+
+        Some(frame)
+    }
+
+    /// Replaces a huge page with a PML1 table mapping the same frames as
+    /// 512 pages of 4 KiB, so parts of it can be unmapped or changed. The
+    /// TLB entry of the page must be flushed afterwards.
+    ///
+    /// ## Arguments
+    ///
+    /// - `page` any page inside the huge page
+    /// - `allocator` needs a page frame allocator to create
+    /// the page table
+    ///
+    /// ## Returns
+    /// `None` when the page isn't part of a huge page.
+    pub fn split_huge<A>(
+        &mut self,
+        page: Page,
+        alloc: &mut A,
+        slot_alloc: &mut PageTableSlotAllocator,
+        temp_mapper: &mut TempMapper,
+    ) -> Option<()>
+    where
+        A: PageFrameAllocator,
+    {
+        let pml3 = self.next_table_temp(page.p4_index(), temp_mapper)?;
+        let mut pml2 = pml3.next_table_temp(page.p3_index(), temp_mapper)?;
+
+        let entry = &mut pml2.entries_mut()[page.p2_index()];
+        if !Self::is_huge_entry(entry) {
+            return None;
+        }
+
+        let huge_entry = entry.clone();
+        entry.set_to_unused();
+
+        let flags = huge_entry.flags() - EntryFlags::HUGE_PAGE;
+        let mut table_flags = EntryFlags::PRESENT | EntryFlags::WRITABLE;
+        if flags.contains(EntryFlags::RING3_ACCESSIBLE) {
+            table_flags |= EntryFlags::RING3_ACCESSIBLE;
+        }
+
+        let is_phys = self.is_phys_identity();
+        let mut pml1 = pml2.next_table_create(
+            page.p2_index(),
+            is_phys,
+            alloc,
+            slot_alloc,
+            temp_mapper,
+            table_flags,
+        );
+
+        for index in 0..PAGES_PER_HUGE_PAGE {
+            let frame = huge_entry.huge_page_part(index).get_frame()?;
+            pml1.set(index, frame, flags);
+        }
+
+        Some(())
+    }
+
     /// Frees all user-accessible child page table slots under this page table.
     ///
     /// This only walks entries marked as ring 3 accessible, so cloned kernel
@@ -232,6 +317,20 @@ impl PageTable {
         slot_allocator: &mut PageTableSlotAllocator,
         temp_mapper: &mut TempMapper,
     ) {
+        for frame in self.user_subtable_frames(temp_mapper) {
+            if let Some(addr) = slot_allocator.addr_for_frame(frame, temp_mapper) {
+                slot_allocator.free(addr);
+            }
+        }
+    }
+
+    /// Lists the frames of all user-accessible child page tables under this
+    /// page table, the ones [`Self::free_user_subtables`] frees.
+    ///
+    /// ## Arguments
+    ///
+    /// - `temp_mapper` a reference to the global temporary page mapping manager
+    pub fn user_subtable_frames(&self, temp_mapper: &mut TempMapper) -> Vec<PageFrame> {
         let mut table_frames = Vec::new();
 
         for p4_index in 0..512 {
@@ -275,11 +374,103 @@ impl PageTable {
             }
         }
 
-        for frame in table_frames {
-            if let Some(addr) = slot_allocator.addr_for_frame(frame, temp_mapper) {
-                slot_allocator.free(addr);
+        table_frames
+    }
+
+    /// Lists the leaf entries under the user-accessible child page tables
+    /// of this page table: mapped pages, huge pages and swapped out pages.
+    ///
+    /// ## Arguments
+    ///
+    /// - `temp_mapper` a reference to the global temporary page mapping manager
+    ///
+    /// ## Returns
+    /// Every page with a used entry, the first page for huge pages, together
+    /// with its entry.
+    pub fn user_leaf_entries(&self, temp_mapper: &mut TempMapper) -> Vec<(Page, PageTableEntry)> {
+        let mut leaves = Vec::new();
+
+        for p4_index in 0..512 {
+            let p4_entry = self.entries()[p4_index].clone();
+            if !Self::is_user_table_entry(&p4_entry) {
+                continue;
+            }
+
+            let Some(pml3_frame) = p4_entry.get_frame() else {
+                continue;
+            };
+
+            let pml3_entries = PageTable::new(temp_mapper.set(pml3_frame))
+                .entries()
+                .to_vec();
+            for (p3_index, pml3_entry) in pml3_entries.iter().enumerate() {
+                if !Self::is_user_table_entry(pml3_entry) {
+                    continue;
+                }
+
+                let Some(pml2_frame) = pml3_entry.get_frame() else {
+                    continue;
+                };
+
+                let pml2_entries = PageTable::new(temp_mapper.set(pml2_frame))
+                    .entries()
+                    .to_vec();
+                for (p2_index, pml2_entry) in pml2_entries.iter().enumerate() {
+                    let addr = (p4_index << 39) | (p3_index << 30) | (p2_index << 21);
+                    if Self::is_huge_entry(pml2_entry) {
+                        leaves.push((Page::for_address(addr), pml2_entry.clone()));
+                        continue;
+                    }
+
+                    if !Self::is_user_table_entry(pml2_entry) {
+                        continue;
+                    }
+
+                    let Some(pml1_frame) = pml2_entry.get_frame() else {
+                        continue;
+                    };
+
+                    let pml1_entries = PageTable::new(temp_mapper.set(pml1_frame))
+                        .entries()
+                        .to_vec();
+                    for (p1_index, pml1_entry) in pml1_entries.iter().enumerate() {
+                        if !pml1_entry.is_unused() {
+                            let page = Page::for_address(addr | (p1_index * PAGE_SIZE));
+                            leaves.push((page, pml1_entry.clone()));
+                        }
+                    }
+                }
             }
         }
+
+        leaves
+    }
+
+    /// Copies the entries leading to user pages from another table of the
+    /// same level, the other entries in the range are cleared. The child
+    /// tables are shared, not copied.
+    ///
+    /// ## Arguments
+    ///
+    /// - `source` the table to copy the entries from
+    /// - `indices` the entry indices to copy
+    pub fn copy_user_entries(&mut self, source: &PageTable, indices: Range<usize>) {
+        let source_entries = source.entries();
+        let entries = self.entries_mut();
+
+        for index in indices {
+            let source_entry = &source_entries[index];
+            entries[index] = if Self::is_user_table_entry(source_entry) {
+                source_entry.clone()
+            } else {
+                PageTableEntry { entry: 0 }
+            };
+        }
+    }
+
+    fn is_huge_entry(entry: &PageTableEntry) -> bool {
+        let flags = entry.flags();
+        flags.contains(EntryFlags::PRESENT) && flags.contains(EntryFlags::HUGE_PAGE)
     }
 
     fn is_user_table_entry(entry: &PageTableEntry) -> bool {
@@ -300,6 +491,10 @@ impl PageTable {
         let is_mapped = (|| -> Option<()> {
             let pml3 = self.next_table_temp(page.p4_index(), temp_mapper)?;
             let pml2 = pml3.next_table_temp(page.p3_index(), temp_mapper)?;
+            if Self::is_huge_entry(&pml2.entries()[page.p2_index()]) {
+                return Some(());
+            }
+
             let pml1 = pml2.next_table_temp(page.p2_index(), temp_mapper)?;
 
             let entry = &pml1.entries()[page.p1_index()];
@@ -323,23 +518,93 @@ impl PageTable {
         addr: usize,
         temp_mapper: &mut TempMapper,
     ) -> Option<PageFrame> {
-        // need to walk the page table
         let page = Page::for_address(addr);
+        self.get_entry(page, temp_mapper)?.get_frame()
+    }
 
-        let p4_index = page.p4_index();
-        let pml3 = self.next_table_temp(p4_index, temp_mapper)?;
+    /// Reads the PML1 entry of a page. For a page inside a huge page,
+    /// the entry it would have as a 4 KiB page is returned, with the
+    /// `HUGE_PAGE` flag set.
+    ///
+    /// ## Arguments
+    ///
+    /// - `page` the page to look up
+    /// - `temp_mapper` a reference to the global temporary page mapping manager
+    ///
+    /// ## Returns
+    /// A copy of the entry, or `None` when a table level is missing.
+    pub fn get_entry(&self, page: Page, temp_mapper: &mut TempMapper) -> Option<PageTableEntry> {
+        let mut found = None;
+        self.walk_range_entries(page.start_address(), PAGE_SIZE, temp_mapper, |_, entry| {
+            found = Some(entry.clone());
+            Some(())
+        })?;
+
+        found
+    }
 
-        let p3_index = page.p3_index();
-        let pml2 = pml3.next_table_temp(p3_index, temp_mapper)?;
+    /// Replaces the PML1 entry of a page, the TLB entry of the page must
+    /// be flushed afterwards.
+    ///
+    /// ## Arguments
+    ///
+    /// - `page` the page to update
+    /// - `entry` the new entry
+    /// - `temp_mapper` a reference to the global temporary page mapping manager
+    ///
+    /// ## Returns
+    /// The previous entry, or `None` when a table level is missing.
+    pub fn replace_entry(
+        &mut self,
+        page: Page,
+        entry: PageTableEntry,
+        temp_mapper: &mut TempMapper,
+    ) -> Option<PageTableEntry> {
+        let pml3 = self.next_table_temp(page.p4_index(), temp_mapper)?;
+        let pml2 = pml3.next_table_temp(page.p3_index(), temp_mapper)?;
+        let mut pml1 = pml2.next_table_temp(page.p2_index(), temp_mapper)?;
 
-        let p2_index = page.p2_index();
-        let pml1 = pml2.next_table_temp(p2_index, temp_mapper)?;
+        let slot = &mut pml1.entries_mut()[page.p1_index()];
+        let previous = slot.clone();
+        unsafe { core::ptr::write_volatile(&mut slot.entry, entry.entry) };
+        pcid::mapping_changed(page.start_address());
 
-        let p1_index = page.p1_index();
-        pml1.get_frame(p1_index)
+        Some(previous)
+    }
+
+    /// Clears flags of a mapped page, like the accessed and dirty bits
+    /// the CPU sets. The TLB entry of the page must be flushed afterwards.
+    ///
+    /// ## Arguments
+    ///
+    /// - `page` the mapped page
+    /// - `flags` the flags to be cleared
+    /// - `temp_mapper` a reference to the global temporary page mapping manager
+    ///
+    /// ## Returns
+    /// The flags of the page before clearing, or `None` when it is not mapped.
+    pub fn clear_flags(
+        &mut self,
+        page: Page,
+        flags: EntryFlags,
+        temp_mapper: &mut TempMapper,
+    ) -> Option<EntryFlags> {
+        let pml3 = self.next_table_temp(page.p4_index(), temp_mapper)?;
+        let pml2 = pml3.next_table_temp(page.p3_index(), temp_mapper)?;
+        let mut pml1 = pml2.next_table_temp(page.p2_index(), temp_mapper)?;
+
+        let entry = &mut pml1.entries_mut()[page.p1_index()];
+        let frame = entry.get_frame()?;
+        let old_flags = entry.flags();
+        entry.set(frame, old_flags - flags);
+        pcid::mapping_changed(page.start_address());
+
+        Some(old_flags)
     }
 
-    /// Walks every PML1 entry touched by a virtual address range.
+    /// Walks every PML1 entry touched by a virtual address range. Pages
+    /// inside a huge page get the entry they would have as 4 KiB pages,
+    /// like [`Self::get_entry`] returns.
     ///
     /// ## Arguments
     ///
@@ -372,6 +637,12 @@ impl PageTable {
         for page in Page::range(start_page, end_page) {
             let pml3 = self.next_table_temp(page.p4_index(), temp_mapper)?;
             let pml2 = pml3.next_table_temp(page.p3_index(), temp_mapper)?;
+            let pml2_entry = &pml2.entries()[page.p2_index()];
+            if Self::is_huge_entry(pml2_entry) {
+                callback(page, &pml2_entry.huge_page_part(page.p1_index()))?;
+                continue;
+            }
+
             let pml1 = pml2.next_table_temp(page.p2_index(), temp_mapper)?;
             let entry = &pml1.entries()[page.p1_index()];
 
@@ -388,11 +659,15 @@ impl PageTable {
     ///
     /// - `index` the index of the current table to get the next table from
     /// - `temp_mapper` a reference to the global temporary page mapping manager
+    ///
+    /// ## Returns
+    /// The next table, or `None` when the entry is unused or maps a huge
+    /// page instead of a table.
     pub fn next_table_temp(&self, index: usize, temp_mapper: &mut TempMapper) -> Option<PageTable> {
         let entries = self.entries();
         let entry = &entries[index];
 
-        let unused = entry.is_unused();
+        let unused = entry.is_unused() || Self::is_huge_entry(entry);
         if unused {
             None
         } else {
@@ -408,12 +683,6 @@ impl PageTable {
         entry.set(frame, flags);
     }
 
-    pub fn get_frame(&self, index: usize) -> Option<PageFrame> {
-        let entries = self.entries();
-        let entry = &entries[index];
-        entry.get_frame()
-    }
-
     /// Determines whether the page table is mapped referencing
     /// a virtual address page table slot, or an identity-mapped
     /// physical frame.
@@ -515,12 +784,18 @@ impl PageTable {
         let entry = &mut entries[index];
 
         if !entry.is_unused() {
+            // a huge page would be taken for a table, split it first
+            let existing_flags = entry.flags();
+            assert!(
+                !existing_flags.contains(EntryFlags::HUGE_PAGE),
+                "Cannot map a page inside a huge page"
+            );
+
             // ring 3 accesses require the RING3_ACCESSIBLE flag on every
             // level of the table walk, so an existing intermediate entry
             // must be upgraded when a user page is mapped beneath it. the
             // leaf entries remain authoritative for per-page protection.
-            let existing_flags = entry.flags();
-            if !existing_flags.contains(flags) && !existing_flags.contains(EntryFlags::HUGE_PAGE) {
+            if !existing_flags.contains(flags) {
                 if let Some(frame) = entry.get_frame() {
                     entry.set(frame, existing_flags | flags);
                 }
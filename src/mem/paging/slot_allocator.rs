@@ -99,7 +99,7 @@ impl PageTableSlotAllocator {
         }
 
         let region_end = self.region_start + (self.last_pml1_slot * PAGE_SIZE);
-        if addr >= region_end || self.free_slots.contains(&addr) {
+        if addr >= region_end || self.is_free(addr) {
             return;
         }
 
@@ -107,6 +107,20 @@ impl PageTableSlotAllocator {
         self.allocated_page_tables = self.allocated_page_tables.saturating_sub(1);
     }
 
+    /// Number of freed page table slots waiting to be reused.
+    pub fn free_slot_count(&self) -> usize {
+        self.free_slots.len()
+    }
+
+    /// Whether a page table slot was freed and not handed out again.
+    ///
+    /// ## Arguments
+    ///
+    /// - `addr` the virtual address of the page table slot
+    pub fn is_free(&self, addr: usize) -> bool {
+        self.free_slots.contains(&addr)
+    }
+
     /// Finds the virtual page table slot that maps to a physical frame.
     ///
     /// ## Arguments
@@ -48,6 +48,13 @@ impl TempMapper {
         self.temp_addr
     }
 
+    /// Drops the temporary page from the TLB of the active PCID. It is
+    /// remapped far too often to make every PCID stale each time, so
+    /// switching to a PCID that keeps its TLB entries flushes it instead.
+    pub fn flush(&self) {
+        tlb::flush(VirtAddr::new(self.temp_addr as u64));
+    }
+
     pub fn get_current_phys(&self) -> Option<PageFrame> {
         // TODO: maybe cache this?
 
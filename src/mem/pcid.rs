@@ -0,0 +1,183 @@
+use core::{
+    arch::{asm, x86_64::__cpuid},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use x86_64::registers::control::{Cr4, Cr4Flags};
+
+use crate::{io::LogType, log};
+
+use super::{PhysicalAddress, VirtualAddress, PHYS_MAP_START};
+
+/// CPUID leaf 1 ECX bit telling whether process-context identifiers exist.
+const CPUID_PCID: u32 = 1 << 17;
+
+/// Number of PCIDs handed out to process page tables. With page table
+/// isolation, the user page table of a process uses its PCID with
+/// `USER_PCID_BIT` set.
+pub const PCID_COUNT: usize = 0x800;
+
+pub const USER_PCID_BIT: u16 = 0x800;
+
+/// CR3 bit keeping the TLB entries of the PCID being switched to.
+pub const CR3_NOFLUSH: u64 = 1 << 63;
+
+static PCID_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Set when a kernel mapping changed, the TLB entries of every PCID may
+/// still hold the old one.
+static KERNEL_MAPPINGS_CHANGED: AtomicBool = AtomicBool::new(false);
+
+/// Turns PCIDs on when the CPU has them, must run while CR3 holds PCID 0.
+pub fn init() {
+    let supported = unsafe { __cpuid(1) }.ecx & CPUID_PCID != 0;
+    if supported {
+        unsafe { Cr4::update(|flags| flags.insert(Cr4Flags::PCID)) };
+        PCID_ENABLED.store(true, Ordering::SeqCst);
+    }
+
+    log!(LogType::OK, "Process-context identifiers: {}", supported);
+}
+
+/// Whether TLB entries are tagged with the PCID in CR3.
+pub fn is_enabled() -> bool {
+    PCID_ENABLED.load(Ordering::SeqCst)
+}
+
+/// Computes the CR3 value of a page table.
+///
+/// ## Arguments
+///
+/// - `phys` the physical address of the top level table
+/// - `pcid` the PCID its TLB entries are tagged with
+/// - `noflush` whether TLB entries already tagged with the PCID are kept
+pub fn cr3_value(phys: PhysicalAddress, pcid: u16, noflush: bool) -> u64 {
+    if !is_enabled() {
+        return phys as u64;
+    }
+
+    let noflush = if noflush { CR3_NOFLUSH } else { 0 };
+    phys as u64 | pcid as u64 | noflush
+}
+
+/// Makes every PCID flush its TLB entries when it is switched to next, when
+/// the changed page belongs to the kernel half. Kernel mappings are shared
+/// by all page tables but aren't global, so flushing the page only drops
+/// it from the active PCID.
+///
+/// ## Arguments
+///
+/// - `addr` the address of the page whose mapping changed or went away
+pub fn mapping_changed(addr: VirtualAddress) {
+    // the kernel half starts with the physical memory map
+    if addr >= PHYS_MAP_START {
+        KERNEL_MAPPINGS_CHANGED.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Switches to a page table, see [`cr3_value`].
+pub unsafe fn write_cr3(phys: PhysicalAddress, pcid: u16, noflush: bool) {
+    let value = cr3_value(phys, pcid, noflush);
+    asm!("mov cr3, {}", in(reg) value, options(nostack, preserves_flags));
+}
+
+/// Hands out the PCIDs of process page tables and tracks which of them
+/// still have TLB entries that went stale while their table wasn't active.
+///
+/// PCID 0 belongs to the kernel page table and to tables that didn't get
+/// their own one, switching to them always flushes.
+pub struct PcidAllocator {
+    used: [u64; PCID_COUNT / 64],
+    stale: [u64; 2 * PCID_COUNT / 64],
+}
+
+impl PcidAllocator {
+    pub const fn new() -> Self {
+        let mut used = [0; PCID_COUNT / 64];
+        used[0] = 1;
+
+        Self {
+            used: used,
+            stale: [0; 2 * PCID_COUNT / 64],
+        }
+    }
+
+    /// Allocates an unused PCID.
+    ///
+    /// ## Returns
+    /// The PCID, or `None` when all of them are in use.
+    pub fn alloc(&mut self) -> Option<u16> {
+        let pcid = (1..PCID_COUNT).find(|&pcid| !test_bit(&self.used, pcid))?;
+        set_bit(&mut self.used, pcid, true);
+
+        // the TLB may still hold entries of the table that had it before
+        let pcid = pcid as u16;
+        self.invalidate(pcid);
+
+        Some(pcid)
+    }
+
+    /// Returns a PCID once its page table is freed.
+    ///
+    /// ## Arguments
+    ///
+    /// - `pcid` the PCID to free
+    pub fn free(&mut self, pcid: u16) {
+        let pcid = (pcid & !USER_PCID_BIT) as usize;
+        if pcid != 0 {
+            set_bit(&mut self.used, pcid, false);
+        }
+    }
+
+    /// Marks the TLB entries of a page table as stale, after its mappings
+    /// changed while another table was active. Covers the user page table
+    /// of the process as well.
+    ///
+    /// ## Arguments
+    ///
+    /// - `pcid` the PCID of the page table
+    pub fn invalidate(&mut self, pcid: u16) {
+        let pcid = (pcid & !USER_PCID_BIT) as usize;
+        if pcid != 0 {
+            set_bit(&mut self.stale, pcid, true);
+            set_bit(&mut self.stale, pcid | USER_PCID_BIT as usize, true);
+        }
+    }
+
+    /// Checks whether switching to a PCID must flush its TLB entries, and
+    /// clears the mark since the switch does so.
+    ///
+    /// ## Arguments
+    ///
+    /// - `pcid` the PCID being switched to
+    ///
+    /// ## Returns
+    /// Whether the TLB entries of the PCID are stale, always true for 0.
+    pub fn take_stale(&mut self, pcid: u16) -> bool {
+        if KERNEL_MAPPINGS_CHANGED.swap(false, Ordering::SeqCst) {
+            self.stale = [u64::MAX; 2 * PCID_COUNT / 64];
+        }
+
+        let pcid = pcid as usize;
+        if pcid & !(USER_PCID_BIT as usize) == 0 {
+            return true;
+        }
+
+        let stale = test_bit(&self.stale, pcid);
+        set_bit(&mut self.stale, pcid, false);
+
+        stale
+    }
+}
+
+fn test_bit(bits: &[u64], index: usize) -> bool {
+    bits[index / 64] & (1 << (index % 64)) != 0
+}
+
+fn set_bit(bits: &mut [u64], index: usize, value: bool) {
+    if value {
+        bits[index / 64] |= 1 << (index % 64);
+    } else {
+        bits[index / 64] &= !(1 << (index % 64));
+    }
+}
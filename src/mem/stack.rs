@@ -1,3 +1,21 @@
+use spin::Mutex;
+
+use super::PAGE_SIZE;
+
+/// Maximum number of kernel stacks whose guard pages are tracked.
+const MAX_KERNEL_STACKS: usize = 8;
+
+/// A named kernel stack, so a hit on its guard page can be traced back to it.
+struct KernelStack {
+    name: &'static str,
+    stack: Stack,
+}
+
+// a fixed table instead of a Vec, the boot stack is registered before
+// the heap exists and the double fault handler must not allocate
+static KERNEL_STACKS: Mutex<[Option<KernelStack>; MAX_KERNEL_STACKS]> =
+    Mutex::new([const { None }; MAX_KERNEL_STACKS]);
+
 #[derive(Clone, Debug)]
 pub struct Stack {
     pub top: usize,
@@ -8,4 +26,50 @@ impl Stack {
     pub fn new(top: usize, bottom: usize) -> Stack {
         Stack { top, bottom }
     }
+
+    /// Address of the unmapped page right below the stack, touching it
+    /// means the stack overflowed.
+    pub fn guard_page(&self) -> usize {
+        self.bottom - PAGE_SIZE
+    }
+}
+
+/// Remembers a kernel stack, so overflowing it can be reported by name.
+///
+/// ## Arguments
+///
+/// - `name` the name used when reporting an overflow
+/// - `stack` the stack, its guard page must be unmapped
+pub fn register_kernel_stack(name: &'static str, stack: &Stack) {
+    let mut stacks = KERNEL_STACKS.lock();
+    let Some(slot) = stacks.iter_mut().find(|slot| slot.is_none()) else {
+        return;
+    };
+
+    *slot = Some(KernelStack {
+        name: name,
+        stack: stack.clone(),
+    });
+}
+
+/// Finds the kernel stack whose guard page contains an address.
+///
+/// Meant for fault handlers, so it gives up instead of waiting when the
+/// stack table is locked.
+///
+/// ## Arguments
+///
+/// - `addr` the faulting address
+///
+/// ## Returns
+/// The name of the overflowed stack and its guard page address.
+pub fn find_guard_page_hit(addr: usize) -> Option<(&'static str, usize)> {
+    let stacks = KERNEL_STACKS.try_lock()?;
+
+    stacks.iter().flatten().find_map(|kernel_stack| {
+        let guard = kernel_stack.stack.guard_page();
+        (guard..guard + PAGE_SIZE)
+            .contains(&addr)
+            .then_some((kernel_stack.name, guard))
+    })
 }
@@ -38,20 +38,34 @@ impl StackAllocator {
         pages_to_alloc: usize,
         flags: EntryFlags,
     ) -> Option<Stack> {
-        if pages_to_alloc == 0 {
+        let stack = self.reserve(pages_to_alloc)?;
+
+        let start = Page::for_address(stack.bottom);
+        let end = Page::for_address(stack.top - 1);
+        for page in Page::range(start, end) {
+            table.map(page, flags, frame_allocator, slot_alloc, temp_mapper);
+        }
+
+        Some(stack)
+    }
+
+    /// Reserves the virtual range of a stack right above a guard page,
+    /// without mapping any of its pages.
+    ///
+    /// ## Arguments
+    ///
+    /// - `pages_to_reserve` the number of stack pages, excluding the guard page
+    pub fn reserve(&mut self, pages_to_reserve: usize) -> Option<Stack> {
+        if pages_to_reserve == 0 {
             return None;
         }
 
         if let Some(index) = self
             .free_ranges
             .iter()
-            .position(|range| range.user_pages == pages_to_alloc)
+            .position(|range| range.user_pages == pages_to_reserve)
         {
             let range = self.free_ranges.remove(index);
-            for page in Page::range(range.start, range.end) {
-                table.map(page, flags, frame_allocator, slot_alloc, temp_mapper);
-            }
-
             let stack_top = range.end.start_address() + PAGE_SIZE;
             return Some(Stack::new(stack_top, range.start.start_address()));
         }
@@ -60,12 +74,12 @@ impl StackAllocator {
         let guard = range.next();
         let start = range.next();
 
-        let end = if pages_to_alloc == 1 {
+        let end = if pages_to_reserve == 1 {
             start
         } else {
             // index starts at 0 and we've already
-            // allocated the start page
-            range.nth(pages_to_alloc - 2)
+            // reserved the start page
+            range.nth(pages_to_reserve - 2)
         };
 
         match (guard, start, end) {
@@ -73,10 +87,6 @@ impl StackAllocator {
                 // update range to also include guard page
                 self.range = range;
 
-                for page in Page::range(start, end) {
-                    table.map(page, flags, frame_allocator, slot_alloc, temp_mapper);
-                }
-
                 let stack_top = end.start_address() + PAGE_SIZE;
                 let stack = Stack::new(stack_top, start.start_address());
                 Some(stack)
@@ -105,4 +115,19 @@ impl StackAllocator {
             user_pages: user_pages,
         });
     }
+
+    /// Number of pages, guard pages included, that were never reserved.
+    pub fn remaining_pages(&self) -> usize {
+        self.range.len()
+    }
+
+    /// Number of freed stack ranges waiting to be reused.
+    pub fn free_range_count(&self) -> usize {
+        self.free_ranges.len()
+    }
+
+    /// Number of stack pages in the freed ranges, guard pages excluded.
+    pub fn free_range_pages(&self) -> usize {
+        self.free_ranges.iter().map(|range| range.user_pages).sum()
+    }
 }
@@ -0,0 +1,71 @@
+use super::{
+    heap::{self, HeapStats},
+    swap::GLOBAL_SWAP,
+    GLOBAL_MEMORY_CONTROLLER,
+};
+
+/// Snapshot of the kernel memory counters.
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryStats {
+    pub total_frames: usize,
+    pub free_frames: usize,
+    pub used_frames: usize,
+
+    pub heap: HeapStats,
+
+    /// Pages of the stack area that were never handed out.
+    pub stack_remaining_pages: usize,
+    pub stack_free_ranges: usize,
+    pub stack_free_range_pages: usize,
+
+    /// Page tables currently in use, and freed slots kept for reuse.
+    pub page_table_pages: usize,
+    pub page_table_free_slots: usize,
+
+    pub swap_slots: usize,
+    pub swap_used_slots: usize,
+}
+
+impl MemoryStats {
+    /// Reads the counters of every kernel allocator.
+    ///
+    /// Must be called without the memory controller held.
+    pub fn collect() -> Self {
+        // read on its own, growing the heap takes the memory controller
+        let heap = heap::heap_stats();
+
+        let mut stats = Self {
+            total_frames: 0,
+            free_frames: 0,
+            used_frames: 0,
+            heap: heap,
+            stack_remaining_pages: 0,
+            stack_free_ranges: 0,
+            stack_free_range_pages: 0,
+            page_table_pages: 0,
+            page_table_free_slots: 0,
+            swap_slots: 0,
+            swap_used_slots: 0,
+        };
+
+        if let Some(mc) = GLOBAL_MEMORY_CONTROLLER.lock().as_ref() {
+            stats.total_frames = mc.frame_allocator.total_count();
+            stats.free_frames = mc.frame_allocator.free_count();
+            stats.used_frames = mc.frame_allocator.used_count();
+
+            stats.stack_remaining_pages = mc.stack_allocator.remaining_pages();
+            stats.stack_free_ranges = mc.stack_allocator.free_range_count();
+            stats.stack_free_range_pages = mc.stack_allocator.free_range_pages();
+
+            stats.page_table_pages = mc.slot_allocator.allocated_page_tables;
+            stats.page_table_free_slots = mc.slot_allocator.free_slot_count();
+        }
+
+        if let Some(swap) = GLOBAL_SWAP.lock().as_ref() {
+            stats.swap_slots = swap.slot_count();
+            stats.swap_used_slots = swap.used_count();
+        }
+
+        stats
+    }
+}
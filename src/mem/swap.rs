@@ -0,0 +1,153 @@
+use alloc::{sync::Arc, vec, vec::Vec};
+use spin::{Mutex, RwLock};
+
+use crate::{fs::fs::File, io::LogType, log};
+
+use super::PAGE_SIZE;
+
+/// Name of the swap file in the root directory of the boot volume.
+pub const SWAP_FILE_NAME: &str = "swap";
+
+/// Cold pages are evicted once fewer frames than this are free.
+pub const SWAP_LOW_WATERMARK: usize = 512; // 2 MiB
+
+/// Maximum number of pages evicted at once.
+pub const SWAP_BATCH_PAGES: usize = 32;
+
+pub static GLOBAL_SWAP: Mutex<Option<SwapSpace>> = Mutex::new(None);
+
+/// Page-sized slots of a swap file, each holding one evicted user page.
+pub struct SwapSpace {
+    file: Arc<RwLock<dyn File>>,
+
+    /// One bit per slot, set while the slot holds a page.
+    slots: Vec<u64>,
+    slot_count: usize,
+    used_slots: usize,
+}
+
+impl SwapSpace {
+    /// Creates a swap space over the whole size of a file.
+    ///
+    /// ## Arguments
+    ///
+    /// - `file` the preallocated swap file
+    pub fn new(file: Arc<RwLock<dyn File>>) -> Self {
+        let slot_count = file.read().size() / PAGE_SIZE;
+
+        Self {
+            file: file,
+            slots: vec![0; slot_count.div_ceil(64)],
+            slot_count: slot_count,
+            used_slots: 0,
+        }
+    }
+
+    /// Number of slots in the swap file.
+    pub fn slot_count(&self) -> usize {
+        self.slot_count
+    }
+
+    /// Number of slots holding a page.
+    pub fn used_count(&self) -> usize {
+        self.used_slots
+    }
+
+    /// Reserves a free slot.
+    pub fn alloc_slot(&mut self) -> Option<usize> {
+        let (word_index, word) = self
+            .slots
+            .iter()
+            .enumerate()
+            .find(|(_, word)| **word != u64::MAX)?;
+
+        let slot = word_index * 64 + (!word).trailing_zeros() as usize;
+        if slot >= self.slot_count {
+            return None;
+        }
+
+        self.slots[word_index] |= 1 << (slot % 64);
+        self.used_slots += 1;
+
+        Some(slot)
+    }
+
+    /// Releases a slot, ignoring slots that aren't in use.
+    pub fn free_slot(&mut self, slot: usize) {
+        let Some(word) = self.slots.get_mut(slot / 64) else {
+            return;
+        };
+
+        let bit = 1 << (slot % 64);
+        if *word & bit != 0 {
+            *word &= !bit;
+            self.used_slots -= 1;
+        }
+    }
+}
+
+/// Uses a file as swap space, replacing any previous one.
+///
+/// ## Arguments
+///
+/// - `file` the preallocated swap file, its size is rounded down to pages
+pub fn init(file: Arc<RwLock<dyn File>>) {
+    let swap = SwapSpace::new(file);
+    log!(
+        LogType::OK,
+        "Swap space: {} slots ({} MiB)",
+        swap.slot_count(),
+        swap.slot_count() * PAGE_SIZE / (1024 * 1024)
+    );
+
+    *GLOBAL_SWAP.lock() = Some(swap);
+}
+
+/// Reserves a free swap slot, `None` when there is no swap space or it
+/// is full.
+pub fn alloc_slot() -> Option<usize> {
+    GLOBAL_SWAP.lock().as_mut()?.alloc_slot()
+}
+
+/// Releases a swap slot.
+pub fn free_slot(slot: usize) {
+    if let Some(swap) = GLOBAL_SWAP.lock().as_mut() {
+        swap.free_slot(slot);
+    }
+}
+
+/// Writes a page to a swap slot.
+///
+/// Must be called without the memory controller held, the disk driver
+/// needs it.
+///
+/// ## Arguments
+///
+/// - `slot` the reserved slot
+/// - `bytes` the page contents
+pub fn write_slot(slot: usize, bytes: &[u8]) -> Option<()> {
+    let file = GLOBAL_SWAP.lock().as_ref()?.file.clone();
+    let length = bytes.len().min(PAGE_SIZE);
+
+    let written = file.read().write(slot * PAGE_SIZE, &bytes[..length])?;
+    (written == length).then_some(())
+}
+
+/// Reads a page back from a swap slot.
+///
+/// Must be called without the memory controller held, the disk driver
+/// needs it.
+///
+/// ## Arguments
+///
+/// - `slot` the slot holding the page
+/// - `buffer` the buffer to fill with the page contents
+pub fn read_slot(slot: usize, buffer: &mut [u8]) -> Option<()> {
+    let file = GLOBAL_SWAP.lock().as_ref()?.file.clone();
+    let length = buffer.len().min(PAGE_SIZE);
+
+    let read = file
+        .read()
+        .read_at(slot * PAGE_SIZE, &mut buffer[..length])?;
+    (read == length).then_some(())
+}
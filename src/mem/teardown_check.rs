@@ -0,0 +1,137 @@
+use alloc::vec::Vec;
+
+use crate::{io::LogType, log};
+
+use super::{
+    paging::{entry::EntryFlags, Page, PageTable, PAGES_PER_HUGE_PAGE},
+    MemoryController, PageFrame,
+};
+
+/// Number of leaked frames and slots logged one by one, the rest are only
+/// counted.
+const MAX_LOGGED_LEAKS: usize = 8;
+
+/// A self-check of address space teardown, only built in debug builds.
+/// The frames and page table slots mapped in a process page table are
+/// recorded right before its address space is torn down, to check that
+/// all of them were returned afterwards.
+///
+/// This is a snapshot, not an allocation tracker: frames that were lost
+/// while the process ran, kernel allocations made on its behalf and swap
+/// slots aren't covered.
+pub struct TeardownCheck {
+    frames: Vec<PageFrame>,
+    slots: Vec<usize>,
+}
+
+impl TeardownCheck {
+    /// Records the frames mapped into a process page table and the slots
+    /// of its tables.
+    ///
+    /// ## Arguments
+    ///
+    /// - `mc` the memory controller
+    /// - `table` the process page table
+    /// - `user_table` the user page table of the process, if it has one
+    /// - `shared` whether a page belongs to a shared memory object, its
+    ///   frame may outlive the process
+    pub fn collect(
+        mc: &mut MemoryController,
+        table: &PageTable,
+        user_table: Option<&PageTable>,
+        shared: impl Fn(Page) -> bool,
+    ) -> Self {
+        let mut frames = Vec::new();
+        for (page, entry) in table.user_leaf_entries(&mut mc.temp_mapper) {
+            // swapped out pages have no frame
+            let Some(frame) = entry.get_frame() else {
+                continue;
+            };
+
+            if shared(page) {
+                continue;
+            }
+
+            if entry.flags().contains(EntryFlags::HUGE_PAGE) {
+                let parts = (0..PAGES_PER_HUGE_PAGE)
+                    .filter_map(|index| entry.huge_page_part(index).get_frame());
+                frames.extend(parts);
+            } else {
+                frames.push(frame);
+            }
+        }
+
+        let mut slots = Vec::new();
+        for frame in table.user_subtable_frames(&mut mc.temp_mapper) {
+            if let Some(addr) = mc.slot_allocator.addr_for_frame(frame, &mut mc.temp_mapper) {
+                slots.push(addr);
+            }
+        }
+
+        slots.push(table.addr);
+        if let Some(user_table) = user_table {
+            slots.push(user_table.addr);
+        }
+
+        Self {
+            frames: frames,
+            slots: slots,
+        }
+    }
+
+    /// Logs the recorded frames and slots that are still allocated.
+    ///
+    /// ## Arguments
+    ///
+    /// - `pid` the process the address space belonged to
+    /// - `mc` the memory controller the frames and slots were returned to
+    ///
+    /// ## Returns
+    /// The number of leaked frames and slots.
+    pub fn report(&self, pid: usize, mc: &MemoryController) -> usize {
+        let leaked_frames: Vec<&PageFrame> = self
+            .frames
+            .iter()
+            .filter(|frame| mc.frame_allocator.is_allocated(frame))
+            .collect();
+        let leaked_slots: Vec<usize> = self
+            .slots
+            .iter()
+            .copied()
+            .filter(|&addr| !mc.slot_allocator.is_free(addr))
+            .collect();
+
+        for frame in leaked_frames.iter().take(MAX_LOGGED_LEAKS) {
+            log!(
+                LogType::ERR,
+                "teardown_check: pid {} leaked frame 0x{:X}",
+                pid,
+                frame.start_address()
+            );
+        }
+
+        for addr in leaked_slots.iter().take(MAX_LOGGED_LEAKS) {
+            log!(
+                LogType::ERR,
+                "teardown_check: pid {} leaked page table slot 0x{:X}",
+                pid,
+                addr
+            );
+        }
+
+        let leaks = leaked_frames.len() + leaked_slots.len();
+        if leaks > 0 {
+            log!(
+                LogType::ERR,
+                "teardown_check: pid {} leaked {} of {} frames and {} of {} page table slots",
+                pid,
+                leaked_frames.len(),
+                self.frames.len(),
+                leaked_slots.len(),
+                self.slots.len()
+            );
+        }
+
+        leaks
+    }
+}
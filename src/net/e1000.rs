@@ -5,8 +5,6 @@ use core::{
     ptr::{read_volatile, write_volatile},
 };
 
-use x86_64::structures::idt::InterruptStackFrame;
-
 use crate::log;
 use crate::{
     arch::{
@@ -15,18 +13,20 @@ use crate::{
             acpi::pci::{BarType, PciDevice, PciDeviceHeaderType0},
             idt::IRQ0,
             pit::end_of_interrupt,
+            registers::FullInterruptStackFrame,
         },
     },
+    interrupt_trampoline,
     mem::{
         paging::{entry::EntryFlags, Page},
-        PageFrame, GLOBAL_MEMORY_CONTROLLER,
+        phys_to_virt, GLOBAL_MEMORY_CONTROLLER,
     },
     net::{dma_ptr::DMAPtr, ETH_DRIVER},
 };
 
 // https://pdos.csail.mit.edu/6.828/2019/readings/hardware/8254x_GBe_SDM.pdf
 
-const DMA_REGION_START: usize = 0x0000_6DCF_0000_0000;
+const DMA_REGION_START: usize = 0xFFFF_F000_0000_0000;
 
 const REG_CONTROL: usize = 0x0000;
 const REG_STATUS: usize = 0x0008;
@@ -144,13 +144,10 @@ impl E1000Driver {
             BarType::Memory64 { address, .. } => address as usize,
         };
 
-        let start = PageFrame::from_address(addr);
-        let end = PageFrame::from_address(addr + bar_size as usize);
-
         let mut controller = GLOBAL_MEMORY_CONTROLLER.lock();
         let controller = controller.as_mut().unwrap();
 
-        controller.identity_map(start, end, EntryFlags::WRITABLE);
+        controller.map_physical(addr, bar_size as usize, EntryFlags::WRITABLE);
 
         header.enable_bus_mastering();
 
@@ -183,7 +180,7 @@ impl E1000Driver {
 
         // register interrupt
         let vector = arch::x86_64::idt::IRQ0 + (self.interrupt_line as usize);
-        let handler = handle_interrupt as usize;
+        let handler = interrupt_handler_trampoline as usize;
 
         unsafe {
             arch::x86_64::idt::register_interrupt(vector, handler, false);
@@ -269,7 +266,9 @@ impl E1000Driver {
         let descs_start_page = Page::for_address(DMA_REGION_START);
         let descs_end_page = Page::for_address(DMA_REGION_START + descs_size - 1);
 
-        mc.map(descs_start_page, descs_end_page, EntryFlags::WRITABLE);
+        let descs_frame = mc
+            .map_contiguous(descs_start_page, descs_end_page, EntryFlags::WRITABLE)
+            .unwrap();
         let descs_ptr = DMA_REGION_START as *mut RxDesc;
 
         unsafe {
@@ -284,20 +283,14 @@ impl E1000Driver {
             let start_page = last_page;
             let end_page = Page::for_address(start_page.start_address() + BUFFER_SIZE - 1);
 
-            mc.map(start_page, end_page, EntryFlags::WRITABLE);
+            // the device writes whole buffers, so they
+            // must be physically contiguous
+            let buffer_frame = mc
+                .map_contiguous(start_page, end_page, EntryFlags::WRITABLE)
+                .unwrap();
             last_page = end_page.add(1);
 
-            let buffer_pa = {
-                // get physical addr for buffer
-                let buffer_va = start_page.start_address();
-                let frame = mc
-                    .kernel_table
-                    .translate_to_phys(buffer_va, &mut mc.temp_mapper)
-                    .unwrap();
-
-                let offset = buffer_va & 0xFFF;
-                frame.start_address() + offset
-            };
+            let buffer_pa = buffer_frame.start_address();
 
             let desc = unsafe { &mut *ptr };
             desc.addr = buffer_pa as u64;
@@ -311,16 +304,7 @@ impl E1000Driver {
             ptr = unsafe { ptr.add(1) };
         }
 
-        let descsc_pa = {
-            let va = descs_ptr as usize;
-            let frame = mc
-                .kernel_table
-                .translate_to_phys(va, &mut mc.temp_mapper)
-                .unwrap();
-
-            let offset = va & 0xFFF;
-            frame.start_address() + offset
-        };
+        let descsc_pa = descs_frame.start_address();
 
         self.send_command(REG_RXDESCLO, (descsc_pa & 0xFFFF_FFFF) as u32);
         self.send_command(REG_RXDESCHI, (descsc_pa >> 32) as u32);
@@ -401,7 +385,7 @@ impl E1000Driver {
                 BarType::Memory64 { .. } => unimplemented!(),
             };
 
-            let mac_base = (base_addr as usize) + REG_MAC;
+            let mac_base = phys_to_virt(base_addr as usize) + REG_MAC;
             let mut base_mac_8 = mac_base as *const u8;
             let base_mac_32 = mac_base as *const u32;
 
@@ -436,7 +420,7 @@ impl E1000Driver {
         match self.bar_type {
             BarType::IO { .. } => unimplemented!(),
             BarType::Memory32 { address, .. } => {
-                let addr = (address as usize) + p_address;
+                let addr = phys_to_virt(address as usize) + p_address;
                 let ptr = addr as *mut u32;
                 unsafe { write_volatile(ptr, p_value) };
             }
@@ -448,7 +432,7 @@ impl E1000Driver {
         match self.bar_type {
             BarType::IO { .. } => unimplemented!(),
             BarType::Memory32 { address, .. } => {
-                let addr = (address as usize) + p_address;
+                let addr = phys_to_virt(address as usize) + p_address;
                 let ptr = addr as *mut u32;
                 unsafe { read_volatile(ptr) }
             }
@@ -465,7 +449,13 @@ impl E1000Driver {
     }
 }
 
-extern "x86-interrupt" fn handle_interrupt(_stack: InterruptStackFrame) {
+#[naked]
+#[link_section = ".text.entry"]
+extern "x86-interrupt" fn interrupt_handler_trampoline() {
+    interrupt_trampoline!(handle_interrupt);
+}
+
+extern "C" fn handle_interrupt(_stack: *mut FullInterruptStackFrame) {
     let mut guard = ETH_DRIVER.lock();
 
     if let Some(driver) = guard.as_mut() {
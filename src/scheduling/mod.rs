@@ -1,20 +1,35 @@
 use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{string::String, sync::Arc, vec::Vec};
 use process::{FileDescriptor, Process, ProcessEntry};
+use shm::SharedMapping;
 use spin::{Mutex, RwLock};
+use user_memory::UserMemory;
+use vma::{DirtyPage, FaultError, FileMapping, VmaTree};
 
 use crate::log;
+#[cfg(debug_assertions)]
+use crate::mem::teardown_check::TeardownCheck;
 use crate::{
-    arch::x86_64::{gdt::GDT, registers::FullInterruptStackFrame},
-    elf,
-    fs::fs::{normalize_path_components, Directory, File},
+    arch::x86_64::{gdt, registers::FullInterruptStackFrame, trampoline},
+    fs::{
+        self,
+        fs::{normalize_path_components, Directory, DirectoryItems, File, Metadata},
+    },
     io::LogType,
-    mem::{paging::PageTable, GLOBAL_MEMORY_CONTROLLER},
+    mem::{
+        kpti,
+        paging::{Page, PageTable},
+        MemoryController, GLOBAL_MEMORY_CONTROLLER,
+    },
     print, time, with_root_dir,
 };
 
 pub mod process;
+pub mod shm;
+mod swap;
+pub mod user_memory;
+pub mod vma;
 
 pub static SCHEDULING_ENABLED: AtomicBool = AtomicBool::new(false);
 pub static CURRENT_INDEX: AtomicUsize = AtomicUsize::new(0);
@@ -24,45 +39,31 @@ pub static PID_COUNTER: AtomicUsize = AtomicUsize::new(0);
 unsafe fn jump(context: &FullInterruptStackFrame) {
     let ctx_addr = context as *const FullInterruptStackFrame as usize;
 
+    // lay the context out like the interrupt trampolines do and leave
+    // through their exit path, which switches to the user page table
     core::arch::asm!(
+        "and rsp, -16",
         "push {ss}",
         "push {rsp}",
         "push {rflags}",
         "push {cs}",
         "push {rip}",
-        "push [{ctx} + 0x00]", // r8
-        "push [{ctx} + 0x08]", // r9
-        "push [{ctx} + 0x10]", // r10
-        "push [{ctx} + 0x18]", // r11
-        "push [{ctx} + 0x20]", // r12
-        "push [{ctx} + 0x28]", // r13
-        "push [{ctx} + 0x30]", // r14
-        "push [{ctx} + 0x38]", // r15
-        "push [{ctx} + 0x40]", // rbp
-        "push [{ctx} + 0x48]", // rdi
-        "push [{ctx} + 0x50]", // rsi
-        "push [{ctx} + 0x58]", // rdx
-        "push [{ctx} + 0x60]", // rcx
-        "push [{ctx} + 0x68]", // rbx
         "push [{ctx} + 0x70]", // rax
-
-        "pop rax",
-        "pop rbx",
-        "pop rcx",
-        "pop rdx",
-        "pop rsi",
-        "pop rdi",
-        "pop rbp",
-        "pop r15",
-        "pop r14",
-        "pop r13",
-        "pop r12",
-        "pop r11",
-        "pop r10",
-        "pop r9",
-        "pop r8",
-
-        "iretq",
+        "push [{ctx} + 0x68]", // rbx
+        "push [{ctx} + 0x60]", // rcx
+        "push [{ctx} + 0x58]", // rdx
+        "push [{ctx} + 0x50]", // rsi
+        "push [{ctx} + 0x48]", // rdi
+        "push [{ctx} + 0x40]", // rbp
+        "push [{ctx} + 0x38]", // r15
+        "push [{ctx} + 0x30]", // r14
+        "push [{ctx} + 0x28]", // r13
+        "push [{ctx} + 0x20]", // r12
+        "push [{ctx} + 0x18]", // r11
+        "push [{ctx} + 0x10]", // r10
+        "push [{ctx} + 0x08]", // r9
+        "push [{ctx} + 0x00]", // r8
+        "jmp {interrupt_return}",
 
         ss = in(reg) context.ss,
         rsp = in(reg) context.rsp,
@@ -70,6 +71,7 @@ unsafe fn jump(context: &FullInterruptStackFrame) {
         cs = in(reg) context.cs,
         rip = in(reg) context.rip,
         ctx = in(reg) ctx_addr,
+        interrupt_return = sym trampoline::interrupt_return,
         options(noreturn)
     );
 }
@@ -91,9 +93,9 @@ fn next_process(interrupt_stack: Option<&FullInterruptStackFrame>) -> Option<Pro
                 // Avoid saving kernel
                 let _m = current.pid + 1;
                 let is_not_presched = !current.pre_schedule;
-                let rip = interrupt_stack.rip;
+                let from_user = interrupt_stack.cs & 3 == 3;
 
-                if is_not_presched && rip > 0x1FFFFF {
+                if is_not_presched && from_user {
                     // save current context
                     current.context = interrupt_stack.clone();
                 }
@@ -189,16 +191,26 @@ pub fn schedule(interrupt_stack: Option<&FullInterruptStackFrame>) {
             loop {}
         };
 
-        if mc.switch_table(&ring3_page_table).is_none() {
-            log!(
-                LogType::ERR,
-                "schedule: failed to switch to pid {} page table 0x{:X}",
-                process_to_jump.pid,
-                ring3_page_table.addr
-            );
+        // staying in the same process keeps its page tables, and the
+        // ones the interrupt entry and exit code switch between
+        if mc.active_table.addr != ring3_page_table.addr {
+            if mc.switch_table(&ring3_page_table).is_none() {
+                log!(
+                    LogType::ERR,
+                    "schedule: failed to switch to pid {} page table 0x{:X}",
+                    process_to_jump.pid,
+                    ring3_page_table.addr
+                );
 
-            unsafe { core::arch::asm!("sti") };
-            loop {}
+                unsafe { core::arch::asm!("sti") };
+                loop {}
+            }
+
+            kpti::activate(
+                mc,
+                &ring3_page_table,
+                process_to_jump.user_page_table.as_ref(),
+            );
         }
 
         // drop memory controller ref
@@ -228,12 +240,16 @@ pub fn deploy(entry: ProcessEntry, fork_current: bool) -> usize {
             return 0;
         };
 
-        Some((current.curr_working_dir.clone(), current.fd_table.clone()))
+        Some((
+            current.curr_working_dir.clone(),
+            current.fd_table.clone(),
+            current.environment.clone(),
+        ))
     } else {
         None
     };
 
-    let cwd = if let Some((cwd, _)) = &parent_state {
+    let cwd = if let Some((cwd, _, _)) = &parent_state {
         cwd.clone()
     } else {
         // root directory
@@ -250,12 +266,14 @@ pub fn deploy(entry: ProcessEntry, fork_current: bool) -> usize {
         return 0;
     };
 
-    if let Some((_, fd_table)) = parent_state {
+    if let Some((_, fd_table, environment)) = parent_state {
         process.fd_table = fd_table;
+        process.environment = environment;
     }
 
-    let cs = GDT.1.user_code.0;
-    let ss = GDT.1.user_data.0;
+    let selectors = gdt::selectors();
+    let cs = selectors.user_code.0;
+    let ss = selectors.user_data.0;
 
     process.context.cs = cs as usize;
     process.context.ss = ss as usize;
@@ -362,18 +380,40 @@ pub fn exit_current() {
         return;
     }
 
-    let removed = processes.remove(current_index);
-
-    elf::unmap(&removed.start_region);
+    let mut removed = processes.remove(current_index);
+    let mut dirty_pages = Vec::new();
 
     {
         let mut mc = GLOBAL_MEMORY_CONTROLLER.lock();
         if let Some(mc) = mc.as_mut() {
-            mc.free_stack(&removed.stack);
+            #[cfg(debug_assertions)]
+            let teardown_check = removed.ring3_page_table.as_ref().map(|page_table| {
+                let vmas = &removed.user_memory.vmas;
+                TeardownCheck::collect(mc, page_table, removed.user_page_table.as_ref(), |page| {
+                    vmas.find(page.start_address())
+                        .is_some_and(|area| matches!(area.backing, vma::VmaBacking::Shared(_)))
+                })
+            });
+
+            // every mapped user page lies inside a memory area,
+            // including the program image and the stack
+            if let Some(mut page_table) = removed.ring3_page_table.clone() {
+                dirty_pages = removed.user_memory.free(&mut page_table, mc);
+            }
+
+            mc.user_stack_allocator.free(&removed.stack);
 
             if let Some(page_table) = &removed.ring3_page_table {
-                page_table.free_user_subtables(&mut mc.slot_allocator, &mut mc.temp_mapper);
-                mc.slot_allocator.free(page_table.addr);
+                let stray_pages =
+                    mc.free_address_space(page_table, removed.user_page_table.as_ref());
+                if stray_pages > 0 {
+                    log!(
+                        LogType::ERR,
+                        "exit_current: pid {} had {} pages mapped outside its memory areas",
+                        removed.pid,
+                        stray_pages
+                    );
+                }
             } else {
                 log!(
                     LogType::ERR,
@@ -381,6 +421,11 @@ pub fn exit_current() {
                     removed.pid
                 );
             }
+
+            #[cfg(debug_assertions)]
+            if let Some(teardown_check) = teardown_check {
+                teardown_check.report(removed.pid, mc);
+            }
         } else {
             log!(
                 LogType::ERR,
@@ -390,6 +435,14 @@ pub fn exit_current() {
         }
     }
 
+    if write_back(dirty_pages).is_none() {
+        log!(
+            LogType::ERR,
+            "exit_current: failed to write back shared mappings of pid {}",
+            removed.pid
+        );
+    }
+
     // adjust current process index
     let new_index = if current_index != 0 {
         current_index - 1
@@ -422,6 +475,337 @@ pub fn get_current_process_page_table() -> Option<PageTable> {
     current_process.ring3_page_table.clone()
 }
 
+/// Runs a closure on the dynamic memory of the current process, with its
+/// page table and the memory controller.
+fn with_current_user_memory<R, F>(f: F) -> Option<R>
+where
+    F: FnOnce(&mut UserMemory, &mut PageTable, &mut MemoryController) -> Option<R>,
+{
+    let mut processes = PROCESSES.lock();
+    let current_index = CURRENT_INDEX.load(Ordering::SeqCst);
+    let current_process = processes.get_mut(current_index)?;
+    let mut page_table = current_process.ring3_page_table.clone()?;
+
+    let mut mc = GLOBAL_MEMORY_CONTROLLER.lock();
+    let mc = mc.as_mut()?;
+
+    f(&mut current_process.user_memory, &mut page_table, mc)
+}
+
+/// Moves the program break of the current process.
+///
+/// ## Arguments
+///
+/// - `new_brk` the requested program break, 0 to query the current one
+///
+/// ## Returns
+/// The resulting program break, or `None` when it cannot be moved.
+pub fn curr_process_brk(new_brk: usize) -> Option<usize> {
+    with_current_user_memory(|memory, page_table, mc| {
+        if new_brk == 0 {
+            return Some(memory.brk);
+        }
+
+        memory.set_brk(page_table, mc, new_brk)
+    })
+}
+
+/// Maps anonymous zeroed memory into the current process.
+///
+/// ## Arguments
+///
+/// - `size` the mapping size in bytes
+/// - `writable` whether the mapping is writable
+/// - `addr` the address to place the mapping at, `None` lets the kernel
+///   pick one
+///
+/// ## Returns
+/// The start address of the mapping.
+pub fn curr_process_map_anonymous(
+    size: usize,
+    writable: bool,
+    addr: Option<usize>,
+) -> Option<usize> {
+    with_current_user_memory(|memory, _, _| memory.map_anonymous(size, writable, addr))
+}
+
+/// Maps an open file into the current process.
+///
+/// ## Arguments
+///
+/// - `fd` the file descriptor of the file
+/// - `offset` the page-aligned file offset to map from
+/// - `size` the mapping size in bytes
+/// - `writable` whether the mapping is writable
+/// - `shared` whether writes are carried back to the file
+/// - `addr` the address to place the mapping at, `None` lets the kernel
+///   pick one
+///
+/// ## Returns
+/// The start address of the mapping.
+pub fn curr_process_map_file(
+    fd: usize,
+    offset: usize,
+    size: usize,
+    writable: bool,
+    shared: bool,
+    addr: Option<usize>,
+) -> Option<usize> {
+    let mut processes = PROCESSES.lock();
+    let current_index = CURRENT_INDEX.load(Ordering::SeqCst);
+    let current_process = processes.get_mut(current_index)?;
+
+    let Some(FileDescriptor::File(open_file)) = current_process.get_fd(fd) else {
+        return None;
+    };
+
+    // a shared writable mapping writes to the file
+    if !open_file.readable || (shared && writable && !open_file.writable) {
+        return None;
+    }
+
+    let mapping = FileMapping {
+        file: open_file.file.clone(),
+        offset: offset,
+        shared: shared,
+    };
+
+    current_process
+        .user_memory
+        .map_file(mapping, size, writable, addr)
+}
+
+/// Maps an open shared memory object into the current process.
+///
+/// ## Arguments
+///
+/// - `fd` the file descriptor of the object
+/// - `offset` the page-aligned offset inside the object to map from
+/// - `size` the mapping size in bytes
+/// - `writable` whether the mapping is writable
+/// - `addr` the address to place the mapping at, `None` lets the kernel
+///   pick one
+///
+/// ## Returns
+/// The start address of the mapping.
+pub fn curr_process_map_shared(
+    fd: usize,
+    offset: usize,
+    size: usize,
+    writable: bool,
+    addr: Option<usize>,
+) -> Option<usize> {
+    let object = match get_current_file_descriptor(fd) {
+        Some(FileDescriptor::SharedMemory(object)) => object,
+        _ => return None,
+    };
+
+    let mapping = SharedMapping {
+        object: object,
+        offset: offset,
+    };
+
+    with_current_user_memory(|memory, page_table, mc| {
+        memory.map_shared(page_table, mc, mapping, size, writable, addr)
+    })
+}
+
+/// Opens a shared memory object for the current process.
+///
+/// ## Arguments
+///
+/// - `name` the name of the object
+/// - `size` the size of a new object in bytes
+/// - `create` whether a missing object is created
+/// - `exclusive` whether opening an existing object fails
+///
+/// ## Returns
+/// The new file descriptor.
+pub fn curr_process_shm_open(
+    name: &str,
+    size: usize,
+    create: bool,
+    exclusive: bool,
+) -> Option<usize> {
+    let object = {
+        let mut mc = GLOBAL_MEMORY_CONTROLLER.lock();
+        let mc = mc.as_mut()?;
+        shm::open(name, size, create, exclusive, mc)?
+    };
+
+    let mut processes = PROCESSES.lock();
+    let current_index = CURRENT_INDEX.load(Ordering::SeqCst);
+    let current_process = processes.get_mut(current_index)?;
+
+    Some(current_process.open_shared_memory(object))
+}
+
+/// Removes the name of a shared memory object, its memory is freed once
+/// the last process unmapped it.
+///
+/// ## Arguments
+///
+/// - `name` the name of the object
+pub fn shm_unlink(name: &str) -> Option<()> {
+    let mut mc = GLOBAL_MEMORY_CONTROLLER.lock();
+    let mc = mc.as_mut()?;
+
+    shm::unlink(name, mc)
+}
+
+/// Unmaps part of an anonymous or file mapping of the current process,
+/// writing modified pages of a shared file mapping back to the file.
+///
+/// ## Arguments
+///
+/// - `addr` the page-aligned start address
+/// - `size` the size in bytes
+pub fn curr_process_unmap(addr: usize, size: usize) -> Option<()> {
+    let dirty_pages = with_current_user_memory(|memory, page_table, mc| {
+        memory.unmap(page_table, mc, addr, size)
+    })?;
+
+    write_back(dirty_pages)
+}
+
+/// Writes the modified pages of the shared file mappings in a range of
+/// the current process back to their files.
+///
+/// ## Arguments
+///
+/// - `addr` the page-aligned start address
+/// - `size` the size in bytes
+pub fn curr_process_sync(addr: usize, size: usize) -> Option<()> {
+    let dirty_pages =
+        with_current_user_memory(|memory, page_table, mc| memory.sync(page_table, mc, addr, size))?;
+
+    write_back(dirty_pages)
+}
+
+/// Writes dirty pages of shared file mappings back to their files, must be
+/// called without the memory controller held.
+fn write_back(dirty_pages: Vec<DirtyPage>) -> Option<()> {
+    let mut result = Some(());
+    for page in dirty_pages {
+        if page.write_back().is_none() {
+            result = None;
+        }
+    }
+
+    result
+}
+
+/// Resolves a page fault of the current process by mapping a page, when
+/// the address lies inside one of its lazily mapped areas.
+///
+/// ## Arguments
+///
+/// - `addr` the faulting address
+/// - `write` whether the fault was caused by a write
+pub fn handle_current_page_fault(addr: usize, write: bool) -> Result<(), FaultError> {
+    let mut processes = PROCESSES.lock();
+
+    // evicting pages only ever happens on faults,
+    // the faulting page is mapped right after
+    swap::reclaim_if_low(&processes);
+
+    let current_index = CURRENT_INDEX.load(Ordering::SeqCst);
+    let current_process = processes
+        .get_mut(current_index)
+        .ok_or(FaultError::Unmapped)?;
+    let mut page_table = current_process
+        .ring3_page_table
+        .clone()
+        .ok_or(FaultError::Unmapped)?;
+
+    fault_in_page(
+        &current_process.user_memory.vmas,
+        &mut page_table,
+        addr,
+        write,
+    )
+}
+
+/// Maps the page containing an address for a fault. File contents and
+/// swapped out pages are read before the memory controller is locked, the
+/// disk driver needs it.
+fn fault_in_page(
+    vmas: &VmaTree,
+    page_table: &mut PageTable,
+    addr: usize,
+    write: bool,
+) -> Result<(), FaultError> {
+    let swap_slot = {
+        let mut mc = GLOBAL_MEMORY_CONTROLLER.lock();
+        let mc = mc.as_mut().ok_or(FaultError::OutOfMemory)?;
+
+        page_table
+            .get_entry(Page::for_address(addr), &mut mc.temp_mapper)
+            .and_then(|entry| entry.swap_slot())
+    };
+
+    if let Some(slot) = swap_slot {
+        return swap::swap_in(vmas, page_table, addr, write, slot);
+    }
+
+    let contents = vmas.read_file_page(addr);
+
+    let mut mc = GLOBAL_MEMORY_CONTROLLER.lock();
+    let mc = mc.as_mut().ok_or(FaultError::OutOfMemory)?;
+
+    vmas.handle_fault(page_table, mc, addr, write, contents.as_deref())
+}
+
+/// Checks a user range against the memory areas of the current process.
+/// Its pages aren't touched, copies fault them in as needed.
+///
+/// ## Arguments
+///
+/// - `page_table` the page table of the current process
+/// - `addr` the start of the range
+/// - `size` the size of the range in bytes
+/// - `writable` whether the range must be writable
+pub fn current_range_accessible(
+    page_table: &PageTable,
+    addr: usize,
+    size: usize,
+    writable: bool,
+) -> bool {
+    let processes = PROCESSES.lock();
+    let current_index = CURRENT_INDEX.load(Ordering::SeqCst);
+    let Some(current_process) = processes.get(current_index) else {
+        return false;
+    };
+
+    let Some(current_table) = current_process.ring3_page_table.as_ref() else {
+        return false;
+    };
+
+    current_table.addr == page_table.addr
+        && current_process
+            .user_memory
+            .vmas
+            .is_accessible(addr, size, writable)
+}
+
+pub fn get_current_environment() -> Vec<String> {
+    let processes = PROCESSES.lock();
+    let current_index = CURRENT_INDEX.load(Ordering::SeqCst);
+    let Some(current_process) = processes.get(current_index) else {
+        return Vec::new();
+    };
+
+    current_process.environment.clone()
+}
+
+pub fn set_current_environment_variable(key: &str, value: &str) -> Option<()> {
+    let mut processes = PROCESSES.lock();
+    let current_index = CURRENT_INDEX.load(Ordering::SeqCst);
+    let current_process = processes.get_mut(current_index)?;
+
+    current_process.set_env(key, value)
+}
+
 pub fn get_current_file_descriptor(fd: usize) -> Option<FileDescriptor> {
     let processes = PROCESSES.lock();
     let current_index = CURRENT_INDEX.load(Ordering::SeqCst);
@@ -431,6 +815,7 @@ pub fn get_current_file_descriptor(fd: usize) -> Option<FileDescriptor> {
 }
 
 /// Finds a file from either an absolute path or the current process cwd.
+/// Lookups go through the root of the VFS, so they cross mount points.
 ///
 /// ## Arguments
 ///
@@ -457,6 +842,7 @@ pub fn find_file_from_path(path: &str) -> Option<Arc<RwLock<dyn File>>> {
 }
 
 /// Finds a directory from either an absolute path or the current process cwd.
+/// Mounted filesystems are entered through their mount points.
 ///
 /// ## Arguments
 ///
@@ -511,6 +897,28 @@ pub fn curr_process_open_file(path: &str, readable: bool, writable: bool) -> Opt
     Some(current_process.open_file(file, readable, writable))
 }
 
+/// Opens a directory for iteration by the current process.
+///
+/// ## Arguments
+///
+/// - `path` the absolute or cwd-relative path of the directory
+///
+/// ## Returns
+/// The new file descriptor, or `None` when the directory does not exist.
+pub fn curr_process_open_directory(path: &str) -> Option<usize> {
+    let directory = find_directory_from_path(path)?;
+
+    // listing may read the disk, which must not happen with the processes
+    // locked
+    let entries = directory.list_dir();
+
+    let mut processes = PROCESSES.lock();
+    let current_index = CURRENT_INDEX.load(Ordering::SeqCst);
+    let current_process = processes.get_mut(current_index)?;
+
+    Some(current_process.open_directory(directory, entries))
+}
+
 /// Creates a new regular file and opens it for the current process.
 ///
 /// ## Arguments
@@ -580,6 +988,89 @@ pub fn curr_process_remove_directory(path: &str) -> bool {
     parent.remove_directory(name).is_some()
 }
 
+/// Renames or moves a file or directory for the current process.
+///
+/// ## Arguments
+///
+/// - `old_path` the absolute or cwd-relative path of the entry to rename
+/// - `new_path` the absolute or cwd-relative path the entry is moved to
+///
+/// ## Returns
+/// Whether the entry was renamed.
+pub fn curr_process_rename(old_path: &str, new_path: &str) -> bool {
+    let Some((old_parent, old_name)) = resolve_parent_directory_and_name(old_path) else {
+        return false;
+    };
+
+    let Some((new_parent, new_name)) = resolve_parent_directory_and_name(new_path) else {
+        return false;
+    };
+
+    old_parent
+        .rename(old_name, &*new_parent, new_name)
+        .is_some()
+}
+
+/// Mounts a filesystem for the current process.
+///
+/// ## Arguments
+///
+/// - `fs_type` the filesystem type, `fat` or `tmpfs`
+/// - `source` the device holding the filesystem, like `disk1`, any label
+///   for a tmpfs
+/// - `target` the absolute or cwd-relative path to mount at, a directory
+///   already there is hidden until the filesystem is unmounted
+///
+/// ## Returns
+/// Whether the filesystem was mounted.
+pub fn curr_process_mount(fs_type: &str, source: &str, target: &str) -> bool {
+    let Some((parent, name)) = resolve_parent_directory_and_name(target) else {
+        return false;
+    };
+
+    fs::mount(fs_type, source, &*parent, name).is_some()
+}
+
+/// Unmounts a filesystem for the current process.
+///
+/// ## Arguments
+///
+/// - `target` the absolute or cwd-relative path of the mount point
+///
+/// ## Returns
+/// Whether the filesystem was unmounted, it stays mounted while any of its
+/// files or directories is in use.
+pub fn curr_process_umount(target: &str) -> bool {
+    let path = {
+        // the looked up directory must be gone before unmounting
+        let Some(directory) = find_directory_from_path(target) else {
+            return false;
+        };
+
+        directory.absolute_path()
+    };
+
+    fs::umount(&path).is_some()
+}
+
+/// Reads the metadata of a file or directory for the current process.
+///
+/// ## Arguments
+///
+/// - `path` the absolute or cwd-relative path of the file or directory
+///
+/// ## Returns
+/// The metadata, or `None` when nothing exists at the path.
+pub fn curr_process_metadata(path: &str) -> Option<Metadata> {
+    if let Some(file) = find_file_from_path(path) {
+        let file_guard = file.read();
+        return file_guard.metadata();
+    }
+
+    let directory = find_directory_from_path(path)?;
+    directory.metadata()
+}
+
 fn create_file_from_path(path: &str) -> Option<Arc<RwLock<dyn File>>> {
     let (parent, name) = resolve_parent_directory_and_name(path)?;
     parent.create_file(name)
@@ -637,6 +1128,14 @@ pub fn read_current_file_descriptor(fd: usize, size: usize) -> Option<Vec<u8>> {
     current_process.read_fd(fd, size)
 }
 
+pub fn read_current_directory_descriptor(fd: usize, count: usize) -> Option<DirectoryItems> {
+    let mut processes = PROCESSES.lock();
+    let current_index = CURRENT_INDEX.load(Ordering::SeqCst);
+    let current_process = processes.get_mut(current_index)?;
+
+    current_process.read_dir_fd(fd, count)
+}
+
 pub fn write_current_file_descriptor(fd: usize, bytes: &[u8]) -> Option<usize> {
     let mut processes = PROCESSES.lock();
     let current_index = CURRENT_INDEX.load(Ordering::SeqCst);
@@ -1,20 +1,41 @@
-use core::{cmp::min, mem::size_of, ptr};
-
-use alloc::{alloc::dealloc, sync::Arc, vec::Vec};
+use core::{cmp::min, mem::size_of};
+
+use alloc::{
+    alloc::dealloc,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
 use spin::{Mutex, RwLock};
 
 use crate::{
-    arch::x86_64::registers::FullInterruptStackFrame,
+    arch::x86_64::{registers::FullInterruptStackFrame, user_access},
     elf::ElfRegion,
-    fs::fs::{Directory, File},
+    fs::fs::{Directory, DirectoryItems, File},
     io::LogType,
     log,
-    mem::{
-        paging::{entry::EntryFlags, PageTable},
-        Stack, GLOBAL_MEMORY_CONTROLLER,
-    },
+    mem::{paging::PageTable, Stack},
+    scheduling::{shm::SharedMemory, user_memory::UserMemory, vma::VmaTree},
 };
 
+/// Environment of processes that are not forked from another process.
+pub const DEFAULT_ENVIRONMENT: &[&str] = &["PATH=/bin"];
+
+/// Maximum number of bytes copied from user memory at once, enough for the
+/// longest path or string a syscall takes. Larger buffers are copied in
+/// chunks with [`Process::copy_from_user_into`].
+pub const USER_COPY_MAX_BYTES: usize = 4096;
+
+/// Maximum number of environment variables of a process. Userspace sizes
+/// its buffers for the environment after this and the total below, see
+/// `ENV_BLOCK_MAX_BYTES` in ulib.
+const ENV_MAX_ENTRIES: usize = 64;
+
+/// Maximum total byte length of the `KEY=VALUE` entries of a process, they
+/// are copied to the stack of every program it executes.
+const ENV_MAX_TOTAL_BYTES: usize = 16 * 1024;
+
 #[derive(Clone)]
 pub struct Process {
     pub pid: usize,
@@ -26,11 +47,18 @@ pub struct Process {
     /// reaches this value.
     pub sleep_until_tick: Option<u64>,
     pub context: FullInterruptStackFrame,
-    pub start_region: Arc<Mutex<ElfRegion>>,
     pub curr_working_dir: Arc<dyn Directory + Send + Sync>,
     pub stack: Stack,
     pub ring3_page_table: Option<PageTable>,
+
+    /// The page table the process runs on with page table isolation, it
+    /// only maps the user pages and the interrupt entry code.
+    pub user_page_table: Option<PageTable>,
     pub fd_table: Vec<Option<FileDescriptor>>,
+
+    /// Environment variables as `KEY=VALUE` entries.
+    pub environment: Vec<String>,
+    pub user_memory: UserMemory,
 }
 
 #[derive(Clone)]
@@ -39,6 +67,10 @@ pub enum FileDescriptor {
     Stdout,
     Stderr,
     File(OpenFile),
+    Directory(OpenDirectory),
+
+    /// A shared memory object, it can only be mapped.
+    SharedMemory(Arc<SharedMemory>),
 }
 
 #[derive(Clone)]
@@ -49,6 +81,18 @@ pub struct OpenFile {
     pub writable: bool,
 }
 
+#[derive(Clone)]
+pub struct OpenDirectory {
+    pub directory: Arc<dyn Directory>,
+
+    /// The entries of the directory when it was opened, so reading it in
+    /// several calls neither skips nor repeats entries.
+    pub entries: DirectoryItems,
+
+    /// The number of entries already returned, directories first.
+    pub cursor: usize,
+}
+
 impl Process {
     pub fn from(entry: ProcessEntry, pid: usize, cwd: Arc<dyn Directory>) -> Option<Process> {
         let mut context = FullInterruptStackFrame::empty();
@@ -84,14 +128,62 @@ impl Process {
             awaiting_process: None,
             sleep_until_tick: None,
             context: context,
-            start_region: entry.start_region,
             curr_working_dir: cwd,
             stack: stack,
             ring3_page_table: entry.ring3_page_table,
+            user_page_table: entry.user_page_table,
             fd_table: Self::standard_fd_table(),
+            environment: DEFAULT_ENVIRONMENT.iter().map(|e| e.to_string()).collect(),
+            user_memory: UserMemory::new(entry.vmas),
         })
     }
 
+    /// Sets an environment variable, replacing any previous value.
+    ///
+    /// ## Arguments
+    ///
+    /// - `key` the variable name, must not be empty or contain `=`
+    /// - `value` the new value
+    ///
+    /// ## Returns
+    /// `Some(())` when the variable was set, or `None` for an invalid key or
+    /// when the environment would grow past its limits.
+    pub fn set_env(&mut self, key: &str, value: &str) -> Option<()> {
+        if key.is_empty() || key.contains('=') || key.contains('\0') || value.contains('\0') {
+            return None;
+        }
+
+        let mut entry = String::with_capacity(key.len() + 1 + value.len());
+        entry.push_str(key);
+        entry.push('=');
+        entry.push_str(value);
+
+        let entries = self.environment.len();
+        let total: usize = self.environment.iter().map(|e| e.len()).sum();
+
+        let existing = self
+            .environment
+            .iter_mut()
+            .find(|e| e.split_once('=').map(|(k, _)| k) == Some(key));
+
+        // a replaced entry gives back its slot and bytes
+        let (entries, total) = match &existing {
+            Some(existing) => (entries, total - existing.len()),
+            None => (entries + 1, total),
+        };
+
+        if entries > ENV_MAX_ENTRIES || total + entry.len() > ENV_MAX_TOTAL_BYTES {
+            return None;
+        }
+
+        match existing {
+            Some(existing) => *existing = entry,
+            None => self.environment.push(entry),
+        }
+
+        Some(())
+    }
+
     fn standard_fd_table() -> Vec<Option<FileDescriptor>> {
         let mut fd_table = Vec::new();
         fd_table.push(Some(FileDescriptor::Stdin));
@@ -107,13 +199,54 @@ impl Process {
         readable: bool,
         writable: bool,
     ) -> usize {
-        let descriptor = Some(FileDescriptor::File(OpenFile {
+        let descriptor = FileDescriptor::File(OpenFile {
             file: file,
             offset: 0,
             readable: readable,
             writable: writable,
-        }));
+        });
+
+        self.insert_fd(descriptor)
+    }
+
+    /// Opens a directory for iteration, starting at its first entry. The
+    /// entries are listed once by the caller, later changes don't show up.
+    ///
+    /// ## Arguments
+    ///
+    /// - `directory` the directory to open
+    /// - `entries` the entries of the directory
+    ///
+    /// ## Returns
+    /// The new file descriptor.
+    pub fn open_directory(
+        &mut self,
+        directory: Arc<dyn Directory>,
+        entries: DirectoryItems,
+    ) -> usize {
+        let descriptor = FileDescriptor::Directory(OpenDirectory {
+            directory: directory,
+            entries: entries,
+            cursor: 0,
+        });
+
+        self.insert_fd(descriptor)
+    }
 
+    /// Opens a shared memory object, so it can be mapped.
+    ///
+    /// ## Arguments
+    ///
+    /// - `object` the shared memory object
+    ///
+    /// ## Returns
+    /// The new file descriptor.
+    pub fn open_shared_memory(&mut self, object: Arc<SharedMemory>) -> usize {
+        self.insert_fd(FileDescriptor::SharedMemory(object))
+    }
+
+    fn insert_fd(&mut self, descriptor: FileDescriptor) -> usize {
+        let descriptor = Some(descriptor);
         for fd in 3..self.fd_table.len() {
             if self.fd_table[fd].is_none() {
                 self.fd_table[fd] = descriptor.clone();
@@ -173,6 +306,45 @@ impl Process {
         }
     }
 
+    /// Reads the next entries of an open directory and advances its cursor.
+    ///
+    /// ## Arguments
+    ///
+    /// - `fd` the directory file descriptor
+    /// - `count` the maximum number of entries to read
+    ///
+    /// ## Returns
+    /// The subdirectories and files following the cursor, directories
+    /// first; both are empty once the directory is exhausted.
+    pub fn read_dir_fd(&mut self, fd: usize, count: usize) -> Option<DirectoryItems> {
+        let descriptor = self.fd_table.get_mut(fd)?.as_mut()?;
+        let FileDescriptor::Directory(open_directory) = descriptor else {
+            return None;
+        };
+
+        let (directories, files) = &open_directory.entries;
+        let skip_directories = min(open_directory.cursor, directories.len());
+        let skip_files = open_directory.cursor - skip_directories;
+
+        let directories: Vec<Arc<dyn Directory>> = directories
+            .iter()
+            .skip(skip_directories)
+            .take(count)
+            .cloned()
+            .collect();
+
+        let remaining = count - directories.len();
+        let files: Vec<Arc<RwLock<dyn File>>> = files
+            .iter()
+            .skip(skip_files)
+            .take(remaining)
+            .cloned()
+            .collect();
+
+        open_directory.cursor += directories.len() + files.len();
+        Some((directories, files))
+    }
+
     fn free_file_region(region: &crate::mem::Region) {
         if region.size == 0 {
             return;
@@ -227,7 +399,9 @@ impl Process {
         }
     }
 
-    /// Checks whether a user pointer range is mapped with the required access.
+    /// Checks whether a user pointer range lies inside the memory areas of
+    /// the current process with the required access. Only the areas are
+    /// looked at, pages that aren't mapped are faulted in by the copy.
     ///
     /// ## Arguments
     ///
@@ -241,21 +415,7 @@ impl Process {
         size: usize,
         writable: bool,
     ) -> bool {
-        let mut mc = GLOBAL_MEMORY_CONTROLLER.lock();
-        let Some(mc) = mc.as_mut() else {
-            return false;
-        };
-
-        page_table
-            .walk_range_entries(addr, size, &mut mc.temp_mapper, |_, entry| {
-                let flags = entry.flags();
-                let valid = flags.contains(EntryFlags::PRESENT)
-                    && flags.contains(EntryFlags::RING3_ACCESSIBLE)
-                    && (!writable || flags.contains(EntryFlags::WRITABLE));
-
-                valid.then_some(())
-            })
-            .is_some()
+        super::current_range_accessible(page_table, addr, size, writable)
     }
 
     /// Checks whether a user pointer range can be accessed by a syscall.
@@ -284,21 +444,43 @@ impl Process {
     /// - `size` the number of bytes to copy
     ///
     /// ## Returns
-    /// A kernel-owned byte buffer if the user range is readable.
+    /// A kernel-owned byte buffer if the user range is readable and at most
+    /// [`USER_COPY_MAX_BYTES`] long.
     pub fn copy_from_user(page_table: &PageTable, addr: usize, size: usize) -> Option<Vec<u8>> {
-        if !Self::validate_user_pointer(page_table, addr, size, false) {
+        if size > USER_COPY_MAX_BYTES {
             return None;
         }
 
-        if size == 0 {
-            return Some(Vec::new());
+        let mut buffer = vec![0; size];
+        Self::copy_from_user_into(page_table, addr, &mut buffer)?;
+
+        Some(buffer)
+    }
+
+    /// Copies bytes from a validated user memory range into a kernel buffer.
+    ///
+    /// ## Arguments
+    ///
+    /// - `page_table` the process page table to validate against
+    /// - `addr` the start virtual address to copy from
+    /// - `buffer` the buffer to fill, its length is the number of bytes
+    ///
+    /// ## Returns
+    /// `Some(())` if the user range is readable.
+    pub fn copy_from_user_into(
+        page_table: &PageTable,
+        addr: usize,
+        buffer: &mut [u8],
+    ) -> Option<()> {
+        if !Self::validate_user_pointer(page_table, addr, buffer.len(), false) {
+            return None;
         }
 
-        let slice = unsafe { core::slice::from_raw_parts(addr as *const u8, size) };
-        let mut buffer = Vec::with_capacity(size);
-        buffer.extend_from_slice(slice);
+        if buffer.is_empty() {
+            return Some(());
+        }
 
-        Some(buffer)
+        unsafe { user_access::copy_from_user(addr, buffer) }
     }
 
     /// Copies bytes from kernel memory into a validated user memory range.
@@ -317,11 +499,7 @@ impl Process {
             return Some(());
         }
 
-        unsafe {
-            ptr::copy_nonoverlapping(bytes.as_ptr(), addr as *mut u8, bytes.len());
-        }
-
-        Some(())
+        unsafe { user_access::copy_to_user(addr, bytes) }
     }
 
     /// Copies a single value into a validated user memory range.
@@ -361,11 +539,8 @@ impl Process {
             return Some(());
         }
 
-        unsafe {
-            ptr::copy_nonoverlapping(values.as_ptr() as *const u8, addr as *mut u8, size);
-        }
-
-        Some(())
+        let bytes = unsafe { core::slice::from_raw_parts(values.as_ptr() as *const u8, size) };
+        unsafe { user_access::copy_to_user(addr, bytes) }
     }
 }
 
@@ -373,9 +548,15 @@ pub struct ProcessEntry {
     pub entry: usize,
     pub start_region: Arc<Mutex<ElfRegion>>,
     pub ring3_page_table: Option<PageTable>,
+
+    /// The page table the process runs on with page table isolation.
+    pub user_page_table: Option<PageTable>,
     pub stack: Option<Stack>,
 
     /// The initial user stack pointer, pointing at the argument
     /// frame below the stack top.
     pub initial_rsp: usize,
+
+    /// The memory areas of the loaded program and its stack.
+    pub vmas: VmaTree,
 }
@@ -0,0 +1,289 @@
+use core::fmt;
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use spin::Mutex;
+
+use crate::mem::{
+    paging::{entry::EntryFlags, Page, PageTable},
+    MemoryController, PageFrame, PageFrameAllocator, PAGE_SIZE,
+};
+
+/// Longest name a shared memory object can have.
+pub const SHM_NAME_MAX: usize = 255;
+
+/// Largest shared memory object, in pages.
+pub const SHM_MAX_PAGES: usize = 4096; // 16 MiB
+
+// lock order: the memory controller, then the registry, then the frames
+// of an object. Objects are only looked up by name in the registry, the
+// mappings and descriptors of processes hold on to them directly.
+static SHARED_MEMORY: Mutex<BTreeMap<String, Arc<SharedMemory>>> = Mutex::new(BTreeMap::new());
+
+/// A frame of a shared memory object, with the number of references to
+/// it: one for each page mapping it, and one while the object has a name.
+/// A frame without references is back in the frame allocator.
+#[derive(Debug)]
+struct SharedFrame {
+    frame: PageFrame,
+    refs: usize,
+}
+
+/// A named piece of memory that can be mapped into several processes.
+///
+/// Its frames are allocated when it is created and stay resident, they are
+/// freed once the name is removed and no page maps them anymore. Open
+/// descriptors don't keep frames, after the unlink they can only map the
+/// ones that are still mapped somewhere.
+pub struct SharedMemory {
+    name: String,
+    frames: Mutex<Vec<SharedFrame>>,
+}
+
+impl SharedMemory {
+    /// Creates an object whose frames are only referenced by its name.
+    ///
+    /// ## Arguments
+    ///
+    /// - `name` the name of the object
+    /// - `frames` the zeroed frames backing the object
+    pub fn new(name: String, frames: Vec<PageFrame>) -> Self {
+        let frames = frames
+            .into_iter()
+            .map(|frame| SharedFrame {
+                frame: frame,
+                refs: 1,
+            })
+            .collect();
+
+        Self {
+            name: name,
+            frames: Mutex::new(frames),
+        }
+    }
+
+    /// The size of the object in bytes, a whole number of pages.
+    pub fn size(&self) -> usize {
+        self.frames.lock().len() * PAGE_SIZE
+    }
+
+    /// Takes a reference to each frame of a part of the object, either to
+    /// all of them or to none.
+    ///
+    /// ## Arguments
+    ///
+    /// - `index` the page index inside the object the part starts at
+    /// - `count` the number of pages in the part
+    ///
+    /// ## Returns
+    /// The frames, or `None` when the part reaches past the end of the
+    /// object or one of its frames has been freed already.
+    pub fn acquire(&self, index: usize, count: usize) -> Option<Vec<PageFrame>> {
+        let mut frames = self.frames.lock();
+        let shared_frames = frames.get_mut(index..index.checked_add(count)?)?;
+
+        // a freed frame may belong to someone else by now
+        if shared_frames.iter().any(|frame| frame.refs == 0) {
+            return None;
+        }
+
+        let frames = shared_frames
+            .iter_mut()
+            .map(|shared_frame| {
+                shared_frame.refs += 1;
+                shared_frame.frame.clone()
+            })
+            .collect();
+
+        Some(frames)
+    }
+
+    /// Drops a reference to a frame of the object.
+    ///
+    /// ## Arguments
+    ///
+    /// - `index` the page index inside the object
+    ///
+    /// ## Returns
+    /// The frame when that was its last reference, the caller must free it.
+    pub fn release(&self, index: usize) -> Option<PageFrame> {
+        let mut frames = self.frames.lock();
+        let shared_frame = frames.get_mut(index)?;
+        if shared_frame.refs == 0 {
+            return None;
+        }
+
+        shared_frame.refs -= 1;
+        (shared_frame.refs == 0).then(|| shared_frame.frame.clone())
+    }
+
+    /// Maps a part of the object into a process page table.
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table
+    /// - `mc` the memory controller
+    /// - `start` the page-aligned address the part is mapped at
+    /// - `end` the page-aligned end of the mapping
+    /// - `offset` the page-aligned offset of the part inside the object
+    /// - `flags` the page table entry flags to be applied
+    ///
+    /// ## Returns
+    /// `None` when the part reaches past the end of the object or some of
+    /// its frames have been freed after the unlink, nothing is mapped then.
+    pub fn map(
+        &self,
+        table: &mut PageTable,
+        mc: &mut MemoryController,
+        start: usize,
+        end: usize,
+        offset: usize,
+        flags: EntryFlags,
+    ) -> Option<()> {
+        let frames = self.acquire(offset / PAGE_SIZE, (end - start) / PAGE_SIZE)?;
+        let pages = Page::range(Page::for_address(start), Page::for_address(end - 1));
+
+        for (page, frame) in pages.zip(frames) {
+            mc.map_user_frame(table, page, frame, flags);
+        }
+
+        Some(())
+    }
+
+    /// Unmaps a part of the object from a process page table, freeing the
+    /// frames that aren't referenced anymore.
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table
+    /// - `mc` the memory controller to return frames to
+    /// - `start` the page-aligned address the part is mapped at
+    /// - `end` the page-aligned end of the mapping
+    /// - `offset` the page-aligned offset of the part inside the object
+    pub fn unmap(
+        &self,
+        table: &mut PageTable,
+        mc: &mut MemoryController,
+        start: usize,
+        end: usize,
+        offset: usize,
+    ) {
+        for page in Page::range(Page::for_address(start), Page::for_address(end - 1)) {
+            if mc.unmap_user_frame(table, page).is_none() {
+                continue;
+            }
+
+            let index = (offset + page.start_address() - start) / PAGE_SIZE;
+            if let Some(frame) = self.release(index) {
+                mc.frame_allocator.free(frame);
+            }
+        }
+    }
+
+    /// Drops the references the name holds, once the object is unlinked.
+    fn release_name(&self, mc: &mut MemoryController) {
+        let count = self.frames.lock().len();
+        for index in 0..count {
+            if let Some(frame) = self.release(index) {
+                mc.frame_allocator.free(frame);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for SharedMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedMemory")
+            .field("name", &self.name)
+            .field("size", &self.size())
+            .finish()
+    }
+}
+
+/// The shared memory object behind a memory area.
+#[derive(Clone, Debug)]
+pub struct SharedMapping {
+    pub object: Arc<SharedMemory>,
+
+    /// The offset inside the object the area starts at, page aligned.
+    pub offset: usize,
+}
+
+/// Opens a shared memory object by name, creating it when asked to.
+///
+/// ## Arguments
+///
+/// - `name` the name of the object
+/// - `size` the size in bytes a new object gets, rounded up to whole
+///   pages. An existing object must be at least this large.
+/// - `create` whether a missing object is created
+/// - `exclusive` whether opening an existing object fails
+/// - `mc` the memory controller to allocate the frames from
+///
+/// ## Returns
+/// The object, or `None` when the name is invalid, the object doesn't
+/// exist or has the wrong size, or there aren't enough free frames.
+pub fn open(
+    name: &str,
+    size: usize,
+    create: bool,
+    exclusive: bool,
+    mc: &mut MemoryController,
+) -> Option<Arc<SharedMemory>> {
+    if name.is_empty() || name.len() > SHM_NAME_MAX || name.contains('/') {
+        return None;
+    }
+
+    let mut objects = SHARED_MEMORY.lock();
+    if let Some(object) = objects.get(name) {
+        if create && exclusive || size > object.size() {
+            return None;
+        }
+
+        return Some(object.clone());
+    }
+
+    if !create || size == 0 {
+        return None;
+    }
+
+    let page_count = size.div_ceil(PAGE_SIZE);
+    if page_count > SHM_MAX_PAGES || page_count > mc.frame_allocator.free_count() {
+        return None;
+    }
+
+    let mut frames = Vec::with_capacity(page_count);
+    for _ in 0..page_count {
+        let Some(frame) = mc.alloc_zeroed_frame() else {
+            frames
+                .into_iter()
+                .for_each(|frame| mc.frame_allocator.free(frame));
+            return None;
+        };
+
+        frames.push(frame);
+    }
+
+    let object = Arc::new(SharedMemory::new(String::from(name), frames));
+    objects.insert(String::from(name), object.clone());
+
+    Some(object)
+}
+
+/// Removes the name of a shared memory object. Processes that still have
+/// it mapped keep using it, its frames are freed when the last mapping
+/// goes away. Frames nobody maps are freed right away, descriptors that
+/// are still open can't map them anymore.
+///
+/// ## Arguments
+///
+/// - `name` the name of the object
+/// - `mc` the memory controller to return frames to
+///
+/// ## Returns
+/// `None` when no object has the name.
+pub fn unlink(name: &str, mc: &mut MemoryController) -> Option<()> {
+    let object = SHARED_MEMORY.lock().remove(name)?;
+    object.release_name(mc);
+
+    Some(())
+}
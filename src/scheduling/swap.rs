@@ -0,0 +1,267 @@
+use alloc::{vec, vec::Vec};
+use spin::Mutex;
+
+use crate::mem::{
+    paging::{entry::EntryFlags, Page, PageTable},
+    swap::{self, SWAP_BATCH_PAGES, SWAP_LOW_WATERMARK},
+    MemoryController, PageFrame, GLOBAL_MEMORY_CONTROLLER, PAGE_SIZE,
+};
+
+use super::{
+    process::Process,
+    vma::{FaultError, VmaBacking, VmaTree},
+};
+
+/// Position of the clock hand, the next scan continues at this address
+/// of the process with this pid.
+struct ClockHand {
+    pid: usize,
+    addr: usize,
+}
+
+static CLOCK_HAND: Mutex<ClockHand> = Mutex::new(ClockHand { pid: 0, addr: 0 });
+
+/// A cold page picked for eviction, its contents are written to the swap
+/// file before its frame is freed.
+struct Victim {
+    table: PageTable,
+    page: Page,
+    frame: PageFrame,
+    slot: usize,
+    bytes: Vec<u8>,
+}
+
+/// Evicts cold user pages when the free frames run low.
+///
+/// Must be called with the process list locked and without the memory
+/// controller held.
+///
+/// ## Arguments
+///
+/// - `processes` every process
+pub fn reclaim_if_low(processes: &[Process]) {
+    let low = {
+        let mc = GLOBAL_MEMORY_CONTROLLER.lock();
+        mc.as_ref()
+            .is_some_and(|mc| mc.frame_allocator.free_count() < SWAP_LOW_WATERMARK)
+    };
+
+    if low {
+        reclaim(processes, SWAP_BATCH_PAGES);
+    }
+}
+
+/// Evicts cold user pages with the clock algorithm. Pages that were
+/// accessed since the hand last passed them get a second chance, the
+/// others are written to the swap file, or just dropped when they are
+/// clean pages of a mapped file.
+///
+/// Must be called with the process list locked and without the memory
+/// controller held.
+///
+/// ## Arguments
+///
+/// - `processes` every process
+/// - `count` the number of pages to evict
+///
+/// ## Returns
+/// The number of frames freed.
+pub fn reclaim(processes: &[Process], count: usize) -> usize {
+    let mut victims = Vec::new();
+    let dropped = {
+        let mut mc = GLOBAL_MEMORY_CONTROLLER.lock();
+        let Some(mc) = mc.as_mut() else {
+            return 0;
+        };
+
+        select_victims(processes, mc, count, &mut victims)
+    };
+
+    // the disk driver needs the memory controller,
+    // so the pages are written without holding it
+    victims.retain(|victim| {
+        let written = swap::write_slot(victim.slot, &victim.bytes).is_some();
+        if !written {
+            swap::free_slot(victim.slot);
+        }
+
+        written
+    });
+
+    let mut mc = GLOBAL_MEMORY_CONTROLLER.lock();
+    let Some(mc) = mc.as_mut() else {
+        return dropped;
+    };
+
+    let mut evicted = 0;
+    for mut victim in victims {
+        // a page that was remapped in the meantime keeps its frame
+        let still_mapped = victim
+            .table
+            .get_entry(victim.page, &mut mc.temp_mapper)
+            .and_then(|entry| entry.get_frame())
+            .is_some_and(|frame| frame == victim.frame);
+
+        if still_mapped
+            && mc
+                .evict_user_page(&mut victim.table, victim.page, Some(victim.slot))
+                .is_some()
+        {
+            evicted += 1;
+        } else {
+            swap::free_slot(victim.slot);
+        }
+    }
+
+    dropped + evicted
+}
+
+/// Moves the clock hand over the pages of every process, clearing the
+/// accessed bits and picking pages whose bit was still clear.
+///
+/// ## Returns
+/// The number of clean file pages that were dropped right away.
+fn select_victims(
+    processes: &[Process],
+    mc: &mut MemoryController,
+    count: usize,
+    victims: &mut Vec<Victim>,
+) -> usize {
+    if processes.is_empty() {
+        return 0;
+    }
+
+    let mut hand = CLOCK_HAND.lock();
+    let first = processes
+        .iter()
+        .position(|process| process.pid == hand.pid)
+        .unwrap_or_else(|| {
+            hand.addr = 0;
+            0
+        });
+
+    let mut dropped = 0;
+
+    // every process is visited twice, so pages that only had their
+    // accessed bit cleared on the first visit can still be picked
+    for step in 0..=2 * processes.len() {
+        let process = &processes[(first + step) % processes.len()];
+        let Some(mut table) = process.ring3_page_table.clone() else {
+            continue;
+        };
+
+        let start = if step == 0 { hand.addr } else { 0 };
+        for area in process.user_memory.vmas.iter() {
+            if area.end <= start {
+                continue;
+            }
+
+            let first_page = Page::for_address(area.start.max(start));
+            let last_page = Page::for_address(area.end - 1);
+            for page in Page::range(first_page, last_page) {
+                if victims.len() + dropped >= count {
+                    hand.pid = process.pid;
+                    hand.addr = page.start_address();
+                    return dropped;
+                }
+
+                let Some(entry) = table.get_entry(page, &mut mc.temp_mapper) else {
+                    continue;
+                };
+
+                // huge pages are never swapped, their frames are freed
+                // together
+                let flags = entry.flags();
+                if !flags.contains(EntryFlags::PRESENT) || flags.contains(EntryFlags::HUGE_PAGE) {
+                    continue;
+                }
+
+                if flags.contains(EntryFlags::ACCESSED) {
+                    mc.clear_user_flags(&mut table, page, EntryFlags::ACCESSED);
+                    continue;
+                }
+
+                match &area.backing {
+                    // clean file pages are read from the file again
+                    VmaBacking::File(_) if !flags.contains(EntryFlags::DIRTY) => {
+                        if mc.evict_user_page(&mut table, page, None).is_some() {
+                            dropped += 1;
+                        }
+
+                        continue;
+                    }
+
+                    // dirty shared pages are written back on msync and munmap
+                    VmaBacking::File(mapping) if mapping.shared => continue,
+
+                    // other processes may map the same frame
+                    VmaBacking::Shared(_) => continue,
+                    _ => {}
+                }
+
+                let Some(frame) = entry.get_frame() else {
+                    continue;
+                };
+
+                let Some(slot) = swap::alloc_slot() else {
+                    return dropped;
+                };
+
+                let mut bytes = vec![0; PAGE_SIZE];
+                if mc.read_user_page(&mut table, page, &mut bytes).is_none() {
+                    swap::free_slot(slot);
+                    continue;
+                }
+
+                victims.push(Victim {
+                    table: table.clone(),
+                    page: page,
+                    frame: frame,
+                    slot: slot,
+                    bytes: bytes,
+                });
+            }
+        }
+    }
+
+    dropped
+}
+
+/// Reads a swapped out page back from the swap file for a page fault.
+///
+/// Must be called without the memory controller held, the disk driver
+/// needs it.
+///
+/// ## Arguments
+///
+/// - `vmas` the memory areas of the process
+/// - `table` the process page table
+/// - `addr` the faulting address
+/// - `write` whether the fault was caused by a write
+/// - `slot` the swap slot holding the page
+pub fn swap_in(
+    vmas: &VmaTree,
+    table: &mut PageTable,
+    addr: usize,
+    write: bool,
+    slot: usize,
+) -> Result<(), FaultError> {
+    let area = vmas.find(addr).ok_or(FaultError::Unmapped)?;
+    if write && !area.flags.contains(EntryFlags::WRITABLE) {
+        return Err(FaultError::ReadOnly);
+    }
+
+    let mut bytes = vec![0; PAGE_SIZE];
+    swap::read_slot(slot, &mut bytes).ok_or(FaultError::ReadFailed)?;
+
+    let mut mc = GLOBAL_MEMORY_CONTROLLER.lock();
+    let mc = mc.as_mut().ok_or(FaultError::OutOfMemory)?;
+
+    let page = Page::for_address(addr);
+    mc.map_user(table, page, page, area.flags)
+        .ok_or(FaultError::OutOfMemory)?;
+    mc.fill_user_page(table, page, &bytes);
+    swap::free_slot(slot);
+
+    Ok(())
+}
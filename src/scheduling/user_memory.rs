@@ -0,0 +1,326 @@
+use crate::mem::{
+    aslr,
+    paging::{entry::EntryFlags, Page, PageTable},
+    MemoryController, PAGE_SIZE,
+};
+
+use alloc::vec::Vec;
+
+use super::{
+    shm::SharedMapping,
+    vma::{DirtyPage, FileMapping, Vma, VmaBacking, VmaTree},
+};
+
+/// Lowest start of the program break region of a process.
+pub const USER_HEAP_START: usize = 0x0000_7100_0000_0000;
+
+/// Number of page offsets the program break region is randomly slid by.
+pub const USER_HEAP_RANDOM_PAGES: usize = 0x10_0000; // 4 GiB
+
+/// Maximum size the program break region can grow to.
+pub const USER_HEAP_MAX_SIZE: usize = 1024 * 1024 * 1024; // 1 GiB
+
+/// Start of the region anonymous and file mappings are placed in.
+pub const USER_MMAP_START: usize = 0x0000_7200_0000_0000;
+
+/// End (exclusive) of the region anonymous and file mappings are placed in.
+pub const USER_MMAP_END: usize = 0x0000_7300_0000_0000;
+
+/// The user address space of a process, its memory areas and the
+/// program break.
+#[derive(Clone, Debug)]
+pub struct UserMemory {
+    /// Start of the heap area, the program break never moves below it.
+    pub heap_start: usize,
+
+    /// The current program break, the heap area reaches up to the
+    /// page-aligned break.
+    pub brk: usize,
+
+    /// Every memory area of the process, from the program image to the
+    /// stack and memory mappings.
+    pub vmas: VmaTree,
+}
+
+impl UserMemory {
+    pub fn new(vmas: VmaTree) -> Self {
+        let heap_start = USER_HEAP_START + aslr::random_page_offset(USER_HEAP_RANDOM_PAGES);
+
+        Self {
+            heap_start: heap_start,
+            brk: heap_start,
+            vmas: vmas,
+        }
+    }
+
+    /// Moves the program break, growing or shrinking the heap area.
+    /// New heap pages are mapped on first access.
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table
+    /// - `mc` the memory controller to return frames to
+    /// - `new_brk` the requested program break
+    ///
+    /// ## Returns
+    /// The new program break, or `None` when it is outside the heap region.
+    pub fn set_brk(
+        &mut self,
+        table: &mut PageTable,
+        mc: &mut MemoryController,
+        new_brk: usize,
+    ) -> Option<usize> {
+        if new_brk < self.heap_start || new_brk > self.heap_start + USER_HEAP_MAX_SIZE {
+            return None;
+        }
+
+        let mapped_end = page_align_up(self.brk)?;
+        let new_mapped_end = page_align_up(new_brk)?;
+
+        if new_mapped_end < mapped_end {
+            let start = Page::for_address(new_mapped_end);
+            let end = Page::for_address(mapped_end - 1);
+            mc.unmap_user(table, start, end);
+        }
+
+        if new_mapped_end != mapped_end {
+            self.vmas.remove(self.heap_start);
+            if new_mapped_end > self.heap_start {
+                self.vmas.insert(Vma {
+                    start: self.heap_start,
+                    end: new_mapped_end,
+                    flags: Self::flags(true),
+                    backing: VmaBacking::Anonymous,
+                })?;
+            }
+        }
+
+        self.brk = new_brk;
+        Some(new_brk)
+    }
+
+    /// Reserves zeroed pages in the mapping region, they are mapped on
+    /// first access.
+    ///
+    /// ## Arguments
+    ///
+    /// - `size` the mapping size in bytes, rounded up to whole pages
+    /// - `writable` whether the mapping is writable
+    /// - `addr` the page-aligned address to place the mapping at, or
+    ///   `None` for the first free gap
+    ///
+    /// ## Returns
+    /// The start address of the mapping, or `None` when no gap is large
+    /// enough or the requested range is taken.
+    pub fn map_anonymous(
+        &mut self,
+        size: usize,
+        writable: bool,
+        addr: Option<usize>,
+    ) -> Option<usize> {
+        self.map_area(size, writable, VmaBacking::Anonymous, addr)
+    }
+
+    /// Maps a file into the mapping region, its pages are read from the
+    /// file on first access.
+    ///
+    /// ## Arguments
+    ///
+    /// - `mapping` the file and the page-aligned offset to map from
+    /// - `size` the mapping size in bytes, rounded up to whole pages
+    /// - `writable` whether the mapping is writable
+    /// - `addr` the page-aligned address to place the mapping at, or
+    ///   `None` for the first free gap
+    ///
+    /// ## Returns
+    /// The start address of the mapping, or `None` when no gap is large
+    /// enough or the requested range is taken.
+    pub fn map_file(
+        &mut self,
+        mapping: FileMapping,
+        size: usize,
+        writable: bool,
+        addr: Option<usize>,
+    ) -> Option<usize> {
+        if mapping.offset % PAGE_SIZE != 0 {
+            return None;
+        }
+
+        self.map_area(size, writable, VmaBacking::File(mapping), addr)
+    }
+
+    /// Maps a part of a shared memory object into the mapping region. Its
+    /// frames are mapped right away, they already exist.
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table
+    /// - `mc` the memory controller
+    /// - `mapping` the object and the page-aligned offset to map from
+    /// - `size` the mapping size in bytes, rounded up to whole pages
+    /// - `writable` whether the mapping is writable
+    /// - `addr` the page-aligned address to place the mapping at, or
+    ///   `None` for the first free gap
+    ///
+    /// ## Returns
+    /// The start address of the mapping, or `None` when the part reaches
+    /// past the end of the object or there is no room for it.
+    pub fn map_shared(
+        &mut self,
+        table: &mut PageTable,
+        mc: &mut MemoryController,
+        mapping: SharedMapping,
+        size: usize,
+        writable: bool,
+        addr: Option<usize>,
+    ) -> Option<usize> {
+        if mapping.offset % PAGE_SIZE != 0
+            || mapping.offset.checked_add(size)? > mapping.object.size()
+        {
+            return None;
+        }
+
+        let object = mapping.object.clone();
+        let offset = mapping.offset;
+        let start = self.map_area(size, writable, VmaBacking::Shared(mapping), addr)?;
+        let end = start + page_align_up(size)?;
+
+        if object
+            .map(table, mc, start, end, offset, Self::flags(writable))
+            .is_none()
+        {
+            self.vmas.remove(start);
+            return None;
+        }
+
+        Some(start)
+    }
+
+    fn map_area(
+        &mut self,
+        size: usize,
+        writable: bool,
+        backing: VmaBacking,
+        addr: Option<usize>,
+    ) -> Option<usize> {
+        if size == 0 {
+            return None;
+        }
+
+        let length = page_align_up(size)?;
+        let start = match addr {
+            Some(addr) => {
+                self.mapping_range_end(addr, length)?;
+                addr
+            }
+            None => self.vmas.find_gap(USER_MMAP_START, USER_MMAP_END, length)?,
+        };
+
+        // a fixed mapping never replaces the areas it overlaps
+        self.vmas.insert(Vma {
+            start: start,
+            end: start + length,
+            flags: Self::flags(writable),
+            backing: backing,
+        })?;
+
+        Some(start)
+    }
+
+    /// Unmaps a page-aligned range that lies within a single mapping,
+    /// splitting the mapping when the range is in its middle.
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table
+    /// - `mc` the memory controller to return frames to
+    /// - `addr` the page-aligned start of the range
+    /// - `size` the range size in bytes, rounded up to whole pages
+    ///
+    /// ## Returns
+    /// The dirty pages of a shared file mapping that still need to be
+    /// written back, or `None` when the range is not covered by a mapping.
+    pub fn unmap(
+        &mut self,
+        table: &mut PageTable,
+        mc: &mut MemoryController,
+        addr: usize,
+        size: usize,
+    ) -> Option<Vec<DirtyPage>> {
+        let end = self.mapping_range_end(addr, size)?;
+        let area = self.vmas.split_off(addr, end)?;
+
+        Some(VmaTree::unmap_area(table, mc, &area))
+    }
+
+    /// Collects the modified pages of the shared file mappings in a range,
+    /// so they can be written back to their files.
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table
+    /// - `mc` the memory controller
+    /// - `addr` the page-aligned start of the range
+    /// - `size` the range size in bytes, rounded up to whole pages
+    ///
+    /// ## Returns
+    /// The dirty pages, or `None` when the range isn't fully mapped.
+    pub fn sync(
+        &mut self,
+        table: &mut PageTable,
+        mc: &mut MemoryController,
+        addr: usize,
+        size: usize,
+    ) -> Option<Vec<DirtyPage>> {
+        let end = self.mapping_range_end(addr, size)?;
+        if !self.vmas.is_accessible(addr, end - addr, false) {
+            return None;
+        }
+
+        Some(self.vmas.take_dirty_pages(table, mc, addr, end))
+    }
+
+    /// Unmaps every memory area of the process.
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table
+    /// - `mc` the memory controller to return frames to
+    ///
+    /// ## Returns
+    /// The dirty pages of shared file mappings, to be written back.
+    pub fn free(&mut self, table: &mut PageTable, mc: &mut MemoryController) -> Vec<DirtyPage> {
+        self.brk = self.heap_start;
+        self.vmas.unmap_all(table, mc)
+    }
+
+    /// Checks that a range lies in the mapping region.
+    ///
+    /// ## Returns
+    /// The page-aligned end of the range.
+    fn mapping_range_end(&self, addr: usize, size: usize) -> Option<usize> {
+        if size == 0 || addr % PAGE_SIZE != 0 || addr < USER_MMAP_START {
+            return None;
+        }
+
+        let end = addr.checked_add(page_align_up(size)?)?;
+        (end <= USER_MMAP_END).then_some(end)
+    }
+
+    fn flags(writable: bool) -> EntryFlags {
+        let mut flags = EntryFlags::RING3_ACCESSIBLE | EntryFlags::NO_EXECUTE;
+        if writable {
+            flags |= EntryFlags::WRITABLE;
+        }
+
+        flags
+    }
+}
+
+/// Rounds an address or size up to the next page boundary.
+///
+/// ## Returns
+/// The aligned value, or `None` when it doesn't fit in the address space.
+fn page_align_up(addr: usize) -> Option<usize> {
+    Some(addr.checked_add(PAGE_SIZE - 1)? & !(PAGE_SIZE - 1))
+}
@@ -0,0 +1,596 @@
+use core::fmt;
+
+use alloc::{collections::BTreeMap, sync::Arc, vec, vec::Vec};
+use spin::RwLock;
+
+use crate::{
+    fs::fs::File,
+    mem::{
+        paging::{entry::EntryFlags, Page, PageTable, HUGE_PAGE_SIZE, PAGES_PER_HUGE_PAGE},
+        MemoryController, PAGE_SIZE,
+    },
+};
+
+use super::shm::SharedMapping;
+
+/// The file behind a memory-mapped file area.
+#[derive(Clone)]
+pub struct FileMapping {
+    pub file: Arc<RwLock<dyn File>>,
+
+    /// The file offset the area starts at, page aligned.
+    pub offset: usize,
+
+    /// Whether writes to the area are carried back to the file, instead
+    /// of staying private to the process.
+    pub shared: bool,
+}
+
+impl fmt::Debug for FileMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileMapping")
+            .field("offset", &self.offset)
+            .field("shared", &self.shared)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Where the contents of a virtual memory area come from.
+#[derive(Clone, Debug)]
+pub enum VmaBacking {
+    /// Zero-filled memory that is mapped on first access, like the BSS,
+    /// the program break and anonymous mappings.
+    Anonymous,
+
+    /// The segments of the program image, filled from the program file
+    /// when it is loaded.
+    Image,
+
+    /// A memory-mapped file, its pages are read from the file on first
+    /// access.
+    File(FileMapping),
+
+    /// A user stack that is mapped on first access, the page right below
+    /// it is its guard page.
+    Stack,
+
+    /// A shared memory object, its frames are mapped when the area is
+    /// created and never swapped out.
+    Shared(SharedMapping),
+}
+
+/// A page-aligned range of user memory that belongs to a process.
+#[derive(Clone, Debug)]
+pub struct Vma {
+    pub start: usize,
+
+    /// The address right after the area.
+    pub end: usize,
+    pub flags: EntryFlags,
+    pub backing: VmaBacking,
+}
+
+impl Vma {
+    pub fn contains(&self, addr: usize) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// Whether the pages of the area are mapped on first access.
+    pub fn is_lazy(&self) -> bool {
+        !matches!(self.backing, VmaBacking::Image | VmaBacking::Shared(_))
+    }
+
+    /// The file mapping of the area, if writes to it go back to the file.
+    fn shared_mapping(&self) -> Option<&FileMapping> {
+        match &self.backing {
+            VmaBacking::File(mapping) if mapping.shared => Some(mapping),
+            _ => None,
+        }
+    }
+
+    /// The part of the area between two page-aligned addresses inside it.
+    fn slice(&self, start: usize, end: usize) -> Vma {
+        let mut backing = self.backing.clone();
+        match &mut backing {
+            VmaBacking::File(mapping) => mapping.offset += start - self.start,
+            VmaBacking::Shared(mapping) => mapping.offset += start - self.start,
+            _ => {}
+        }
+
+        Vma {
+            start: start,
+            end: end,
+            flags: self.flags,
+            backing: backing,
+        }
+    }
+
+    /// Copies the dirty pages of a shared file mapping and marks them
+    /// clean again.
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table
+    /// - `mc` the memory controller
+    /// - `dirty_pages` the list the dirty pages are added to
+    fn take_dirty_pages(
+        &self,
+        table: &mut PageTable,
+        mc: &mut MemoryController,
+        dirty_pages: &mut Vec<DirtyPage>,
+    ) {
+        let Some(mapping) = self.shared_mapping() else {
+            return;
+        };
+
+        if !self.flags.contains(EntryFlags::WRITABLE) {
+            return;
+        }
+
+        for page in Page::range(self.start_page(), self.end_page()) {
+            let Some(flags) = mc.clear_user_flags(table, page, EntryFlags::DIRTY) else {
+                continue;
+            };
+
+            if !flags.contains(EntryFlags::DIRTY) {
+                continue;
+            }
+
+            let mut bytes = vec![0; PAGE_SIZE];
+            if mc.read_user_page(table, page, &mut bytes).is_none() {
+                continue;
+            }
+
+            dirty_pages.push(DirtyPage {
+                file: mapping.file.clone(),
+                offset: mapping.offset + (page.start_address() - self.start),
+                bytes: bytes,
+            });
+        }
+    }
+
+    /// Whether the 2 MiB around a page lie inside the area and may be
+    /// mapped as a single huge page. Only anonymous memory is, the other
+    /// kinds are read in or swapped page by page.
+    fn fits_huge_page(&self, page: Page) -> bool {
+        let huge_start = page.huge_page_start().start_address();
+
+        matches!(self.backing, VmaBacking::Anonymous)
+            && huge_start >= self.start
+            && huge_start + HUGE_PAGE_SIZE <= self.end
+    }
+
+    fn start_page(&self) -> Page {
+        Page::for_address(self.start)
+    }
+
+    fn end_page(&self) -> Page {
+        Page::for_address(self.end - 1)
+    }
+}
+
+/// Huge pages are only mapped while this many frames are free, so large
+/// mappings don't eat up the frames left before swapping starts.
+const HUGE_PAGE_MIN_FREE_FRAMES: usize = 4 * PAGES_PER_HUGE_PAGE;
+
+/// Why a page fault could not be resolved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultError {
+    /// The address is not inside any area.
+    Unmapped,
+
+    /// The address is on the guard page below a stack.
+    StackOverflow,
+
+    /// The area does not allow writes.
+    ReadOnly,
+
+    /// There is no free page frame left.
+    OutOfMemory,
+
+    /// The page could not be read from its file or the swap file.
+    ReadFailed,
+}
+
+/// A modified page of a shared file mapping, copied out of the process
+/// so it can be written back to its file.
+pub struct DirtyPage {
+    file: Arc<RwLock<dyn File>>,
+    offset: usize,
+    bytes: Vec<u8>,
+}
+
+impl DirtyPage {
+    /// Writes the page back to its file. Files are never grown through a
+    /// mapping, bytes past the end of the file are dropped.
+    ///
+    /// Must be called without the memory controller held, the disk driver
+    /// needs it.
+    ///
+    /// ## Returns
+    /// The number of bytes written.
+    pub fn write_back(&self) -> Option<usize> {
+        let file = self.file.read();
+        let size = file.size();
+        if self.offset >= size {
+            return Some(0);
+        }
+
+        let length = (size - self.offset).min(self.bytes.len());
+        file.write(self.offset, &self.bytes[..length])
+    }
+}
+
+/// The memory areas of a process, keyed by their start address.
+#[derive(Clone, Debug)]
+pub struct VmaTree {
+    areas: BTreeMap<usize, Vma>,
+}
+
+impl VmaTree {
+    pub fn new() -> Self {
+        Self {
+            areas: BTreeMap::new(),
+        }
+    }
+
+    /// Adds an area.
+    ///
+    /// ## Arguments
+    ///
+    /// - `area` the page-aligned area to add
+    ///
+    /// ## Returns
+    /// `Some(())` when the area was added, or `None` when it is empty or
+    /// overlaps another area.
+    pub fn insert(&mut self, area: Vma) -> Option<()> {
+        if area.start >= area.end || self.overlaps(area.start, area.end) {
+            return None;
+        }
+
+        self.areas.insert(area.start, area);
+        Some(())
+    }
+
+    /// Removes the area starting at an address.
+    pub fn remove(&mut self, start: usize) -> Option<Vma> {
+        self.areas.remove(&start)
+    }
+
+    /// Finds the area containing an address.
+    pub fn find(&self, addr: usize) -> Option<&Vma> {
+        let (_, area) = self.areas.range(..=addr).next_back()?;
+        area.contains(addr).then_some(area)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Vma> {
+        self.areas.values()
+    }
+
+    /// Whether any area overlaps the range from `start` up to `end`.
+    pub fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.find(start).is_some() || self.areas.range(start..end).next().is_some()
+    }
+
+    /// Finds the lowest free gap of `length` bytes inside a region.
+    ///
+    /// ## Arguments
+    ///
+    /// - `region_start` the page-aligned start of the region
+    /// - `region_end` the end of the region
+    /// - `length` the page-aligned gap size in bytes
+    pub fn find_gap(&self, region_start: usize, region_end: usize, length: usize) -> Option<usize> {
+        let mut start = match self.find(region_start) {
+            Some(area) => area.end,
+            None => region_start,
+        };
+
+        for area in self.areas.range(start..region_end).map(|(_, a)| a) {
+            if area.start - start >= length {
+                break;
+            }
+
+            start = area.end;
+        }
+
+        (region_end.checked_sub(start)? >= length).then_some(start)
+    }
+
+    /// Cuts a range out of the area containing it, keeping whatever remains
+    /// on either side of the range as separate areas.
+    ///
+    /// ## Arguments
+    ///
+    /// - `start` the page-aligned start of the range
+    /// - `end` the page-aligned end of the range
+    ///
+    /// ## Returns
+    /// The removed part of the area, or `None` when the range is not
+    /// covered by a single area.
+    pub fn split_off(&mut self, start: usize, end: usize) -> Option<Vma> {
+        let area = self.find(start)?;
+        if start >= end || end > area.end {
+            return None;
+        }
+
+        let area_start = area.start;
+        let area = self.areas.remove(&area_start)?;
+        if area.start < start {
+            self.areas.insert(area.start, area.slice(area.start, start));
+        }
+
+        if end < area.end {
+            self.areas.insert(end, area.slice(end, area.end));
+        }
+
+        Some(area.slice(start, end))
+    }
+
+    /// Whether a user range lies entirely inside areas that allow the
+    /// requested access.
+    ///
+    /// ## Arguments
+    ///
+    /// - `addr` the start of the range
+    /// - `size` the size of the range in bytes
+    /// - `writable` whether the range must be writable
+    pub fn is_accessible(&self, addr: usize, size: usize, writable: bool) -> bool {
+        let Some(end) = addr.checked_add(size) else {
+            return false;
+        };
+
+        let mut cursor = addr;
+        while cursor < end {
+            let Some(area) = self.find(cursor) else {
+                return false;
+            };
+
+            let flags = area.flags;
+            if !flags.contains(EntryFlags::RING3_ACCESSIBLE)
+                || (writable && !flags.contains(EntryFlags::WRITABLE))
+            {
+                return false;
+            }
+
+            cursor = area.end;
+        }
+
+        true
+    }
+
+    /// Reads the file contents of the page containing an address, when it
+    /// lies inside a memory-mapped file.
+    ///
+    /// Must be called without the memory controller held, the disk driver
+    /// needs it.
+    ///
+    /// ## Arguments
+    ///
+    /// - `addr` the address inside the page
+    ///
+    /// ## Returns
+    /// A page of file contents, zero-filled past the end of the file, or
+    /// `None` when the address isn't file backed or the file can't be read.
+    pub fn read_file_page(&self, addr: usize) -> Option<Vec<u8>> {
+        let area = self.find(addr)?;
+        let VmaBacking::File(mapping) = &area.backing else {
+            return None;
+        };
+
+        let page_start = Page::for_address(addr).start_address();
+        let offset = mapping.offset + (page_start - area.start);
+
+        let mut bytes = vec![0; PAGE_SIZE];
+        mapping.file.read().read_at(offset, &mut bytes)?;
+
+        Some(bytes)
+    }
+
+    /// Maps a page for a page fault inside a lazy area, zeroed or filled
+    /// with the file contents read through [`Self::read_file_page`].
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table
+    /// - `mc` the memory controller to allocate frames from
+    /// - `addr` the faulting address
+    /// - `write` whether the fault was caused by a write
+    /// - `contents` the file contents of the page, for file-backed areas
+    pub fn handle_fault(
+        &self,
+        table: &mut PageTable,
+        mc: &mut MemoryController,
+        addr: usize,
+        write: bool,
+        contents: Option<&[u8]>,
+    ) -> Result<(), FaultError> {
+        let Some(area) = self.find(addr) else {
+            let on_guard_page = self.iter().any(|area| {
+                matches!(area.backing, VmaBacking::Stack)
+                    && addr < area.start
+                    && addr >= area.start - PAGE_SIZE
+            });
+
+            if on_guard_page {
+                return Err(FaultError::StackOverflow);
+            }
+
+            return Err(FaultError::Unmapped);
+        };
+
+        // image pages are all mapped when the program is loaded
+        if !area.is_lazy() {
+            return Err(FaultError::Unmapped);
+        }
+
+        if write && !area.flags.contains(EntryFlags::WRITABLE) {
+            return Err(FaultError::ReadOnly);
+        }
+
+        let is_file = matches!(area.backing, VmaBacking::File(_));
+        if is_file && contents.is_none() {
+            return Err(FaultError::ReadFailed);
+        }
+
+        let page = Page::for_address(addr);
+        if area.fits_huge_page(page)
+            && mc.frame_allocator.free_count() >= HUGE_PAGE_MIN_FREE_FRAMES
+            && mc
+                .map_user_huge(table, page.huge_page_start(), area.flags)
+                .is_some()
+        {
+            return Ok(());
+        }
+
+        mc.map_user(table, page, page, area.flags)
+            .ok_or(FaultError::OutOfMemory)?;
+
+        if let Some(contents) = contents {
+            mc.fill_user_page(table, page, contents);
+        }
+
+        Ok(())
+    }
+
+    /// Maps every page of a user range that lies inside a lazy area and is
+    /// not mapped yet, so the kernel can access the range without faulting.
+    /// Pages of memory-mapped files and swapped out pages are left to the
+    /// caller, since they can't be read with the memory controller held.
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table
+    /// - `mc` the memory controller to allocate frames from
+    /// - `addr` the start of the range
+    /// - `size` the size of the range in bytes
+    ///
+    /// ## Returns
+    /// The addresses of the pages that still need to be read in, or `None`
+    /// when there are no free frames left.
+    pub fn populate(
+        &self,
+        table: &mut PageTable,
+        mc: &mut MemoryController,
+        addr: usize,
+        size: usize,
+    ) -> Option<Vec<usize>> {
+        let end = addr.checked_add(size)?;
+        let mut pending_pages = Vec::new();
+
+        for area in self.iter() {
+            let start = addr.max(area.start);
+            let end = end.min(area.end);
+            if start >= end {
+                continue;
+            }
+
+            for page in Page::range(Page::for_address(start), Page::for_address(end - 1)) {
+                let entry = table.get_entry(page, &mut mc.temp_mapper);
+                if let Some(entry) = entry.filter(|entry| !entry.is_unused()) {
+                    if entry.swap_slot().is_some() {
+                        pending_pages.push(page.start_address());
+                    }
+
+                    continue;
+                }
+
+                match area.backing {
+                    VmaBacking::Image | VmaBacking::Shared(_) => continue,
+                    VmaBacking::File(_) => {
+                        pending_pages.push(page.start_address());
+                        continue;
+                    }
+                    _ => {}
+                }
+
+                mc.map_user(table, page, page, area.flags)?;
+            }
+        }
+
+        Some(pending_pages)
+    }
+
+    /// Copies the dirty pages of the shared file mappings inside a range
+    /// and marks them clean again, so they can be written back.
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table
+    /// - `mc` the memory controller
+    /// - `start` the page-aligned start of the range
+    /// - `end` the page-aligned end of the range
+    pub fn take_dirty_pages(
+        &self,
+        table: &mut PageTable,
+        mc: &mut MemoryController,
+        start: usize,
+        end: usize,
+    ) -> Vec<DirtyPage> {
+        let mut dirty_pages = Vec::new();
+
+        for area in self.iter().filter(|area| area.shared_mapping().is_some()) {
+            let start = start.max(area.start);
+            let end = end.min(area.end);
+            if start < end {
+                area.slice(start, end)
+                    .take_dirty_pages(table, mc, &mut dirty_pages);
+            }
+        }
+
+        dirty_pages
+    }
+
+    /// Unmaps the pages of an area and frees their frames, the frames of
+    /// a shared memory object only once nothing else references them.
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table
+    /// - `mc` the memory controller to return frames to
+    /// - `area` the area to unmap
+    ///
+    /// ## Returns
+    /// The dirty pages of the area when it is a shared file mapping, to be
+    /// written back.
+    pub fn unmap_area(
+        table: &mut PageTable,
+        mc: &mut MemoryController,
+        area: &Vma,
+    ) -> Vec<DirtyPage> {
+        if let VmaBacking::Shared(mapping) = &area.backing {
+            let offset = mapping.offset;
+            mapping
+                .object
+                .unmap(table, mc, area.start, area.end, offset);
+            return Vec::new();
+        }
+
+        let mut dirty_pages = Vec::new();
+        area.take_dirty_pages(table, mc, &mut dirty_pages);
+
+        mc.unmap_user(table, area.start_page(), area.end_page());
+        dirty_pages
+    }
+
+    /// Unmaps every area and removes them from the tree.
+    ///
+    /// ## Arguments
+    ///
+    /// - `table` the process page table
+    /// - `mc` the memory controller to return frames to
+    ///
+    /// ## Returns
+    /// The dirty pages of shared file mappings, to be written back.
+    pub fn unmap_all(
+        &mut self,
+        table: &mut PageTable,
+        mc: &mut MemoryController,
+    ) -> Vec<DirtyPage> {
+        let mut dirty_pages = Vec::new();
+        for (_, area) in core::mem::take(&mut self.areas) {
+            dirty_pages.append(&mut Self::unmap_area(table, mc, &area));
+        }
+
+        dirty_pages
+    }
+}
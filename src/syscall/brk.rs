@@ -0,0 +1,9 @@
+// syscall 26 - move the program break of the current process
+
+use crate::{arch::x86_64::registers::FullInterruptStackFrame, scheduling};
+
+pub fn brk(stack: &FullInterruptStackFrame) -> Option<usize> {
+    let new_brk = stack.rdi;
+
+    scheduling::curr_process_brk(new_brk).or(Some(0))
+}
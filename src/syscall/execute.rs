@@ -132,7 +132,11 @@ pub fn execute(stack: &FullInterruptStackFrame) -> Option<usize> {
         region
     };
 
-    let Some(elf_entry) = elf::load(region, &argv) else {
+    // the child inherits the environment of the calling process
+    let environment = scheduling::get_current_environment();
+    let envp: Vec<&str> = environment.iter().map(|e| e.as_str()).collect();
+
+    let Some(elf_entry) = elf::load(region, &argv, &envp) else {
         log!(
             LogType::ERR,
             "execute: elf::load failed for path {:?}",
@@ -0,0 +1,35 @@
+// syscall 19 - read the metadata of an open file descriptor
+
+use crate::{
+    arch::x86_64::registers::FullInterruptStackFrame,
+    scheduling,
+    scheduling::process::{FileDescriptor, Process},
+};
+
+use super::stat::SyscallStat;
+
+pub fn fstat(stack: &FullInterruptStackFrame) -> Option<usize> {
+    let file_descriptor = stack.rdi;
+    let stat_addr = stack.rsi;
+
+    let Some(page_table) = scheduling::get_current_process_page_table() else {
+        return Some(0);
+    };
+
+    let metadata = match scheduling::get_current_file_descriptor(file_descriptor) {
+        Some(FileDescriptor::File(open_file)) => open_file.file.read().metadata(),
+        Some(FileDescriptor::Directory(open_directory)) => open_directory.directory.metadata(),
+        _ => None,
+    };
+
+    let Some(metadata) = metadata else {
+        return Some(0);
+    };
+
+    let stat = SyscallStat::from_metadata(&metadata);
+    if Process::copy_value_to_user(&page_table, stat_addr, &stat).is_none() {
+        return Some(0);
+    }
+
+    Some(1)
+}
@@ -0,0 +1,26 @@
+// syscall 23 - write the absolute path of the current working directory
+
+use crate::{
+    arch::x86_64::registers::FullInterruptStackFrame, scheduling, scheduling::process::Process,
+};
+
+pub fn getcwd(stack: &FullInterruptStackFrame) -> Option<usize> {
+    let buffer_addr = stack.rdi;
+    let buffer_size = stack.rsi;
+
+    let Some(page_table) = scheduling::get_current_process_page_table() else {
+        return Some(0);
+    };
+
+    let path = scheduling::get_current_cwd().absolute_path();
+    let path = path.as_bytes();
+    if path.len() > buffer_size {
+        return Some(0);
+    }
+
+    if Process::copy_slice_to_user(&page_table, buffer_addr, path).is_none() {
+        return Some(0);
+    }
+
+    Some(path.len())
+}
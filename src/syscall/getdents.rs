@@ -0,0 +1,41 @@
+// syscall 22 - read the next entries of an open directory descriptor
+
+use core::mem::size_of;
+
+use crate::{
+    arch::x86_64::registers::FullInterruptStackFrame, scheduling, scheduling::process::Process,
+};
+
+use super::read_dir::SyscallDirEntry;
+
+pub fn getdents(stack: &FullInterruptStackFrame) -> Option<usize> {
+    let file_descriptor = stack.rdi;
+    let buffer_addr = stack.rsi;
+    let max_items = stack.rdx;
+
+    let Some(page_table) = scheduling::get_current_process_page_table() else {
+        return Some(0);
+    };
+
+    let Some(buffer_size) = max_items.checked_mul(size_of::<SyscallDirEntry>()) else {
+        return Some(0);
+    };
+
+    if max_items == 0 || !Process::can_process_pointer(&page_table, buffer_addr, buffer_size, true)
+    {
+        return Some(0);
+    }
+
+    let Some(items) = scheduling::read_current_directory_descriptor(file_descriptor, max_items)
+    else {
+        return Some(0);
+    };
+
+    let directory_entries = SyscallDirEntry::from_items(&items, max_items);
+    let num_entries = directory_entries.len();
+    if Process::copy_slice_to_user(&page_table, buffer_addr, &directory_entries).is_none() {
+        return Some(0);
+    }
+
+    Some(num_entries)
+}
@@ -0,0 +1,62 @@
+// syscall 30 - read the kernel memory counters into a user struct
+
+use crate::{
+    arch::x86_64::registers::FullInterruptStackFrame, mem::stats::MemoryStats, scheduling,
+    scheduling::process::Process,
+};
+
+/// Memory counters as seen by userspace, frames and pages are counted
+/// in 4 KiB pages and the heap in bytes.
+#[repr(C)]
+pub struct SyscallMemInfo {
+    total_frames: u64,
+    free_frames: u64,
+    used_frames: u64,
+    heap_size: u64,
+    heap_used: u64,
+    heap_free: u64,
+    heap_largest_free: u64,
+    stack_remaining_pages: u64,
+    stack_free_ranges: u64,
+    stack_free_range_pages: u64,
+    page_table_pages: u64,
+    page_table_free_slots: u64,
+    swap_slots: u64,
+    swap_used_slots: u64,
+}
+
+impl SyscallMemInfo {
+    pub fn from_stats(stats: &MemoryStats) -> Self {
+        Self {
+            total_frames: stats.total_frames as u64,
+            free_frames: stats.free_frames as u64,
+            used_frames: stats.used_frames as u64,
+            heap_size: stats.heap.size as u64,
+            heap_used: stats.heap.used as u64,
+            heap_free: stats.heap.free as u64,
+            heap_largest_free: stats.heap.largest_free as u64,
+            stack_remaining_pages: stats.stack_remaining_pages as u64,
+            stack_free_ranges: stats.stack_free_ranges as u64,
+            stack_free_range_pages: stats.stack_free_range_pages as u64,
+            page_table_pages: stats.page_table_pages as u64,
+            page_table_free_slots: stats.page_table_free_slots as u64,
+            swap_slots: stats.swap_slots as u64,
+            swap_used_slots: stats.swap_used_slots as u64,
+        }
+    }
+}
+
+pub fn meminfo(stack: &FullInterruptStackFrame) -> Option<usize> {
+    let meminfo_addr = stack.rdi;
+
+    let Some(page_table) = scheduling::get_current_process_page_table() else {
+        return Some(0);
+    };
+
+    let meminfo = SyscallMemInfo::from_stats(&MemoryStats::collect());
+    if Process::copy_value_to_user(&page_table, meminfo_addr, &meminfo).is_none() {
+        return Some(0);
+    }
+
+    Some(1)
+}
@@ -0,0 +1,66 @@
+// syscall 27 - map anonymous memory, a file or a shared memory object into
+// the current process
+
+use crate::{
+    arch::x86_64::registers::FullInterruptStackFrame, mem::PAGE_SIZE, scheduling,
+    scheduling::process::FileDescriptor,
+};
+
+/// Pages may be written to.
+pub const PROT_WRITE: usize = 0x2;
+
+/// Writes to a file mapping are carried back to the file.
+pub const MAP_SHARED: usize = 0x01;
+
+/// Writes to a file mapping stay private to the process.
+pub const MAP_PRIVATE: usize = 0x02;
+
+/// The mapping is placed at the address in rdi, it fails when that range
+/// is taken.
+pub const MAP_FIXED: usize = 0x10;
+
+/// The mapping is not backed by a file.
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+pub fn mmap(stack: &FullInterruptStackFrame) -> Option<usize> {
+    let size = stack.rsi;
+    let protection = stack.rdx;
+    let flags = stack.r10;
+    let writable = protection & PROT_WRITE != 0;
+
+    // without MAP_FIXED the address in rdi is only a hint,
+    // and hints are ignored
+    let addr = (flags & MAP_FIXED != 0).then_some(stack.rdi);
+
+    if flags & MAP_ANONYMOUS != 0 {
+        return scheduling::curr_process_map_anonymous(size, writable, addr).or(Some(0));
+    }
+
+    let file_descriptor = stack.r8;
+    let offset = stack.r9;
+
+    let shared = match flags & (MAP_SHARED | MAP_PRIVATE) {
+        MAP_SHARED => true,
+        MAP_PRIVATE => false,
+        _ => return Some(0),
+    };
+
+    if offset % PAGE_SIZE != 0 {
+        return Some(0);
+    }
+
+    // shared memory objects can't be mapped privately
+    if let Some(FileDescriptor::SharedMemory(_)) =
+        scheduling::get_current_file_descriptor(file_descriptor)
+    {
+        if !shared {
+            return Some(0);
+        }
+
+        return scheduling::curr_process_map_shared(file_descriptor, offset, size, writable, addr)
+            .or(Some(0));
+    }
+
+    scheduling::curr_process_map_file(file_descriptor, offset, size, writable, shared, addr)
+        .or(Some(0))
+}
@@ -1,34 +1,68 @@
+mod brk;
 mod cd;
 mod clock_gettime;
 mod close;
 mod create;
 mod execute;
 mod exit;
+mod fstat;
+mod getcwd;
+mod getdents;
+mod meminfo;
 mod mkdir;
+mod mmap;
+mod mount;
+mod msync;
+mod munmap;
 mod nanosleep;
 mod open;
+mod opendir;
 mod read;
 mod read_dir;
+mod read_env;
+mod rename;
 mod rmdir;
+mod set_env;
+mod shm_open;
+mod shm_unlink;
+mod stat;
 mod truncate;
+mod umount;
 mod unlink;
 mod wait_for_process;
 mod write;
 mod yld;
 
+pub use brk::brk;
 pub use cd::cd;
 pub use clock_gettime::clock_gettime;
 pub use close::close;
 pub use create::create;
 pub use execute::execute;
 pub use exit::exit;
+pub use fstat::fstat;
+pub use getcwd::getcwd;
+pub use getdents::getdents;
+pub use meminfo::meminfo;
 pub use mkdir::mkdir;
+pub use mmap::mmap;
+pub use mount::mount;
+pub use msync::msync;
+pub use munmap::munmap;
 pub use nanosleep::nanosleep;
 pub use open::open;
+pub use opendir::opendir;
 pub use read::read;
 pub use read_dir::read_dir;
+pub use read_env::read_env;
+pub use rename::rename;
 pub use rmdir::rmdir;
+pub use set_env::set_env;
+pub use shm_open::shm_open;
+pub use shm_unlink::shm_unlink;
+pub use stat::stat;
 pub use truncate::truncate;
+pub use umount::umount;
 pub use unlink::unlink;
 pub use wait_for_process::wait_for_process;
 pub use write::write;
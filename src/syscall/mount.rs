@@ -0,0 +1,54 @@
+// syscall 33 - mount a filesystem
+
+use alloc::format;
+
+use crate::log;
+use crate::{
+    arch::x86_64::registers::FullInterruptStackFrame, scheduling, scheduling::process::Process,
+};
+
+pub fn mount(stack: &FullInterruptStackFrame) -> Option<usize> {
+    let source_addr = stack.rdi;
+    let source_size = stack.rsi;
+    let target_addr = stack.rdx;
+    let target_size = stack.r10;
+    let type_addr = stack.r8;
+    let type_size = stack.r9;
+
+    let Some(page_table) = scheduling::get_current_process_page_table() else {
+        return Some(0);
+    };
+
+    let Some(source_buffer) = Process::copy_from_user(&page_table, source_addr, source_size) else {
+        return Some(0);
+    };
+
+    let Some(target_buffer) = Process::copy_from_user(&page_table, target_addr, target_size) else {
+        return Some(0);
+    };
+
+    let Some(type_buffer) = Process::copy_from_user(&page_table, type_addr, type_size) else {
+        return Some(0);
+    };
+
+    let (source, target, fs_type) = match (
+        core::str::from_utf8(&source_buffer),
+        core::str::from_utf8(&target_buffer),
+        core::str::from_utf8(&type_buffer),
+    ) {
+        (Ok(source), Ok(target), Ok(fs_type)) => (source.trim(), target.trim(), fs_type.trim()),
+        (Err(error), _, _) | (_, Err(error), _) | (_, _, Err(error)) => {
+            let message = format!(
+                "Invalid string for mount syscall, rdi: 0x{:X}, rsi: 0x{:X}, rdx: 0x{:X}, r10: 0x{:X}, r8: 0x{:X}, r9: 0x{:X}",
+                source_addr, source_size, target_addr, target_size, type_addr, type_size
+            );
+
+            log!(crate::io::LogType::SYS, "{}\n{:?}", message, error);
+            return Some(0);
+        }
+    };
+
+    scheduling::curr_process_mount(fs_type, source, target)
+        .then_some(1)
+        .or(Some(0))
+}
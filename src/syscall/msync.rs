@@ -0,0 +1,16 @@
+// syscall 29 - write the changes of shared file mappings back to their files
+
+use crate::{arch::x86_64::registers::FullInterruptStackFrame, scheduling};
+
+pub fn msync(stack: &FullInterruptStackFrame) -> Option<usize> {
+    let addr = stack.rdi;
+    let size = stack.rsi;
+
+    // pages are always written back before returning,
+    // so the flags in rdx are ignored
+    if scheduling::curr_process_sync(addr, size).is_none() {
+        return Some(0);
+    }
+
+    Some(1)
+}
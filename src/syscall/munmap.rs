@@ -0,0 +1,14 @@
+// syscall 28 - unmap anonymous or file-backed memory of the current process
+
+use crate::{arch::x86_64::registers::FullInterruptStackFrame, scheduling};
+
+pub fn munmap(stack: &FullInterruptStackFrame) -> Option<usize> {
+    let addr = stack.rdi;
+    let size = stack.rsi;
+
+    if scheduling::curr_process_unmap(addr, size).is_none() {
+        return Some(0);
+    }
+
+    Some(1)
+}
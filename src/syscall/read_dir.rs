@@ -5,7 +5,8 @@ use core::mem::size_of;
 use alloc::vec::Vec;
 
 use crate::{
-    arch::x86_64::registers::FullInterruptStackFrame, scheduling, scheduling::process::Process,
+    arch::x86_64::registers::FullInterruptStackFrame, fs::fs::DirectoryItems, scheduling,
+    scheduling::process::Process,
 };
 
 /// Maximum filename bytes copied into a [`SyscallDirEntry`].
@@ -13,12 +14,53 @@ const DIR_ENTRY_NAME_CAPACITY: usize = 256;
 
 /// Simplified version of the FAT directory entry
 #[repr(C)]
-struct SyscallDirEntry {
+pub struct SyscallDirEntry {
     name: [u8; DIR_ENTRY_NAME_CAPACITY],
     attr: u8,
     size: u32,
 }
 
+impl SyscallDirEntry {
+    fn new(name: &str, attr: u8) -> Self {
+        let mut name_buffer = [0u8; DIR_ENTRY_NAME_CAPACITY];
+        let name_len = name.len().min(DIR_ENTRY_NAME_CAPACITY);
+        let name = name.as_bytes();
+
+        name_buffer[..name_len].copy_from_slice(&name[..name_len]);
+
+        Self {
+            name: name_buffer,
+            attr: attr,
+            size: 0,
+        }
+    }
+
+    /// Converts directory items into syscall entries, directories first.
+    ///
+    /// ## Arguments
+    ///
+    /// - `items` the subdirectories and files to convert
+    /// - `max_items` the maximum number of entries to convert
+    pub fn from_items(items: &DirectoryItems, max_items: usize) -> Vec<SyscallDirEntry> {
+        let mut directory_entries: Vec<SyscallDirEntry> = items
+            .0
+            .iter()
+            .map(|e| SyscallDirEntry::new(&e.name(), 0x10))
+            .take(max_items)
+            .collect();
+
+        let remaining_items = max_items.saturating_sub(directory_entries.len());
+        let file_entries = items
+            .1
+            .iter()
+            .map(|e| SyscallDirEntry::new(&e.read().name(), 0))
+            .take(remaining_items);
+
+        directory_entries.extend(file_entries);
+        directory_entries
+    }
+}
+
 pub fn read_dir(stack: &FullInterruptStackFrame) -> Option<usize> {
     let buffer_addr = stack.rdi;
     let max_items = stack.rsi;
@@ -36,49 +78,7 @@ pub fn read_dir(stack: &FullInterruptStackFrame) -> Option<usize> {
 
     let cwd = scheduling::get_current_cwd();
     let entries = cwd.list_dir();
-
-    let mut directory_entries: Vec<SyscallDirEntry> = entries
-        .0
-        .iter()
-        .map(|e| {
-            let mut name_buffer = [0u8; DIR_ENTRY_NAME_CAPACITY];
-            let name = e.name();
-            let name_len = name.len().min(DIR_ENTRY_NAME_CAPACITY);
-            let name = name.as_bytes();
-
-            name_buffer[..name_len].copy_from_slice(&name[..name_len]);
-
-            SyscallDirEntry {
-                name: name_buffer,
-                attr: 0x10,
-                size: 0,
-            }
-        })
-        .take(max_items)
-        .collect();
-
-    let remaining_items = max_items.saturating_sub(directory_entries.len());
-    let file_entries: Vec<SyscallDirEntry> = entries
-        .1
-        .iter()
-        .map(|e| {
-            let mut name_buffer = [0u8; DIR_ENTRY_NAME_CAPACITY];
-            let name = e.read().name();
-            let name_len = name.len().min(DIR_ENTRY_NAME_CAPACITY);
-            let name = name.as_bytes();
-
-            name_buffer[..name_len].copy_from_slice(&name[..name_len]);
-
-            SyscallDirEntry {
-                name: name_buffer,
-                attr: 0,
-                size: 0,
-            }
-        })
-        .take(remaining_items)
-        .collect();
-
-    directory_entries.extend(file_entries);
+    let directory_entries = SyscallDirEntry::from_items(&entries, max_items);
 
     let num_entries = directory_entries.len();
     if Process::copy_slice_to_user(&page_table, buffer_addr, &directory_entries).is_none() {
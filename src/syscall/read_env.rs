@@ -0,0 +1,33 @@
+// syscall 25 - read the environment of the current process into a user buffer
+
+use alloc::vec::Vec;
+
+use crate::{
+    arch::x86_64::registers::FullInterruptStackFrame, scheduling, scheduling::process::Process,
+};
+
+pub fn read_env(stack: &FullInterruptStackFrame) -> Option<usize> {
+    let buffer_addr = stack.rdi;
+    let buffer_size = stack.rsi;
+
+    let Some(page_table) = scheduling::get_current_process_page_table() else {
+        return Some(0);
+    };
+
+    // entries are written as NUL-terminated `KEY=VALUE` strings
+    let mut block = Vec::new();
+    for entry in scheduling::get_current_environment() {
+        block.extend_from_slice(entry.as_bytes());
+        block.push(0);
+    }
+
+    if block.len() > buffer_size {
+        return Some(0);
+    }
+
+    if Process::copy_slice_to_user(&page_table, buffer_addr, &block).is_none() {
+        return Some(0);
+    }
+
+    Some(block.len())
+}
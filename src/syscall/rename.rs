@@ -0,0 +1,47 @@
+// syscall 20 - rename or move a file or directory
+
+use alloc::format;
+
+use crate::log;
+use crate::{
+    arch::x86_64::registers::FullInterruptStackFrame, scheduling, scheduling::process::Process,
+};
+
+pub fn rename(stack: &FullInterruptStackFrame) -> Option<usize> {
+    let old_addr = stack.rdi;
+    let old_size = stack.rsi;
+    let new_addr = stack.rdx;
+    let new_size = stack.r10;
+
+    let Some(page_table) = scheduling::get_current_process_page_table() else {
+        return Some(0);
+    };
+
+    let Some(old_buffer) = Process::copy_from_user(&page_table, old_addr, old_size) else {
+        return Some(0);
+    };
+
+    let Some(new_buffer) = Process::copy_from_user(&page_table, new_addr, new_size) else {
+        return Some(0);
+    };
+
+    let (old_path, new_path) = match (
+        core::str::from_utf8(&old_buffer),
+        core::str::from_utf8(&new_buffer),
+    ) {
+        (Ok(old_path), Ok(new_path)) => (old_path.trim(), new_path.trim()),
+        (Err(error), _) | (_, Err(error)) => {
+            let message = format!(
+                "Invalid string for rename syscall, rdi: 0x{:X}, rsi: 0x{:X}, rdx: 0x{:X}, r10: 0x{:X}",
+                old_addr, old_size, new_addr, new_size
+            );
+
+            log!(crate::io::LogType::SYS, "{}\n{:?}", message, error);
+            return Some(0);
+        }
+    };
+
+    scheduling::curr_process_rename(old_path, new_path)
+        .then_some(1)
+        .or(Some(0))
+}
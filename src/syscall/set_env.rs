@@ -0,0 +1,53 @@
+// syscall 24 - set an environment variable of the current process
+
+use alloc::format;
+
+use crate::log;
+use crate::{
+    arch::x86_64::registers::FullInterruptStackFrame, scheduling, scheduling::process::Process,
+};
+
+/// Maximum byte length of an environment variable key or value.
+const ENV_MAX_BYTES: usize = 1024;
+
+pub fn set_env(stack: &FullInterruptStackFrame) -> Option<usize> {
+    let key_addr = stack.rdi;
+    let key_size = stack.rsi;
+    let value_addr = stack.rdx;
+    let value_size = stack.r10;
+
+    if key_size > ENV_MAX_BYTES || value_size > ENV_MAX_BYTES {
+        return Some(0);
+    }
+
+    let Some(page_table) = scheduling::get_current_process_page_table() else {
+        return Some(0);
+    };
+
+    let Some(key_buffer) = Process::copy_from_user(&page_table, key_addr, key_size) else {
+        return Some(0);
+    };
+
+    let Some(value_buffer) = Process::copy_from_user(&page_table, value_addr, value_size) else {
+        return Some(0);
+    };
+
+    let (Ok(key), Ok(value)) = (
+        core::str::from_utf8(&key_buffer),
+        core::str::from_utf8(&value_buffer),
+    ) else {
+        let message = format!(
+            "Invalid string for set_env syscall, rdi: 0x{:X}, rsi: 0x{:X}, rdx: 0x{:X}, r10: 0x{:X}",
+            key_addr, key_size, value_addr, value_size
+        );
+
+        log!(crate::io::LogType::SYS, "{}", message);
+        return Some(0);
+    };
+
+    if scheduling::set_current_environment_variable(key.trim(), value).is_none() {
+        return Some(0);
+    }
+
+    Some(1)
+}
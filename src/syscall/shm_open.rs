@@ -0,0 +1,47 @@
+// syscall 31 - open or create a named shared memory object
+
+use alloc::format;
+
+use crate::log;
+use crate::{
+    arch::x86_64::registers::FullInterruptStackFrame, scheduling, scheduling::process::Process,
+};
+
+/// Creates the object when it doesn't exist.
+pub const SHM_CREATE: usize = 0x1;
+
+/// Together with `SHM_CREATE`, fails when the object already exists.
+pub const SHM_EXCLUSIVE: usize = 0x2;
+
+pub fn shm_open(stack: &FullInterruptStackFrame) -> Option<usize> {
+    let buffer_addr = stack.rdi;
+    let buffer_size = stack.rsi;
+    let size = stack.rdx;
+    let flags = stack.r10;
+
+    let Some(page_table) = scheduling::get_current_process_page_table() else {
+        return Some(0);
+    };
+
+    let Some(buffer) = Process::copy_from_user(&page_table, buffer_addr, buffer_size) else {
+        return Some(0);
+    };
+
+    let name = match core::str::from_utf8(&buffer) {
+        Ok(name) => name.trim(),
+        Err(error) => {
+            let message = format!(
+                "Invalid string for shm_open syscall, rdi: 0x{:X}, rsi: 0x{:X}",
+                buffer_addr, buffer_size
+            );
+
+            log!(crate::io::LogType::SYS, "{}\n{:?}", message, error);
+            return Some(0);
+        }
+    };
+
+    let create = flags & SHM_CREATE != 0;
+    let exclusive = flags & SHM_EXCLUSIVE != 0;
+
+    scheduling::curr_process_shm_open(name, size, create, exclusive).or(Some(0))
+}
@@ -0,0 +1,94 @@
+// syscall 18 - read the metadata of a file or directory by path
+
+use alloc::format;
+
+use crate::log;
+use crate::{
+    arch::x86_64::registers::FullInterruptStackFrame,
+    fs::fs::{FileType, Metadata},
+    scheduling,
+    scheduling::process::Process,
+};
+
+/// [`SyscallStat::file_type`] of a regular file.
+pub const STAT_TYPE_REGULAR: u8 = 1;
+
+/// [`SyscallStat::file_type`] of a directory.
+pub const STAT_TYPE_DIRECTORY: u8 = 2;
+
+/// File metadata as seen by userspace, timestamps are Unix seconds
+#[repr(C)]
+pub struct SyscallStat {
+    file_type: u8,
+    attributes: u8,
+
+    /// Zeroed, so no kernel stack bytes end up in the padding.
+    _reserved: [u8; 6],
+    size: u64,
+    cluster_count: u64,
+    created: u64,
+    modified: u64,
+    accessed: u64,
+}
+
+impl SyscallStat {
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        let file_type = match metadata.file_type {
+            FileType::Regular => STAT_TYPE_REGULAR,
+            FileType::Directory => STAT_TYPE_DIRECTORY,
+        };
+
+        Self {
+            file_type: file_type,
+            attributes: metadata.attributes,
+            _reserved: [0; 6],
+            size: metadata.size as u64,
+            cluster_count: metadata.cluster_count as u64,
+            created: metadata.created,
+            modified: metadata.modified,
+            accessed: metadata.accessed,
+        }
+    }
+}
+
+pub fn stat(stack: &FullInterruptStackFrame) -> Option<usize> {
+    let buffer_addr = stack.rdi;
+    let buffer_size = stack.rsi;
+    let stat_addr = stack.rdx;
+
+    let Some(page_table) = scheduling::get_current_process_page_table() else {
+        return Some(0);
+    };
+
+    let Some(buffer) = Process::copy_from_user(&page_table, buffer_addr, buffer_size) else {
+        return Some(0);
+    };
+
+    let path = match core::str::from_utf8(&buffer) {
+        Ok(path) => path.trim(),
+        Err(error) => {
+            let message = format!(
+                "Invalid string for stat syscall, rdi: 0x{:X}, rsi: 0x{:X}",
+                buffer_addr, buffer_size
+            );
+
+            log!(crate::io::LogType::SYS, "{}\n{:?}", message, error);
+            return Some(0);
+        }
+    };
+
+    if path.is_empty() {
+        return Some(0);
+    }
+
+    let Some(metadata) = scheduling::curr_process_metadata(path) else {
+        return Some(0);
+    };
+
+    let stat = SyscallStat::from_metadata(&metadata);
+    if Process::copy_value_to_user(&page_table, stat_addr, &stat).is_none() {
+        return Some(0);
+    }
+
+    Some(1)
+}
@@ -1,6 +1,7 @@
 // syscall 2 - write bytes to a file descriptor
 
-use alloc::format;
+use alloc::{format, vec};
+use core::cmp::min;
 
 use crate::log;
 use crate::{
@@ -9,6 +10,10 @@ use crate::{
     scheduling::process::{FileDescriptor, Process},
 };
 
+/// The bytes are copied from user memory and written in chunks of this
+/// size, so the buffer doesn't grow with the requested size.
+const WRITE_CHUNK_BYTES: usize = 4096;
+
 pub fn write(stack: &FullInterruptStackFrame) -> Option<usize> {
     let file_descriptor = stack.rdi;
     let buffer_addr = stack.rsi;
@@ -18,33 +23,67 @@ pub fn write(stack: &FullInterruptStackFrame) -> Option<usize> {
         return Some(0);
     };
 
-    let Some(buffer) = Process::copy_from_user(&page_table, buffer_addr, buffer_size) else {
-        return Some(0);
+    let descriptor = scheduling::get_current_file_descriptor(file_descriptor);
+    let is_output = match descriptor {
+        Some(FileDescriptor::Stdout) | Some(FileDescriptor::Stderr) => true,
+        Some(FileDescriptor::File(_)) => false,
+        _ => return Some(0),
     };
 
-    match scheduling::get_current_file_descriptor(file_descriptor) {
-        Some(FileDescriptor::Stdout) | Some(FileDescriptor::Stderr) => {
-            let string = match core::str::from_utf8(&buffer) {
-                Ok(s) => s,
-                Err(e) => {
-                    let msg = format!(
-                        "Invalid string for write syscall, rdi: 0x{:X}, rsi: 0x{:X}, rdx: 0x{:X}\n",
-                        file_descriptor, buffer_addr, buffer_size
-                    );
-
-                    log!(crate::io::LogType::ERR, "{}\n{:?}", msg, e);
-                    return Some(0);
-                }
-            };
+    let mut buffer = vec![0; WRITE_CHUNK_BYTES];
+
+    // bytes of a character split across chunks, kept at the buffer start
+    let mut carried = 0;
+    let mut written = 0;
 
-            print!("{}", string);
-            Some(buffer.len())
+    while written < buffer_size {
+        let size = min(WRITE_CHUNK_BYTES - carried, buffer_size - written);
+        let chunk = &mut buffer[carried..carried + size];
+        if Process::copy_from_user_into(&page_table, buffer_addr + written, chunk).is_none() {
+            break;
         }
-        Some(FileDescriptor::File(_)) => {
-            // map a failed write to 0 bytes written, otherwise the
-            // dispatcher would leave the syscall number in rax
-            scheduling::write_current_file_descriptor(file_descriptor, &buffer).or(Some(0))
+
+        if !is_output {
+            // a failed write reports the bytes written until then, never
+            // None, otherwise the dispatcher would leave the syscall
+            // number in rax
+            let chunk = &buffer[..size];
+            let Some(count) = scheduling::write_current_file_descriptor(file_descriptor, chunk)
+            else {
+                break;
+            };
+
+            written += count;
+            if count < size {
+                break;
+            }
+
+            continue;
         }
-        _ => Some(0),
+
+        let pending = carried + size;
+        let last_chunk = written + size == buffer_size;
+        let valid = match core::str::from_utf8(&buffer[..pending]) {
+            Ok(string) => string.len(),
+            Err(e) if e.error_len().is_none() && !last_chunk => e.valid_up_to(),
+            Err(e) => {
+                let msg = format!(
+                    "Invalid string for write syscall, rdi: 0x{:X}, rsi: 0x{:X}, rdx: 0x{:X}\n",
+                    file_descriptor, buffer_addr, buffer_size
+                );
+
+                log!(crate::io::LogType::ERR, "{}\n{:?}", msg, e);
+                break;
+            }
+        };
+
+        let string = core::str::from_utf8(&buffer[..valid]).unwrap_or_default();
+        print!("{}", string);
+
+        buffer.copy_within(valid..pending, 0);
+        carried = pending - valid;
+        written += size;
     }
+
+    Some(written)
 }
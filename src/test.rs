@@ -1,9 +1,29 @@
-use multiboot2::BootInformation;
+use core::alloc::{Allocator, Layout};
 
+use alloc::{string::String, sync::Arc};
+
+use multiboot2::{BootInformation, MemoryAreaType};
+
+use crate::fs::{
+    fs::Directory,
+    tmpfs::TmpFileSystem,
+    vfs::{self, MountedFilesystem},
+};
 use crate::log;
-use crate::mem::paging::{entry::EntryFlags, Page};
-use crate::mem::{PageFrameAllocator, SimplePageFrameAllocator};
+use crate::mem::aslr;
+use crate::mem::heap::{self, SlabHeap};
+use crate::mem::paging::{
+    entry::{EntryFlags, PageTableEntry},
+    Page, PageTable, HUGE_PAGE_SIZE, PAGES_PER_HUGE_PAGE,
+};
+use crate::mem::pcid::{self, PcidAllocator, PCID_COUNT, USER_PCID_BIT};
+use crate::mem::{take_frame_bitmap, BitmapFrameAllocator, PageFrame, PageFrameAllocator};
+use crate::mem::{PAGE_SIZE, PHYS_MAP_START};
 use crate::print;
+use crate::scheduling::shm::SharedMemory;
+use crate::scheduling::vma::{Vma, VmaBacking, VmaTree};
+use crate::utils::safe::Safe;
+use spin::Mutex;
 
 pub struct TestUnit<'a> {
     function: &'a dyn Fn() -> bool,
@@ -15,7 +35,22 @@ static mut BOOT_INFO_ADDR: Option<usize> = None;
 static mut BOOT_INFO: Option<BootInformation> = None;
 static mut MULTIBOOT_MEM_END: Option<usize> = None;
 static mut MEM_END: Option<usize> = None;
-static mut PAGE_FRAME_ALLOCATOR: Option<SimplePageFrameAllocator> = None;
+static mut PAGE_FRAME_ALLOCATOR: Option<BitmapFrameAllocator> = None;
+
+const TEST_HEAP_SIZE: usize = 256 * 1024;
+
+/// Size of the heap behind the global allocator while testing.
+const GLOBAL_HEAP_SIZE: usize = 1024 * 1024;
+
+#[repr(align(4096))]
+struct TestHeapMemory<const SIZE: usize>([u8; SIZE]);
+
+static mut TEST_HEAP_MEMORY: TestHeapMemory<TEST_HEAP_SIZE> = TestHeapMemory([0; TEST_HEAP_SIZE]);
+static mut GLOBAL_HEAP_MEMORY: TestHeapMemory<GLOBAL_HEAP_SIZE> =
+    TestHeapMemory([0; GLOBAL_HEAP_SIZE]);
+
+#[repr(align(4096))]
+struct TestPageTable([u64; 512]);
 
 impl<'a> TestUnit<'a> {
     pub fn new(func: &'a dyn Fn() -> bool, name: &'a str) -> TestUnit<'a> {
@@ -53,15 +88,36 @@ macro_rules! assert_true {
 
 pub fn run_tests(boot_info_addr: usize) {
     unsafe { BOOT_INFO_ADDR = Some(boot_info_addr) };
+    init_global_heap();
 
     TestUnit::new(&test_boot_info, "Test Boot Info");
     TestUnit::new(&test_memory_map, "Test Memory Map");
     TestUnit::new(&test_page_frame_allocator, "Test Page Frame Allocator");
     TestUnit::new(&test_paging, "Test Paging");
+    TestUnit::new(&test_huge_page_entries, "Test Huge Page Entries");
+    TestUnit::new(&test_user_entry_copy, "Test User Entry Copy");
+    TestUnit::new(&test_pcid_allocator, "Test PCID Allocator");
     TestUnit::new(
         &test_frame_allocator_fill_memory,
         "Test Page Frame Allocator Fill Memory",
     );
+    TestUnit::new(&test_kernel_heap, "Test Kernel Heap");
+    TestUnit::new(&test_vma_tree, "Test VMA Tree");
+    TestUnit::new(&test_aslr_offsets, "Test ASLR Offsets");
+    TestUnit::new(&test_shared_memory_refs, "Test Shared Memory Refs");
+    TestUnit::new(&test_vfs_paths, "Test VFS Paths");
+    TestUnit::new(&test_tmpfs, "Test Tmpfs");
+}
+
+/// Backs the global allocator with a static buffer. The tests run without
+/// the memory controller, so the heap can't grow the way the kernel heap
+/// does.
+fn init_global_heap() {
+    let heap_start = unsafe { core::ptr::addr_of_mut!(GLOBAL_HEAP_MEMORY.0) as usize };
+    let heap_end = heap_start + GLOBAL_HEAP_SIZE;
+
+    let allocator = unsafe { &*core::ptr::addr_of!(crate::HEAP_ALLOCATOR) };
+    allocator.lock().init(heap_start, heap_end, heap_end);
 }
 
 fn test_boot_info() -> bool {
@@ -116,16 +172,59 @@ fn test_memory_map() -> bool {
 
 fn test_page_frame_allocator() -> bool {
     unsafe {
+        assert_true!(BOOT_INFO.is_some());
+        let boot_info = BOOT_INFO.as_ref().unwrap();
+
         assert_true!(MULTIBOOT_MEM_END.is_some());
-        let mem_start = MULTIBOOT_MEM_END.as_ref().unwrap();
+        let multiboot_end = MULTIBOOT_MEM_END.as_ref().unwrap();
 
-        assert_true!(MEM_END.is_some());
-        let mem_end = MEM_END.as_ref().unwrap();
+        let bitmap = take_frame_bitmap();
+        assert_true!(bitmap.is_some());
 
-        let mut allocator = SimplePageFrameAllocator::new(mem_start.clone(), mem_end.clone());
+        let mut allocator = BitmapFrameAllocator::from_boot_info(boot_info, bitmap.unwrap());
+        assert_true!(allocator.total_count() > 0);
+        assert_true!(allocator.free_count() == allocator.total_count());
 
         let frame_res = allocator.falloc();
         assert_true!(frame_res.is_some());
+        assert_true!(allocator.used_count() == 1);
+
+        // the multiboot information must never be handed out
+        let frame = frame_res.unwrap();
+        let boot_info_addr = BOOT_INFO_ADDR.unwrap();
+        let frame_end = frame.start_address() + PAGE_SIZE;
+        assert_true!(frame_end <= boot_info_addr || frame.start_address() >= multiboot_end.clone());
+
+        // freed frames are handed out again
+        assert_true!(allocator.is_allocated(&frame));
+        allocator.free(frame.clone());
+        assert_true!(allocator.used_count() == 0);
+        assert_true!(!allocator.is_allocated(&frame));
+
+        let next_frame = allocator.falloc();
+        assert_true!(next_frame == Some(frame));
+
+        // a double free must not inflate the free count
+        allocator.free(next_frame.clone().unwrap());
+        allocator.free(next_frame.unwrap());
+        assert_true!(allocator.used_count() == 0);
+
+        let first = allocator.falloc_contiguous(4);
+        assert_true!(first.is_some());
+        assert_true!(allocator.used_count() == 4);
+
+        allocator.free_contiguous(first.unwrap(), 4);
+        assert_true!(allocator.used_count() == 0);
+
+        // huge pages need runs starting at an aligned frame
+        let huge = allocator.falloc_contiguous_aligned(PAGES_PER_HUGE_PAGE, PAGES_PER_HUGE_PAGE);
+        assert_true!(huge
+            .as_ref()
+            .is_some_and(|frame| frame.frame_number % PAGES_PER_HUGE_PAGE == 0));
+        assert_true!(allocator.used_count() == PAGES_PER_HUGE_PAGE);
+
+        allocator.free_contiguous(huge.unwrap(), PAGES_PER_HUGE_PAGE);
+        assert_true!(allocator.used_count() == 0);
 
         PAGE_FRAME_ALLOCATOR = Some(allocator);
     }
@@ -171,35 +270,462 @@ fn test_paging() -> bool {
     return true;
 }
 
+fn test_huge_page_entries() -> bool {
+    let page = Page::for_address(HUGE_PAGE_SIZE + 3 * PAGE_SIZE);
+    assert_true!(!page.is_huge_aligned());
+    assert_true!(page.huge_page_start() == Page::for_address(HUGE_PAGE_SIZE));
+    assert_true!(page.huge_page_start().is_huge_aligned());
+
+    // the 4 KiB view of a huge page keeps its flags
+    let flags = EntryFlags::PRESENT | EntryFlags::WRITABLE | EntryFlags::HUGE_PAGE;
+    let huge_entry = PageTableEntry {
+        entry: HUGE_PAGE_SIZE as u64 | flags.bits(),
+    };
+
+    let part = huge_entry.huge_page_part(page.p1_index());
+    assert_true!(part.flags().contains(flags));
+    assert_true!(part
+        .get_frame()
+        .is_some_and(|frame| frame.start_address() == page.start_address()));
+
+    return true;
+}
+
+fn test_user_entry_copy() -> bool {
+    let user_flags = EntryFlags::PRESENT | EntryFlags::WRITABLE | EntryFlags::RING3_ACCESSIBLE;
+    let kernel_flags = EntryFlags::PRESENT | EntryFlags::WRITABLE;
+    let stale_entry = 0x5000 | user_flags.bits();
+
+    let mut source = TestPageTable([0; 512]);
+    source.0[0] = 0x1000 | user_flags.bits();
+    source.0[1] = 0x2000 | user_flags.bits();
+    source.0[2] = 0x3000 | kernel_flags.bits();
+    let mut destination = TestPageTable([stale_entry; 512]);
+
+    let source_table = PageTable::new(&source as *const TestPageTable as usize);
+    let mut destination_table = PageTable::new(&mut destination as *mut TestPageTable as usize);
+    destination_table.copy_user_entries(&source_table, 1..4);
+
+    // user entries in the range are shared, the others in it are cleared
+    assert_true!(destination.0[0] == stale_entry);
+    assert_true!(destination.0[1] == source.0[1]);
+    assert_true!(destination.0[2] == 0);
+    assert_true!(destination.0[3] == 0);
+    assert_true!(destination.0[4] == stale_entry);
+
+    return true;
+}
+
+fn test_pcid_allocator() -> bool {
+    let mut allocator = PcidAllocator::new();
+
+    // PCID 0 is never handed out and always flushes
+    let first = allocator.alloc();
+    assert_true!(first == Some(1));
+    assert_true!(allocator.take_stale(0));
+
+    // a fresh PCID flushes once, for both page tables of the process
+    assert_true!(allocator.take_stale(1));
+    assert_true!(!allocator.take_stale(1));
+    assert_true!(allocator.take_stale(1 | USER_PCID_BIT));
+    assert_true!(!allocator.take_stale(1 | USER_PCID_BIT));
+
+    allocator.invalidate(1);
+    assert_true!(allocator.take_stale(1));
+    assert_true!(allocator.take_stale(1 | USER_PCID_BIT));
+
+    // kernel mappings are shared by all tables, user ones aren't
+    pcid::mapping_changed(PHYS_MAP_START);
+    assert_true!(allocator.take_stale(1));
+    assert_true!(allocator.take_stale(1 | USER_PCID_BIT));
+    pcid::mapping_changed(PAGE_SIZE);
+    assert_true!(!allocator.take_stale(1));
+
+    // a freed PCID is reused, stale again
+    assert_true!(allocator.alloc() == Some(2));
+    allocator.free(1);
+    assert_true!(allocator.alloc() == Some(1));
+    assert_true!(allocator.take_stale(1));
+
+    for _ in 3..PCID_COUNT {
+        assert_true!(allocator.alloc().is_some());
+    }
+    assert_true!(allocator.alloc().is_none());
+
+    return true;
+}
+
 fn test_frame_allocator_fill_memory() -> bool {
     unsafe {
         assert_true!(PAGE_FRAME_ALLOCATOR.is_some());
         let allocator = PAGE_FRAME_ALLOCATOR.as_mut().unwrap();
 
-        assert_true!(MEM_END.is_some());
-        let mem_end = MEM_END.as_ref().unwrap();
+        assert_true!(BOOT_INFO.is_some());
+        let boot_info = BOOT_INFO.as_ref().unwrap();
+        let memory_areas = boot_info.memory_map_tag().unwrap().memory_areas();
 
+        let total = allocator.total_count();
+        let mut allocated = 0;
         let mut last_page_num = 0;
         for i in 0.. {
             let alloc_res = allocator.falloc();
             match alloc_res {
                 Some(page) => {
-                    let addr = page.start_address();
-                    assert_true!(addr < mem_end.clone());
-
+                    // every frame lies inside an available memory area
+                    let addr = page.start_address() as u64;
+                    let available = memory_areas.iter().any(|area| {
+                        MemoryAreaType::from(area.typ()) == MemoryAreaType::Available
+                            && area.start_address() <= addr
+                            && addr + PAGE_SIZE as u64 <= area.end_address()
+                    });
+                    assert_true!(available);
+
+                    // frames are handed out in ascending order,
+                    // skipping holes and reserved ranges
                     let num = page.frame_number;
+                    allocated += 1;
                     if i == 0 {
                         last_page_num = num;
                         continue;
                     }
 
-                    assert_true!(num - 1 == last_page_num);
+                    assert_true!(num > last_page_num);
                     last_page_num = num;
                 }
                 None => break,
             }
         }
+
+        assert_true!(allocated == total);
+        assert_true!(allocator.free_count() == 0);
+        assert_true!(allocator.falloc_contiguous(1).is_none());
+
+        // a freed run can be allocated contiguously again
+        let run_start = last_page_num - 3;
+        for frame_number in run_start..=last_page_num {
+            allocator.free(PageFrame {
+                frame_number: frame_number,
+            });
+        }
+
+        assert_true!(allocator.free_count() == 4);
+
+        let run = allocator.falloc_contiguous(4);
+        assert_true!(run.map(|frame| frame.frame_number) == Some(run_start));
+        assert_true!(allocator.free_count() == 0);
     }
 
     return true;
 }
+
+fn test_kernel_heap() -> bool {
+    let heap = Safe::new(SlabHeap::empty());
+    let heap_start = unsafe { core::ptr::addr_of_mut!(TEST_HEAP_MEMORY.0) as usize };
+    let heap_end = heap_start + TEST_HEAP_SIZE;
+
+    // the test heap can't grow, it isn't backed by the memory controller
+    heap.lock().init(heap_start, heap_end, heap_end);
+
+    let layouts = [
+        Layout::from_size_align(24, 8).unwrap(),
+        Layout::from_size_align(100, 64).unwrap(),
+        Layout::from_size_align(512, 512).unwrap(),
+        Layout::from_size_align(4096, 4096).unwrap(),
+        Layout::from_size_align(5000, 2048).unwrap(),
+        Layout::from_size_align(3, 1).unwrap(),
+    ];
+
+    // every allocation honours its alignment and lies inside the heap
+    let mut pointers = [0; 6];
+    for (index, layout) in layouts.iter().enumerate() {
+        let Ok(ptr) = heap.allocate(*layout) else {
+            return false;
+        };
+
+        let addr = ptr.addr().get();
+        assert_true!(addr % layout.align() == 0);
+        assert_true!(heap_start <= addr && addr + layout.size() <= heap_end);
+        pointers[index] = addr;
+    }
+
+    let stats = heap.lock().stats();
+    assert_true!(stats.size == heap_end - heap_start);
+    assert_true!(stats.used + stats.free == stats.size);
+    assert_true!(stats.used >= layouts.iter().map(|layout| layout.size()).sum::<usize>());
+    assert_true!(stats.largest_free <= stats.free);
+
+    // no two allocations overlap
+    for (i, a) in layouts.iter().enumerate() {
+        for (j, b) in layouts.iter().enumerate() {
+            if i == j {
+                continue;
+            }
+
+            let a_end = pointers[i] + a.size();
+            let b_end = pointers[j] + b.size();
+            assert_true!(a_end <= pointers[j] || b_end <= pointers[i]);
+        }
+    }
+
+    // freed small objects are reused first
+    let small = layouts[0];
+    let reused = pointers[0];
+    unsafe { heap.deallocate(core::ptr::NonNull::new(reused as *mut u8).unwrap(), small) };
+    let Ok(ptr) = heap.allocate(small) else {
+        return false;
+    };
+    assert_true!(ptr.addr().get() == reused);
+
+    for (index, layout) in layouts.iter().enumerate() {
+        let ptr = core::ptr::NonNull::new(pointers[index] as *mut u8).unwrap();
+        unsafe { heap.deallocate(ptr, *layout) };
+    }
+
+    // free slab objects count as free memory
+    let stats = heap.lock().stats();
+    assert_true!(stats.used == 0 && stats.free == stats.size);
+
+    // freed regions are merged again, so a large allocation still fits
+    let large = Layout::from_size_align(TEST_HEAP_SIZE / 2, PAGE_SIZE).unwrap();
+    assert_true!(heap.allocate(large).is_ok());
+
+    let too_large = Layout::from_size_align(TEST_HEAP_SIZE, 16).unwrap();
+    assert_true!(heap.allocate(too_large).is_err());
+
+    return true;
+}
+
+fn test_vma_tree() -> bool {
+    let base = 0x1000_0000;
+    let user_rw = EntryFlags::RING3_ACCESSIBLE | EntryFlags::WRITABLE;
+    let area = |start: usize, end: usize, flags: EntryFlags| Vma {
+        start: base + start * PAGE_SIZE,
+        end: base + end * PAGE_SIZE,
+        flags: flags,
+        backing: VmaBacking::Anonymous,
+    };
+
+    let used = heap::heap_stats().used;
+    let mut vmas = VmaTree::new();
+    assert_true!(vmas.insert(area(0, 4, user_rw)).is_some());
+    assert_true!(vmas
+        .insert(area(8, 10, EntryFlags::RING3_ACCESSIBLE))
+        .is_some());
+
+    // empty and overlapping areas are rejected
+    assert_true!(vmas.insert(area(2, 2, user_rw)).is_none());
+    assert_true!(vmas.insert(area(3, 9, user_rw)).is_none());
+    assert_true!(vmas.insert(area(7, 8, user_rw)).is_some());
+
+    assert_true!(vmas.find(base + 3 * PAGE_SIZE).is_some());
+    assert_true!(vmas.find(base + 4 * PAGE_SIZE).is_none());
+
+    // the first gap that fits is used
+    let region_end = base + 16 * PAGE_SIZE;
+    assert_true!(vmas.find_gap(base, region_end, 3 * PAGE_SIZE) == Some(base + 4 * PAGE_SIZE));
+    assert_true!(vmas.find_gap(base, region_end, 4 * PAGE_SIZE) == Some(base + 10 * PAGE_SIZE));
+    assert_true!(vmas.find_gap(base, region_end, 7 * PAGE_SIZE).is_none());
+
+    // access checks follow the area flags and must not cross holes
+    assert_true!(vmas.is_accessible(base, 4 * PAGE_SIZE, true));
+    assert_true!(!vmas.is_accessible(base, 5 * PAGE_SIZE, false));
+    assert_true!(vmas.is_accessible(base + 7 * PAGE_SIZE, 3 * PAGE_SIZE, false));
+    assert_true!(!vmas.is_accessible(base + 7 * PAGE_SIZE, 3 * PAGE_SIZE, true));
+
+    // splitting the middle of an area keeps both sides
+    let Some(removed) = vmas.split_off(base + PAGE_SIZE, base + 2 * PAGE_SIZE) else {
+        return false;
+    };
+    assert_true!(removed.start == base + PAGE_SIZE && removed.end == base + 2 * PAGE_SIZE);
+    assert_true!(vmas.find(base).is_some_and(|a| a.end == base + PAGE_SIZE));
+    assert_true!(vmas
+        .find(base + 2 * PAGE_SIZE)
+        .is_some_and(|a| a.end == base + 4 * PAGE_SIZE));
+    assert_true!(vmas.find(base + PAGE_SIZE).is_none());
+
+    // ranges spanning more than one area can't be split off
+    assert_true!(vmas
+        .split_off(base + 7 * PAGE_SIZE, base + 9 * PAGE_SIZE)
+        .is_none());
+
+    // the areas live on the global heap and give it back
+    drop((vmas, removed));
+    assert_true!(heap::heap_stats().used == used);
+
+    return true;
+}
+
+fn test_aslr_offsets() -> bool {
+    // offsets stay page aligned and inside the slide range
+    for _ in 0..64 {
+        let offset = aslr::random_page_offset(16);
+        assert_true!(offset % PAGE_SIZE == 0);
+        assert_true!(offset < 16 * PAGE_SIZE);
+
+        assert_true!(aslr::random_below(10) < 10);
+    }
+
+    // an empty range can't be slid
+    assert_true!(aslr::random_page_offset(0) == 0);
+    assert_true!(aslr::random_page_offset(1) == 0);
+
+    return true;
+}
+
+fn test_shared_memory_refs() -> bool {
+    let used = heap::heap_stats().used;
+    let frames = (0..2)
+        .map(|number| PageFrame {
+            frame_number: 0x100 + number,
+        })
+        .collect();
+    let object = SharedMemory::new(String::from("test"), frames);
+    assert_true!(object.size() == 2 * PAGE_SIZE);
+
+    // two mappings of the first page, the name holds the third reference
+    assert_true!(object
+        .acquire(0, 1)
+        .is_some_and(|f| f.len() == 1 && f[0].frame_number == 0x100));
+    assert_true!(object.acquire(0, 1).is_some());
+    assert_true!(object.acquire(1, 2).is_none());
+
+    assert_true!(object.release(0).is_none());
+
+    // dropping the name frees whatever isn't mapped anymore
+    assert_true!(object.release(0).is_none());
+    assert_true!(object.release(1).is_some_and(|f| f.frame_number == 0x101));
+    assert_true!(object.release(1).is_none());
+
+    // an open descriptor can't map freed frames after the unlink, parts
+    // with one of them aren't mapped at all
+    assert_true!(object.acquire(1, 1).is_none());
+    assert_true!(object.acquire(0, 2).is_none());
+    assert_true!(object.acquire(0, 1).is_some());
+
+    assert_true!(object.release(0).is_none());
+    assert_true!(object.release(0).is_some_and(|f| f.frame_number == 0x100));
+    assert_true!(object.acquire(0, 1).is_none());
+
+    // the object lives on the global heap and gives it back
+    drop(object);
+    assert_true!(heap::heap_stats().used == used);
+
+    return true;
+}
+
+fn test_vfs_paths() -> bool {
+    let used = heap::heap_stats().used;
+
+    // the root has its slash already
+    assert_true!(vfs::child_path("/", "tmp") == "/tmp");
+    assert_true!(vfs::child_path("/tmp", "scratch") == "/tmp/scratch");
+
+    // nothing is mounted before the filesystem is initialized
+    assert_true!(!vfs::is_mount_point("/"));
+    assert_true!(vfs::umount("/tmp").is_none());
+    assert_true!(vfs::umount("/").is_none());
+
+    // the paths are built on the global heap and give it back
+    assert_true!(heap::heap_stats().used == used);
+
+    return true;
+}
+
+fn test_tmpfs() -> bool {
+    let fs = Arc::new(Mutex::new(TmpFileSystem::new(2 * PAGE_SIZE)));
+    let root = TmpFileSystem::root_dir(fs.clone());
+
+    assert_true!(root.create_directory("scratch").is_some());
+    assert_true!(root.create_directory("scratch").is_none());
+    assert_true!(root.create_file("..").is_none());
+
+    let Some(scratch) = root.find_directory("scratch") else {
+        return false;
+    };
+    let Some(file) = scratch.create_file("notes") else {
+        return false;
+    };
+
+    // writing past the end fills the gap with zeroes
+    assert_true!(file.read().write(4, b"data") == Some(4));
+    let mut buffer = [0xFF; 8];
+    assert_true!(file.read().read_at(0, &mut buffer) == Some(8));
+    assert_true!(buffer == *b"\0\0\0\0data");
+
+    // every lookup shares the data, names are case-sensitive
+    assert_true!(file.write().truncate(2).is_some());
+    assert_true!(scratch
+        .find_file("notes")
+        .is_some_and(|f| f.read().size() == 2));
+    assert_true!(scratch.find_file("NOTES").is_none());
+
+    // files can't outgrow the capacity
+    assert_true!(file.write().truncate(3 * PAGE_SIZE).is_none());
+
+    // directories can't be moved into themselves
+    assert_true!(scratch.rename("notes", &root, "moved").is_some());
+    assert_true!(root.find_file("moved").is_some());
+    assert_true!(root.rename("scratch", &*scratch, "inner").is_none());
+
+    // only empty directories are removed
+    assert_true!(scratch.create_directory("inner").is_some());
+    assert_true!(root.remove_directory("scratch").is_none());
+    assert_true!(scratch.remove_directory("inner").is_some());
+    assert_true!(root.remove_directory("scratch").is_some());
+
+    // unlinked files stay readable and keep their space until closed
+    assert_true!(file.write().truncate(2 * PAGE_SIZE).is_some());
+    assert_true!(root.unlink_file("moved").is_some());
+    assert_true!(file.read().size() == 2 * PAGE_SIZE);
+    let Some(other) = root.create_file("other") else {
+        return false;
+    };
+    assert_true!(other.write().truncate(PAGE_SIZE).is_none());
+
+    // the data lives on the global heap and goes back with the last handle
+    let used = heap::heap_stats().used;
+    drop(file);
+    assert_true!(heap::heap_stats().used + 2 * PAGE_SIZE <= used);
+    assert_true!(other.write().truncate(2 * PAGE_SIZE).is_some());
+    assert_true!(root.unlink_file("other").is_some());
+    drop((scratch, other));
+
+    // lookups through the mount point end up in the tmpfs
+    let filesystem = MountedFilesystem::Tmp { fs: fs };
+    let Some(mounted) = vfs::mount(String::from("/tmpfs-test"), Arc::new(root), filesystem) else {
+        return false;
+    };
+    assert_true!(vfs::is_mount_point("/tmpfs-test"));
+    assert_true!(mounted.create_directory("dir").is_some());
+    assert_true!(mounted
+        .find_directory("dir")
+        .is_some_and(|d| d.absolute_path() == "/tmpfs-test/dir"));
+
+    // handed out directories follow their parent when it is moved
+    let Some(dir) = mounted.find_directory("dir") else {
+        return false;
+    };
+    assert_true!(dir.create_directory("sub").is_some());
+    let Some(sub) = dir.find_directory("sub") else {
+        return false;
+    };
+    assert_true!(mounted.create_directory("other").is_some());
+    let Some(other) = mounted.find_directory("other") else {
+        return false;
+    };
+    assert_true!(mounted.rename("dir", &*other, "moved").is_some());
+    assert_true!(sub.absolute_path() == "/tmpfs-test/other/moved/sub");
+    assert_true!(sub
+        .find_directory("..")
+        .and_then(|d| d.parent())
+        .is_some_and(|d| d.absolute_path() == "/tmpfs-test/other"));
+    drop((dir, sub, other));
+
+    // busy until the last directory of it is gone
+    assert_true!(vfs::umount("/tmpfs-test").is_none());
+    drop(mounted);
+    assert_true!(vfs::umount("/tmpfs-test").is_some());
+    assert_true!(!vfs::is_mount_point("/tmpfs-test"));
+
+    return true;
+}
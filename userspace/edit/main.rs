@@ -1,9 +1,14 @@
 #![no_std]
 #![no_main]
 
-use core::{arch::global_asm, cell::UnsafeCell, panic::PanicInfo};
+extern crate alloc;
 
-const FILE_CAPACITY: usize = 4 * 1024;
+use alloc::{vec, vec::Vec};
+use core::{arch::global_asm, panic::PanicInfo};
+
+/// Initial free space after the file contents, the buffer doubles
+/// whenever it fills up.
+const FILE_HEADROOM: usize = 4 * 1024;
 const PATH_CAPACITY: usize = 256;
 const VIEW_ROWS: usize = 16;
 
@@ -27,12 +32,6 @@ _start:
 "#
 );
 
-struct FileBuffer(UnsafeCell<[u8; FILE_CAPACITY]>);
-
-unsafe impl Sync for FileBuffer {}
-
-static FILE_BUFFER: FileBuffer = FileBuffer(UnsafeCell::new([0; FILE_CAPACITY]));
-
 struct Editor {
     fd: usize,
     len: usize,
@@ -72,14 +71,21 @@ extern "C" fn rust_main(argc: usize, argv: *const *const u8) -> ! {
         }
     };
 
-    let buffer = file_buffer();
-    let len = ulib::read(fd, buffer);
-    if len == FILE_CAPACITY {
-        let mut extra = [0u8; 1];
-        if ulib::read(fd, &mut extra) != 0 {
-            ulib::close(fd);
-            ulib::stdout(b"\nFile is larger than edit's 4 KiB buffer.\n");
-            ulib::exit();
+    let mut stat = ulib::Stat::empty();
+    let file_size = if ulib::fstat(fd, &mut stat) {
+        stat.size as usize
+    } else {
+        0
+    };
+
+    let mut buffer = vec![0u8; file_size + FILE_HEADROOM];
+    let mut len = 0;
+    loop {
+        let read = ulib::read(fd, &mut buffer[len..]);
+        len += read;
+
+        if read == 0 || len == buffer.len() {
+            break;
         }
     }
 
@@ -95,9 +101,9 @@ extern "C" fn rust_main(argc: usize, argv: *const *const u8) -> ! {
     };
 
     loop {
-        editor.render(path, buffer);
+        editor.render(path, &buffer);
 
-        if editor.handle_input(ulib::read_stdin_char(), buffer) {
+        if editor.handle_input(ulib::read_stdin_char(), &mut buffer) {
             break;
         }
     }
@@ -112,10 +118,6 @@ fn panic(_info: &PanicInfo) -> ! {
     ulib::exit();
 }
 
-fn file_buffer() -> &'static mut [u8; FILE_CAPACITY] {
-    unsafe { &mut *FILE_BUFFER.0.get() }
-}
-
 fn open_or_create(path: &[u8]) -> Option<usize> {
     let fd = ulib::open(path);
     if fd != 0 {
@@ -155,7 +157,7 @@ fn read_line(buffer: &mut [u8]) -> usize {
 }
 
 impl Editor {
-    fn handle_input(&mut self, input: u8, buffer: &mut [u8]) -> bool {
+    fn handle_input(&mut self, input: u8, buffer: &mut Vec<u8>) -> bool {
         if self.escape_state == 1 {
             if input == b'[' {
                 self.escape_state = 2;
@@ -210,10 +212,9 @@ impl Editor {
         }
     }
 
-    fn insert_byte(&mut self, buffer: &mut [u8], byte: u8) {
+    fn insert_byte(&mut self, buffer: &mut Vec<u8>, byte: u8) {
         if self.len == buffer.len() {
-            self.message = b"Buffer full";
-            return;
+            buffer.resize(buffer.len() * 2, 0);
         }
 
         buffer.copy_within(self.cursor..self.len, self.cursor + 1);
@@ -0,0 +1,129 @@
+#![no_std]
+#![no_main]
+
+use core::{arch::global_asm, panic::PanicInfo};
+
+use ulib::MemInfo;
+
+const PAGE_KIB: u64 = 4;
+const COLUMN_WIDTH: usize = 12;
+
+// runs on the kernel-provided stack, with the System V
+// argument frame at the initial stack pointer
+global_asm!(
+    r#"
+    .section .text
+    .global _start
+
+_start:
+    mov rdi, [rsp]
+    lea rsi, [rsp + 8]
+    call rust_main
+
+    mov rax, 1
+    int 0x80
+
+1:
+    jmp 1b
+"#
+);
+
+#[no_mangle]
+extern "C" fn rust_main(_argc: usize, _argv: *const *const u8) -> ! {
+    let mut info = MemInfo::empty();
+    if !ulib::meminfo(&mut info) {
+        ulib::stdout(b"free: could not read memory info\n");
+        ulib::exit();
+    }
+
+    ulib::stdout(b"KiB        ");
+    write_column(b"total");
+    write_column(b"used");
+    write_column(b"free");
+    ulib::stdout(b"\n");
+
+    write_row(
+        b"Mem:       ",
+        info.total_frames * PAGE_KIB,
+        info.used_frames * PAGE_KIB,
+        info.free_frames * PAGE_KIB,
+    );
+    write_row(
+        b"Heap:      ",
+        info.heap_size / 1024,
+        info.heap_used / 1024,
+        info.heap_free / 1024,
+    );
+
+    let swap_free = info.swap_slots.saturating_sub(info.swap_used_slots);
+    write_row(
+        b"Swap:      ",
+        info.swap_slots * PAGE_KIB,
+        info.swap_used_slots * PAGE_KIB,
+        swap_free * PAGE_KIB,
+    );
+
+    ulib::stdout(b"\nLargest free heap block: ");
+    write_number(info.heap_largest_free);
+    ulib::stdout(b" bytes\nPage tables: ");
+    write_number(info.page_table_pages);
+    ulib::stdout(b" in use, ");
+    write_number(info.page_table_free_slots);
+    ulib::stdout(b" free slots\nStacks: ");
+    write_number(info.stack_remaining_pages);
+    ulib::stdout(b" pages left, ");
+    write_number(info.stack_free_ranges);
+    ulib::stdout(b" free ranges with ");
+    write_number(info.stack_free_range_pages);
+    ulib::stdout(b" pages\n");
+
+    ulib::exit();
+}
+
+fn write_row(label: &[u8], total: u64, used: u64, free: u64) {
+    ulib::stdout(label);
+
+    for value in [total, used, free] {
+        let mut digits = [0u8; 20];
+        let len = format_number(value, &mut digits);
+        write_column(&digits[20 - len..]);
+    }
+
+    ulib::stdout(b"\n");
+}
+
+/// Writes text right aligned in a column.
+fn write_column(text: &[u8]) {
+    for _ in text.len()..COLUMN_WIDTH {
+        ulib::stdout(b" ");
+    }
+
+    ulib::stdout(text);
+}
+
+fn write_number(number: u64) {
+    let mut digits = [0u8; 20];
+    let len = format_number(number, &mut digits);
+    ulib::stdout(&digits[20 - len..]);
+}
+
+/// Writes the decimal digits of a number to the end of the buffer,
+/// returning how many were written.
+fn format_number(mut number: u64, digits: &mut [u8; 20]) -> usize {
+    let mut len = 0;
+
+    loop {
+        len += 1;
+        digits[20 - len] = b'0' + (number % 10) as u8;
+        number /= 10;
+
+        if number == 0 {
+            return len;
+        }
+    }
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    ulib::exit();
+}
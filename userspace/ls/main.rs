@@ -3,24 +3,20 @@
 
 use core::{arch::global_asm, cell::UnsafeCell, panic::PanicInfo};
 
-use ulib::DirEntry;
+use ulib::{Args, DirEntry};
 
 const MAX_ENTRIES: usize = 32;
 
+// runs on the kernel-provided stack, with the System V
+// argument frame at the initial stack pointer
 global_asm!(
     r#"
-    .section .bss
-    .align 16
-
-stack_bottom:
-    .skip 4096
-
-stack_top:
     .section .text
     .global _start
 
 _start:
-    lea rsp, [rip + stack_top]
+    mov rdi, [rsp]
+    lea rsi, [rsp + 8]
     call rust_main
 
     mov rax, 1
@@ -31,7 +27,7 @@ _start:
 "#
 );
 
-// entries are too large for the 4 KiB stack, so keep them in .bss
+// entries are too large for the stack, so keep them in .bss
 struct EntryBuffer(UnsafeCell<[DirEntry; MAX_ENTRIES]>);
 
 unsafe impl Sync for EntryBuffer {}
@@ -39,21 +35,38 @@ unsafe impl Sync for EntryBuffer {}
 static ENTRY_BUFFER: EntryBuffer = EntryBuffer(UnsafeCell::new([DirEntry::empty(); MAX_ENTRIES]));
 
 #[no_mangle]
-extern "C" fn rust_main() -> ! {
-    let entries = unsafe { &mut *ENTRY_BUFFER.0.get() };
-    let count = ulib::read_dir(entries);
+extern "C" fn rust_main(argc: usize, argv: *const *const u8) -> ! {
+    let args = Args::new(argc, argv);
+    let path = args.get(1).unwrap_or(b".");
+
+    let fd = ulib::opendir(path);
+    if fd == 0 {
+        ulib::stdout(b"ls: could not open ");
+        ulib::stdout(path);
+        ulib::stdout(b"\n");
+        ulib::exit();
+    }
 
-    for entry in &entries[..count] {
-        if entry.is_directory() {
-            ulib::stdout(b"[ DIR ] ");
-        } else {
-            ulib::stdout(b"        ");
+    let entries = unsafe { &mut *ENTRY_BUFFER.0.get() };
+    loop {
+        let count = ulib::getdents(fd, entries);
+        if count == 0 {
+            break;
         }
 
-        ulib::stdout(entry.name_bytes());
-        ulib::stdout(b"\n");
+        for entry in &entries[..count] {
+            if entry.is_directory() {
+                ulib::stdout(b"[ DIR ] ");
+            } else {
+                ulib::stdout(b"        ");
+            }
+
+            ulib::stdout(entry.name_bytes());
+            ulib::stdout(b"\n");
+        }
     }
 
+    ulib::close(fd);
     ulib::exit();
 }
 
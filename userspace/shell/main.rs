@@ -1,6 +1,9 @@
 #![no_std]
 #![no_main]
 
+extern crate alloc;
+
+use alloc::vec;
 use core::arch::global_asm;
 use core::panic::PanicInfo;
 
@@ -31,7 +34,6 @@ _start:
 #[no_mangle]
 extern "C" fn rust_main() -> ! {
     let mut input_buffer = [0u8; 256];
-    let mut cwd = Cwd::new();
 
     ulib::stdout(br#"
  _______             __        __        __                   ______    ______
@@ -49,7 +51,7 @@ $$$$$$$/   $$$$$$/  $$$$$$$/  $$$$$$$/  $$/  $$$$$$$/        $$$$$$/   $$$$$$/
     ulib::stdout(b"\nWelcome to the Bubble OS Kernel Shell :D\n\n");
 
     loop {
-        cwd.print_prompt();
+        print_prompt();
 
         let input_len = read_command(&mut input_buffer);
         ulib::stdout(b"\n");
@@ -61,8 +63,31 @@ $$$$$$$/   $$$$$$/  $$$$$$$/  $$$$$$$/  $$/  $$$$$$$/        $$$$$$/   $$$$$$/
         let command = &input_buffer[..input_len];
         if command.starts_with(b"cd ") {
             let path = trim_ascii_spaces(&command[3..]);
-            if ulib::cd(path) {
-                cwd.update(path);
+            ulib::cd(path);
+
+            continue;
+        }
+
+        if command.starts_with(b"export ") {
+            let assignment = trim_ascii_spaces(&command[7..]);
+            let exported = match assignment.iter().position(|b| *b == b'=') {
+                Some(index) => ulib::set_env(&assignment[..index], &assignment[index + 1..]),
+                None => false,
+            };
+
+            if !exported {
+                ulib::stdout(b"Usage: export <key>=<value>\n");
+            }
+
+            continue;
+        }
+
+        if command == b"env" {
+            let mut env_buffer = vec![0u8; ulib::ENV_BLOCK_MAX_BYTES];
+            let env = ulib::Env::read(&mut env_buffer);
+            for entry in env.entries() {
+                ulib::stdout(entry);
+                ulib::stdout(b"\n");
             }
 
             continue;
@@ -125,6 +150,56 @@ $$$$$$$/   $$$$$$/  $$$$$$$/  $$$$$$$/  $$/  $$$$$$$/        $$$$$$/   $$$$$$/
             continue;
         }
 
+        if command.starts_with(b"mv ") {
+            let (source, destination) = split_command_line(trim_ascii_spaces(&command[3..]));
+            if source.is_empty() || destination.is_empty() {
+                ulib::stdout(b"Usage: mv <source> <destination>\n");
+            } else if move_entry(source, destination) {
+                ulib::stdout(b"Moved\n");
+            } else {
+                ulib::stdout(b"Could not move\n");
+            }
+
+            continue;
+        }
+
+        if command.starts_with(b"mount ") {
+            let (fs_type, rest) = split_command_line(trim_ascii_spaces(&command[6..]));
+            let (source, target) = split_command_line(rest);
+            if fs_type.is_empty() || source.is_empty() || target.is_empty() {
+                ulib::stdout(b"Usage: mount <type> <source> <target>\n");
+            } else if ulib::mount(fs_type, source, target) {
+                ulib::stdout(b"Mounted\n");
+            } else {
+                ulib::stdout(b"Could not mount\n");
+            }
+
+            continue;
+        }
+
+        if command.starts_with(b"umount ") {
+            let path = trim_ascii_spaces(&command[7..]);
+            if ulib::umount(path) {
+                ulib::stdout(b"Unmounted\n");
+            } else {
+                ulib::stdout(b"Could not unmount\n");
+            }
+
+            continue;
+        }
+
+        if command.starts_with(b"stat ") {
+            let path = trim_ascii_spaces(&command[5..]);
+            let mut stat = ulib::Stat::empty();
+            if ulib::stat(path, &mut stat) {
+                print_stat(&stat);
+            } else {
+                ulib::stdout(b"Could not stat path\n");
+            }
+
+            continue;
+        }
+
         if command == b"uptime" {
             let mut timespec = ulib::Timespec::zero();
             if ulib::clock_gettime(ulib::CLOCK_MONOTONIC, &mut timespec) {
@@ -171,92 +246,16 @@ $$$$$$$/   $$$$$$/  $$$$$$$/  $$$$$$$/  $$/  $$$$$$$/        $$$$$$/   $$$$$$/
     }
 }
 
-struct Cwd {
-    path: [u8; 256],
-    len: usize,
-}
+fn print_prompt() {
+    let mut cwd = [0u8; 256];
+    let cwd_len = ulib::getcwd(&mut cwd);
 
-impl Cwd {
-    const fn new() -> Self {
-        Self {
-            path: [0; 256],
-            len: 0,
-        }
+    ulib::stdout(b"~");
+    if cwd_len > 1 {
+        ulib::stdout(&cwd[..cwd_len]);
     }
 
-    fn print_prompt(&self) {
-        ulib::stdout(b"~");
-
-        if self.len > 0 {
-            ulib::stdout(b"/");
-            ulib::stdout(&self.path[..self.len]);
-        }
-
-        ulib::stdout(b" $ ");
-    }
-
-    fn update(&mut self, path: &[u8]) {
-        let path = trim_ascii_spaces(path);
-        if path == b"/" || path == b"~" {
-            self.len = 0;
-            return;
-        }
-
-        if path.starts_with(b"/") {
-            self.len = 0;
-        }
-
-        let mut remaining = path;
-        loop {
-            let (component, rest) = split_next_path_component(remaining);
-            self.apply_component(component);
-
-            let Some(rest) = rest else {
-                break;
-            };
-
-            remaining = rest;
-        }
-    }
-
-    fn apply_component(&mut self, component: &[u8]) {
-        if component.is_empty() || component == b"." {
-            return;
-        }
-
-        if component == b".." {
-            self.pop_component();
-            return;
-        }
-
-        self.push_component(component);
-    }
-
-    fn push_component(&mut self, component: &[u8]) {
-        let separator_len = if self.len == 0 { 0 } else { 1 };
-        let new_len = self.len + separator_len + component.len();
-        if new_len > self.path.len() {
-            return;
-        }
-
-        if separator_len == 1 {
-            self.path[self.len] = b'/';
-            self.len += 1;
-        }
-
-        self.path[self.len..new_len].copy_from_slice(component);
-        self.len = new_len;
-    }
-
-    fn pop_component(&mut self) {
-        while self.len > 0 && self.path[self.len - 1] != b'/' {
-            self.len -= 1;
-        }
-
-        if self.len > 0 {
-            self.len -= 1;
-        }
-    }
+    ulib::stdout(b" $ ");
 }
 
 #[panic_handler]
@@ -335,6 +334,58 @@ fn print_number(mut number: usize) {
     }
 }
 
+/// Renames `source` to `destination`, moving it inside `destination`
+/// under its current name when that is an existing directory.
+fn move_entry(source: &[u8], destination: &[u8]) -> bool {
+    let mut stat = ulib::Stat::empty();
+    if !ulib::stat(destination, &mut stat) || !stat.is_directory() {
+        return ulib::rename(source, destination);
+    }
+
+    let source = trim_trailing_slashes(source);
+    let name = match source.iter().rposition(|byte| *byte == b'/') {
+        Some(index) => &source[index + 1..],
+        None => source,
+    };
+
+    let destination = trim_trailing_slashes(destination);
+    let mut path_buffer = [0u8; 512];
+    let path_len = destination.len() + 1 + name.len();
+    if name.is_empty() || path_len > path_buffer.len() {
+        return false;
+    }
+
+    path_buffer[..destination.len()].copy_from_slice(destination);
+    path_buffer[destination.len()] = b'/';
+    path_buffer[destination.len() + 1..path_len].copy_from_slice(name);
+
+    ulib::rename(source, &path_buffer[..path_len])
+}
+
+fn trim_trailing_slashes(mut bytes: &[u8]) -> &[u8] {
+    while bytes.len() > 1 && bytes.ends_with(b"/") {
+        bytes = &bytes[..bytes.len() - 1];
+    }
+
+    bytes
+}
+
+fn print_stat(stat: &ulib::Stat) {
+    if stat.is_directory() {
+        ulib::stdout(b"Type: directory\n");
+    } else {
+        ulib::stdout(b"Type: file\n");
+    }
+
+    ulib::stdout(b"Size: ");
+    print_number(stat.size as usize);
+    ulib::stdout(b"\nClusters: ");
+    print_number(stat.cluster_count as usize);
+    ulib::stdout(b"\nModified: ");
+    print_number(stat.modified as usize);
+    ulib::stdout(b"\n");
+}
+
 fn parse_number(bytes: &[u8]) -> Option<usize> {
     if bytes.is_empty() {
         return None;
@@ -352,24 +403,17 @@ fn parse_number(bytes: &[u8]) -> Option<usize> {
     Some(number)
 }
 
-fn split_next_path_component(bytes: &[u8]) -> (&[u8], Option<&[u8]>) {
-    match bytes.iter().position(|b| *b == b'/') {
-        Some(index) => (&bytes[..index], Some(&bytes[index + 1..])),
-        None => (bytes, None),
-    }
-}
-
 fn launch(command: &[u8]) -> bool {
     let (program, args) = split_command_line(command);
 
     let pid = if program.contains(&b'/') {
         ulib::execute(program, args)
     } else {
-        let bin_pid = execute_from_bin(program, args);
-        if bin_pid == 0 {
+        let search_pid = execute_from_search_path(program, args);
+        if search_pid == 0 {
             ulib::execute(program, args)
         } else {
-            bin_pid
+            search_pid
         }
     };
 
@@ -392,17 +436,34 @@ fn split_command_line(command: &[u8]) -> (&[u8], &[u8]) {
     }
 }
 
-fn execute_from_bin(program: &[u8], args: &[u8]) -> usize {
-    const BIN_PREFIX: &[u8] = b"/bin/";
-
-    let mut path_buffer = [0u8; 261];
-    let path_len = BIN_PREFIX.len() + program.len();
-    if path_len > path_buffer.len() {
+fn execute_from_search_path(program: &[u8], args: &[u8]) -> usize {
+    let mut env_buffer = vec![0u8; ulib::ENV_BLOCK_MAX_BYTES];
+    let env = ulib::Env::read(&mut env_buffer);
+    let Some(search_path) = env.get(b"PATH") else {
         return 0;
-    }
+    };
+
+    let mut path_buffer = [0u8; 512];
+    for directory in search_path.split(|b| *b == b':') {
+        let directory = trim_trailing_slashes(directory);
+        if directory.is_empty() {
+            continue;
+        }
 
-    path_buffer[..BIN_PREFIX.len()].copy_from_slice(BIN_PREFIX);
-    path_buffer[BIN_PREFIX.len()..path_len].copy_from_slice(program);
+        let path_len = directory.len() + 1 + program.len();
+        if path_len > path_buffer.len() {
+            continue;
+        }
+
+        path_buffer[..directory.len()].copy_from_slice(directory);
+        path_buffer[directory.len()] = b'/';
+        path_buffer[directory.len() + 1..path_len].copy_from_slice(program);
+
+        let pid = ulib::execute(&path_buffer[..path_len], args);
+        if pid != 0 {
+            return pid;
+        }
+    }
 
-    ulib::execute(&path_buffer[..path_len], args)
+    0
 }
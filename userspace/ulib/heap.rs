@@ -0,0 +1,176 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cell::UnsafeCell,
+    mem::size_of,
+    ptr,
+};
+
+/// Every block starts with a header and is aligned to this.
+const BLOCK_ALIGN: usize = 16;
+
+/// Minimum number of bytes the program break is moved by at once.
+const HEAP_GROWTH: usize = 64 * 1024;
+
+/// Placed right before every allocation, so `dealloc` can recover the
+/// block it belongs to.
+#[repr(C)]
+struct BlockHeader {
+    start: usize,
+    size: usize,
+}
+
+/// A freed block, stored in the block memory itself.
+#[repr(C)]
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+struct HeapState {
+    /// Free blocks sorted by address, neighbours are merged on free.
+    free_list: *mut FreeBlock,
+}
+
+/// First-fit allocator on top of the program break.
+///
+/// Userspace programs are single threaded, so the state is not locked.
+pub struct BrkAllocator {
+    state: UnsafeCell<HeapState>,
+}
+
+unsafe impl Sync for BrkAllocator {}
+
+impl BrkAllocator {
+    pub const fn new() -> Self {
+        Self {
+            state: UnsafeCell::new(HeapState {
+                free_list: ptr::null_mut(),
+            }),
+        }
+    }
+
+    /// Takes a block that fits `size` bytes at `align` out of the
+    /// free list, returning its start and full size.
+    unsafe fn take_free(
+        state: &mut HeapState,
+        size: usize,
+        align: usize,
+    ) -> Option<(usize, usize)> {
+        let mut previous: *mut FreeBlock = ptr::null_mut();
+        let mut current = state.free_list;
+
+        while !current.is_null() {
+            let start = current as usize;
+            let block_size = (*current).size;
+            let next = (*current).next;
+
+            let user = align_up(start + size_of::<BlockHeader>(), align);
+            if user + size <= start + block_size {
+                if previous.is_null() {
+                    state.free_list = next;
+                } else {
+                    (*previous).next = next;
+                }
+
+                return Some((start, block_size));
+            }
+
+            previous = current;
+            current = next;
+        }
+
+        None
+    }
+
+    /// Returns a block to the free list, merging it with its neighbours.
+    unsafe fn insert_free(state: &mut HeapState, start: usize, size: usize) {
+        let mut previous: *mut FreeBlock = ptr::null_mut();
+        let mut current = state.free_list;
+
+        while !current.is_null() && (current as usize) < start {
+            previous = current;
+            current = (*current).next;
+        }
+
+        let block = start as *mut FreeBlock;
+        (*block).size = size;
+        (*block).next = current;
+
+        if !current.is_null() && start + size == current as usize {
+            (*block).size += (*current).size;
+            (*block).next = (*current).next;
+        }
+
+        if previous.is_null() {
+            state.free_list = block;
+        } else if previous as usize + (*previous).size == start {
+            (*previous).size += (*block).size;
+            (*previous).next = (*block).next;
+        } else {
+            (*previous).next = block;
+        }
+    }
+
+    /// Moves the program break to make room for at least `size` bytes.
+    unsafe fn grow(state: &mut HeapState, size: usize) -> Option<()> {
+        let size = align_up(size.max(HEAP_GROWTH), BLOCK_ALIGN);
+        let start = crate::sbrk(size)?;
+        Self::insert_free(state, start as usize, size);
+
+        Some(())
+    }
+}
+
+unsafe impl GlobalAlloc for BrkAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let state = &mut *self.state.get();
+        let align = layout.align().max(BLOCK_ALIGN);
+        let size = align_up(layout.size().max(size_of::<FreeBlock>()), BLOCK_ALIGN);
+
+        let (start, block_size) = match Self::take_free(state, size, align) {
+            Some(block) => block,
+            None => {
+                // worst case padding for the header and the alignment
+                let needed = size + size_of::<BlockHeader>() + align;
+                if Self::grow(state, needed).is_none() {
+                    return ptr::null_mut();
+                }
+
+                match Self::take_free(state, size, align) {
+                    Some(block) => block,
+                    None => return ptr::null_mut(),
+                }
+            }
+        };
+
+        let user = align_up(start + size_of::<BlockHeader>(), align);
+        let used = user + size - start;
+
+        // split off the tail when it can hold another block
+        let block_size = if block_size - used >= size_of::<BlockHeader>() + BLOCK_ALIGN * 2 {
+            Self::insert_free(state, start + used, block_size - used);
+            used
+        } else {
+            block_size
+        };
+
+        let header = (user - size_of::<BlockHeader>()) as *mut BlockHeader;
+        (*header).start = start;
+        (*header).size = block_size;
+
+        user as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        let state = &mut *self.state.get();
+        let header = (ptr as usize - size_of::<BlockHeader>()) as *const BlockHeader;
+        let start = (*header).start;
+        let size = (*header).size;
+
+        Self::insert_free(state, start, size);
+    }
+}
+
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
@@ -1,7 +1,16 @@
 #![no_std]
 
+extern crate alloc;
+
+mod heap;
+
 use core::arch::asm;
 
+pub use heap::BrkAllocator;
+
+#[global_allocator]
+static ALLOCATOR: BrkAllocator = BrkAllocator::new();
+
 pub const STDIN: usize = 0;
 pub const STDOUT: usize = 1;
 pub const STDERR: usize = 2;
@@ -23,6 +32,34 @@ const SYS_UNLINK: usize = 14;
 const SYS_RMDIR: usize = 15;
 const SYS_CLOCK_GETTIME: usize = 16;
 const SYS_NANOSLEEP: usize = 17;
+const SYS_STAT: usize = 18;
+const SYS_FSTAT: usize = 19;
+const SYS_RENAME: usize = 20;
+const SYS_OPENDIR: usize = 21;
+const SYS_GETDENTS: usize = 22;
+const SYS_GETCWD: usize = 23;
+const SYS_SET_ENV: usize = 24;
+const SYS_READ_ENV: usize = 25;
+const SYS_BRK: usize = 26;
+const SYS_MMAP: usize = 27;
+const SYS_MUNMAP: usize = 28;
+const SYS_MSYNC: usize = 29;
+const SYS_MEMINFO: usize = 30;
+const SYS_SHM_OPEN: usize = 31;
+const SYS_SHM_UNLINK: usize = 32;
+const SYS_MOUNT: usize = 33;
+const SYS_UMOUNT: usize = 34;
+
+pub const PROT_READ: usize = 0x1;
+pub const PROT_WRITE: usize = 0x2;
+
+pub const MAP_SHARED: usize = 0x01;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_FIXED: usize = 0x10;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+pub const SHM_CREATE: usize = 0x1;
+pub const SHM_EXCLUSIVE: usize = 0x2;
 
 pub const CLOCK_REALTIME: usize = 0;
 pub const CLOCK_MONOTONIC: usize = 1;
@@ -30,6 +67,10 @@ pub const CLOCK_MONOTONIC: usize = 1;
 pub const NANOSECONDS_PER_SECOND: i64 = 1_000_000_000;
 pub const NANOSECONDS_PER_MILLISECOND: i64 = 1_000_000;
 
+/// Size of the largest environment [`read_env`] can write: the kernel
+/// allows up to 64 entries of 16 KiB in total, each followed by a NUL.
+pub const ENV_BLOCK_MAX_BYTES: usize = 16 * 1024 + 64;
+
 /// A point in time, laid out like the POSIX `timespec`.
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -94,6 +135,90 @@ impl DirEntry {
     }
 }
 
+/// [`Stat::file_type`] of a regular file.
+pub const STAT_TYPE_REGULAR: u8 = 1;
+
+/// [`Stat::file_type`] of a directory.
+pub const STAT_TYPE_DIRECTORY: u8 = 2;
+
+/// File metadata; must match the kernel's `SyscallStat` layout.
+///
+/// Timestamps are seconds since the Unix epoch, or 0 when unknown.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Stat {
+    pub file_type: u8,
+    pub attributes: u8,
+    _reserved: [u8; 6],
+    pub size: u64,
+    pub cluster_count: u64,
+    pub created: u64,
+    pub modified: u64,
+    pub accessed: u64,
+}
+
+impl Stat {
+    pub const fn empty() -> Self {
+        Self {
+            file_type: 0,
+            attributes: 0,
+            _reserved: [0; 6],
+            size: 0,
+            cluster_count: 0,
+            created: 0,
+            modified: 0,
+            accessed: 0,
+        }
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.file_type == STAT_TYPE_DIRECTORY
+    }
+}
+
+/// Kernel memory counters; must match the kernel's `SyscallMemInfo` layout.
+///
+/// Frames and pages are 4 KiB each, the heap counters are in bytes.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MemInfo {
+    pub total_frames: u64,
+    pub free_frames: u64,
+    pub used_frames: u64,
+    pub heap_size: u64,
+    pub heap_used: u64,
+    pub heap_free: u64,
+    pub heap_largest_free: u64,
+    pub stack_remaining_pages: u64,
+    pub stack_free_ranges: u64,
+    pub stack_free_range_pages: u64,
+    pub page_table_pages: u64,
+    pub page_table_free_slots: u64,
+    pub swap_slots: u64,
+    pub swap_used_slots: u64,
+}
+
+impl MemInfo {
+    pub const fn empty() -> Self {
+        Self {
+            total_frames: 0,
+            free_frames: 0,
+            used_frames: 0,
+            heap_size: 0,
+            heap_used: 0,
+            heap_free: 0,
+            heap_largest_free: 0,
+            stack_remaining_pages: 0,
+            stack_free_ranges: 0,
+            stack_free_range_pages: 0,
+            page_table_pages: 0,
+            page_table_free_slots: 0,
+            swap_slots: 0,
+            swap_used_slots: 0,
+        }
+    }
+}
+
 /// The process arguments, read from the System V style entry stack frame.
 ///
 /// Construct one in `rust_main` from the `argc`/`argv` values that `_start`
@@ -158,6 +283,44 @@ impl Iterator for Args {
     }
 }
 
+/// The environment of the current process, as read by [`Env::read`].
+pub struct Env<'a> {
+    block: &'a [u8],
+}
+
+impl<'a> Env<'a> {
+    /// Reads the current environment into a caller-provided buffer.
+    ///
+    /// ## Arguments
+    ///
+    /// - `buffer` the storage for the `KEY=VALUE` entries, any environment
+    ///   fits into [`ENV_BLOCK_MAX_BYTES`]
+    ///
+    /// ## Returns
+    /// The environment, empty when it does not fit into `buffer`.
+    pub fn read(buffer: &'a mut [u8]) -> Self {
+        let len = read_env(buffer);
+        Self {
+            block: &buffer[..len],
+        }
+    }
+
+    /// The value of a variable, without the `KEY=` prefix.
+    pub fn get(&self, key: &[u8]) -> Option<&'a [u8]> {
+        self.entries().find_map(|entry| {
+            let value = entry.strip_prefix(key)?;
+            value.strip_prefix(b"=")
+        })
+    }
+
+    /// Every `KEY=VALUE` entry, in the order they were set.
+    pub fn entries(&self) -> impl Iterator<Item = &'a [u8]> {
+        self.block
+            .split(|b| *b == 0)
+            .filter(|entry| !entry.is_empty())
+    }
+}
+
 #[inline(always)]
 unsafe fn syscall0(number: usize) -> usize {
     let ret: usize;
@@ -223,6 +386,31 @@ unsafe fn syscall4(number: usize, arg0: usize, arg1: usize, arg2: usize, arg3: u
     ret
 }
 
+#[inline(always)]
+unsafe fn syscall6(
+    number: usize,
+    arg0: usize,
+    arg1: usize,
+    arg2: usize,
+    arg3: usize,
+    arg4: usize,
+    arg5: usize,
+) -> usize {
+    let ret: usize;
+    asm!(
+        "int 0x80",
+        inlateout("rax") number => ret,
+        in("rdi") arg0,
+        in("rsi") arg1,
+        in("rdx") arg2,
+        in("r10") arg3,
+        in("r8") arg4,
+        in("r9") arg5,
+    );
+
+    ret
+}
+
 pub fn write(fd: usize, bytes: &[u8]) -> usize {
     unsafe { syscall3(SYS_WRITE, fd, bytes.as_ptr() as usize, bytes.len()) }
 }
@@ -298,6 +486,185 @@ pub fn read_dir(entries: &mut [DirEntry]) -> usize {
     unsafe { syscall2(SYS_READ_DIR, entries.as_mut_ptr() as usize, entries.len()) }
 }
 
+/// Opens a directory for iteration with [`getdents`].
+///
+/// ## Returns
+/// The directory descriptor, or 0 if the directory does not exist.
+pub fn opendir(path: &[u8]) -> usize {
+    unsafe { syscall2(SYS_OPENDIR, path.as_ptr() as usize, path.len()) }
+}
+
+/// Reads the next entries of an open directory descriptor.
+///
+/// ## Returns
+/// The number of entries written to `entries`, 0 once the directory is exhausted.
+pub fn getdents(fd: usize, entries: &mut [DirEntry]) -> usize {
+    unsafe {
+        syscall3(
+            SYS_GETDENTS,
+            fd,
+            entries.as_mut_ptr() as usize,
+            entries.len(),
+        )
+    }
+}
+
+/// Writes the absolute path of the current working directory.
+///
+/// ## Returns
+/// The length of the path, or 0 if it does not fit in `buffer`.
+pub fn getcwd(buffer: &mut [u8]) -> usize {
+    unsafe { syscall2(SYS_GETCWD, buffer.as_mut_ptr() as usize, buffer.len()) }
+}
+
+/// Sets an environment variable of the current process, inherited by
+/// every process it launches afterwards.
+pub fn set_env(key: &[u8], value: &[u8]) -> bool {
+    unsafe {
+        syscall4(
+            SYS_SET_ENV,
+            key.as_ptr() as usize,
+            key.len(),
+            value.as_ptr() as usize,
+            value.len(),
+        ) != 0
+    }
+}
+
+/// Writes the environment as NUL-terminated `KEY=VALUE` entries.
+///
+/// ## Returns
+/// The number of bytes written, or 0 if they do not fit in `buffer`.
+pub fn read_env(buffer: &mut [u8]) -> usize {
+    unsafe { syscall2(SYS_READ_ENV, buffer.as_mut_ptr() as usize, buffer.len()) }
+}
+
+/// Moves the program break.
+///
+/// ## Arguments
+///
+/// - `addr` the new program break, 0 to query the current one
+///
+/// ## Returns
+/// The resulting program break, or 0 if it could not be moved.
+pub fn brk(addr: usize) -> usize {
+    unsafe { syscall1(SYS_BRK, addr) }
+}
+
+/// Grows the program break by `increment` bytes.
+///
+/// ## Returns
+/// The start of the new memory, the previous program break.
+pub fn sbrk(increment: usize) -> Option<*mut u8> {
+    let current = brk(0);
+    if current == 0 {
+        return None;
+    }
+
+    if increment == 0 {
+        return Some(current as *mut u8);
+    }
+
+    let new_brk = current.checked_add(increment)?;
+    if brk(new_brk) != new_brk {
+        return None;
+    }
+
+    Some(current as *mut u8)
+}
+
+/// Maps zeroed anonymous memory, `flags` must contain `MAP_ANONYMOUS`.
+///
+/// ## Returns
+/// The start of the mapping, or null on failure.
+pub fn mmap(size: usize, protection: usize, flags: usize) -> *mut u8 {
+    unsafe { syscall4(SYS_MMAP, 0, size, protection, flags) as *mut u8 }
+}
+
+/// Maps part of an open file, its pages are read on first access.
+///
+/// ## Arguments
+///
+/// - `fd` the file descriptor of the file
+/// - `offset` the page-aligned file offset to map from
+/// - `size` the mapping size in bytes
+/// - `protection` `PROT_READ` and optionally `PROT_WRITE`
+/// - `flags` either `MAP_SHARED`, to write changes back to the file on
+/// `msync` and `munmap`, or `MAP_PRIVATE`
+///
+/// ## Returns
+/// The start of the mapping, or null on failure.
+pub fn mmap_file(
+    fd: usize,
+    offset: usize,
+    size: usize,
+    protection: usize,
+    flags: usize,
+) -> *mut u8 {
+    unsafe { syscall6(SYS_MMAP, 0, size, protection, flags, fd, offset) as *mut u8 }
+}
+
+/// Maps part of a file or shared memory object at a fixed page-aligned
+/// address, failing when the range is already in use.
+///
+/// ## Arguments
+///
+/// - `addr` the address to place the mapping at
+/// - `fd` the file descriptor of the file or shared memory object
+/// - `offset` the page-aligned offset to map from
+/// - `size` the mapping size in bytes
+/// - `protection` `PROT_READ` and optionally `PROT_WRITE`
+/// - `flags` `MAP_SHARED` or `MAP_PRIVATE`, `MAP_FIXED` is added
+///
+/// ## Returns
+/// The start of the mapping, or null on failure.
+pub fn mmap_fixed(
+    addr: *mut u8,
+    fd: usize,
+    offset: usize,
+    size: usize,
+    protection: usize,
+    flags: usize,
+) -> *mut u8 {
+    let flags = flags | MAP_FIXED;
+    unsafe { syscall6(SYS_MMAP, addr as usize, size, protection, flags, fd, offset) as *mut u8 }
+}
+
+/// Opens a named shared memory object, it's mapped with `mmap_file` and
+/// `MAP_SHARED`.
+///
+/// ## Arguments
+///
+/// - `name` the name of the object
+/// - `size` the size of a new object, an existing one must be at least
+/// this large
+/// - `flags` `SHM_CREATE` to create a missing object, together with
+/// `SHM_EXCLUSIVE` to fail when it exists
+///
+/// ## Returns
+/// The file descriptor of the object, or 0 on failure.
+pub fn shm_open(name: &[u8], size: usize, flags: usize) -> usize {
+    unsafe { syscall4(SYS_SHM_OPEN, name.as_ptr() as usize, name.len(), size, flags) }
+}
+
+/// Removes the name of a shared memory object, its memory stays around
+/// until the last mapping of it is gone.
+pub fn shm_unlink(name: &[u8]) -> bool {
+    unsafe { syscall2(SYS_SHM_UNLINK, name.as_ptr() as usize, name.len()) != 0 }
+}
+
+/// Unmaps a page-aligned range of a mapping, the changes of a shared
+/// file mapping are written back to the file.
+pub fn munmap(addr: *mut u8, size: usize) -> bool {
+    unsafe { syscall2(SYS_MUNMAP, addr as usize, size) != 0 }
+}
+
+/// Writes the changes of the shared file mappings in a page-aligned range
+/// back to their files.
+pub fn msync(addr: *mut u8, size: usize) -> bool {
+    unsafe { syscall2(SYS_MSYNC, addr as usize, size) != 0 }
+}
+
 pub fn cd(path: &[u8]) -> bool {
     unsafe { syscall2(SYS_CD, path.as_ptr() as usize, path.len()) != 0 }
 }
@@ -322,6 +689,72 @@ pub fn rmdir(path: &[u8]) -> bool {
     unsafe { syscall2(SYS_RMDIR, path.as_ptr() as usize, path.len()) != 0 }
 }
 
+/// Renames or moves a file or directory.
+///
+/// ## Arguments
+///
+/// - `old_path` the current path of the entry
+/// - `new_path` the full new path of the entry, including its name
+pub fn rename(old_path: &[u8], new_path: &[u8]) -> bool {
+    unsafe {
+        syscall4(
+            SYS_RENAME,
+            old_path.as_ptr() as usize,
+            old_path.len(),
+            new_path.as_ptr() as usize,
+            new_path.len(),
+        ) != 0
+    }
+}
+
+/// Mounts a filesystem, a directory already at the target is hidden until
+/// it is unmounted.
+///
+/// ## Arguments
+///
+/// - `fs_type` the filesystem type, `fat` or `tmpfs`
+/// - `source` the device holding the filesystem, like `disk1`, any label
+///   for a tmpfs
+/// - `target` the path to mount at
+pub fn mount(fs_type: &[u8], source: &[u8], target: &[u8]) -> bool {
+    unsafe {
+        syscall6(
+            SYS_MOUNT,
+            source.as_ptr() as usize,
+            source.len(),
+            target.as_ptr() as usize,
+            target.len(),
+            fs_type.as_ptr() as usize,
+            fs_type.len(),
+        ) != 0
+    }
+}
+
+/// Unmounts the filesystem mounted at a path, fails while any of its files
+/// or directories is in use.
+pub fn umount(target: &[u8]) -> bool {
+    unsafe { syscall2(SYS_UMOUNT, target.as_ptr() as usize, target.len()) != 0 }
+}
+
+pub fn stat(path: &[u8], stat: &mut Stat) -> bool {
+    unsafe {
+        syscall3(
+            SYS_STAT,
+            path.as_ptr() as usize,
+            path.len(),
+            stat as *mut Stat as usize,
+        ) != 0
+    }
+}
+
+pub fn fstat(fd: usize, stat: &mut Stat) -> bool {
+    unsafe { syscall2(SYS_FSTAT, fd, stat as *mut Stat as usize) != 0 }
+}
+
+pub fn meminfo(meminfo: &mut MemInfo) -> bool {
+    unsafe { syscall1(SYS_MEMINFO, meminfo as *mut MemInfo as usize) != 0 }
+}
+
 pub fn close(fd: usize) -> bool {
     unsafe { syscall1(SYS_CLOSE, fd) != 0 }
 }